@@ -0,0 +1,106 @@
+//! Measure `scrubcsv` throughput with and without the zero-copy fast path.
+//!
+//! Run with `cargo bench -p scrubcsv`. Set `SCRUBCSV_BENCH_ROWS` to change the
+//! size of the generated input.
+//!
+//! We generate two inputs. The fast path can copy the raw bytes of records
+//! without any quotes straight to our output, so one input has no quotes at
+//! all, and the other quotes a field in every row, which forces the fast path
+//! to re-encode each record.
+
+use std::{
+    env, fs,
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+/// How many times should we run each benchmark?
+const RUNS: usize = 5;
+
+/// Generate a test file with `rows` rows, a few of which are bad. If `quoted`
+/// is true, every good row contains a quoted field.
+fn generate_input(path: &Path, rows: usize, quoted: bool) {
+    let notes = if quoted {
+        "\"quoted, with a comma\""
+    } else {
+        "plain notes without quotes"
+    };
+    let file = fs::File::create(path).expect("cannot create benchmark input");
+    let mut wtr = BufWriter::new(file);
+    writeln!(wtr, "id,name,amount,notes,code").unwrap();
+    for i in 0..rows {
+        if i % 1000 == 999 {
+            writeln!(wtr, "{},bad row", i).unwrap();
+        } else {
+            writeln!(
+                wtr,
+                "{},Name {},{}.{:02},{},ABC{}",
+                i,
+                i % 97,
+                i * 3,
+                i % 100,
+                notes,
+                i % 10,
+            )
+            .unwrap();
+        }
+    }
+    wtr.flush().unwrap();
+}
+
+/// Run `scrubcsv` on `input` with `args`, returning the best throughput in
+/// MB/s.
+fn bench(input: &Path, args: &[&str]) -> f64 {
+    let size = fs::metadata(input).unwrap().len() as f64;
+    let mut best = 0.0f64;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_scrubcsv"))
+            .arg("--quiet")
+            .args(args)
+            .arg(input)
+            .stdout(Stdio::null())
+            .status()
+            .expect("cannot run scrubcsv");
+        let elapsed = start.elapsed().as_secs_f64();
+        assert!(status.success(), "scrubcsv failed");
+        best = best.max(size / elapsed / 1_000_000.0);
+    }
+    best
+}
+
+fn main() {
+    let rows = env::var("SCRUBCSV_BENCH_ROWS")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(2_000_000);
+    let cases: &[(&str, &[&str])] = &[
+        ("fast path", &[]),
+        ("no fast path", &["--no-fast-path"]),
+        (
+            "fast path, --select",
+            &["--select", "id", "--select", "amount"],
+        ),
+        (
+            "no fast path, --select",
+            &["--no-fast-path", "--select", "id", "--select", "amount"],
+        ),
+        ("cleaning values", &["--trim-whitespace"]),
+    ];
+    for (label, quoted) in [("quote-free input", false), ("quoted input", true)] {
+        let input = env::temp_dir().join(format!(
+            "scrubcsv-bench-{}-{}.csv",
+            if quoted { "quoted" } else { "plain" },
+            rows,
+        ));
+        if !input.exists() {
+            generate_input(&input, rows, quoted);
+        }
+        println!("{}:", label);
+        for (name, args) in cases {
+            println!("  {:<25} {:>8.1} MB/s", name, bench(&input, args));
+        }
+    }
+}
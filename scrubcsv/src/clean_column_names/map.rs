@@ -0,0 +1,127 @@
+//! Mappings from original column names to clean names, saved between runs.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::errors::*;
+use crate::format_err;
+
+/// A mapping from original column names to clean names. This allows us to
+/// keep using the same clean names when a vendor reorders or adds columns.
+///
+/// Maps are stored as either a JSON object, or a CSV file with `original`
+/// and `clean` columns.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnNameMap {
+    /// Our mappings, in the order they were added.
+    names: Vec<(String, String)>,
+    /// Have we added any mappings since we were loaded?
+    modified: bool,
+}
+
+impl ColumnNameMap {
+    /// Create an empty `ColumnNameMap`.
+    pub fn new() -> ColumnNameMap {
+        ColumnNameMap::default()
+    }
+
+    /// Load a map from a JSON file (if `path` ends in `.json`) or a CSV file.
+    pub fn from_path(path: &Path) -> Result<ColumnNameMap> {
+        let data = fs::read(path)
+            .with_context(|_| format!("cannot read {}", path.display()))?;
+        let mut map = ColumnNameMap::new();
+        if is_json(path) {
+            let names = serde_json::from_slice::<BTreeMap<String, String>>(&data)
+                .with_context(|_| format!("cannot parse {}", path.display()))?;
+            for (original, clean) in names {
+                map.insert(original, clean);
+            }
+        } else {
+            let mut rdr = csv::Reader::from_reader(&data[..]);
+            for record in rdr.records() {
+                let record = record
+                    .with_context(|_| format!("cannot parse {}", path.display()))?;
+                if record.len() != 2 {
+                    return Err(format_err!(
+                        "expected 2 columns in {}, found {}",
+                        path.display(),
+                        record.len()
+                    ));
+                }
+                map.insert(record[0].to_owned(), record[1].to_owned());
+            }
+        }
+        map.modified = false;
+        Ok(map)
+    }
+
+    /// Save this map to `path`, using the same format as `from_path`.
+    pub fn to_path(&self, path: &Path) -> Result<()> {
+        let data = if is_json(path) {
+            let names = self.names.iter().cloned().collect::<BTreeMap<_, _>>();
+            serde_json::to_vec_pretty(&names).context("cannot serialize names")?
+        } else {
+            let mut wtr = csv::Writer::from_writer(vec![]);
+            wtr.write_record(["original", "clean"])
+                .context("cannot serialize names")?;
+            for (original, clean) in &self.names {
+                wtr.write_record([original, clean])
+                    .context("cannot serialize names")?;
+            }
+            wtr.into_inner().map_err(|err| {
+                format_err!("cannot serialize names: {}", err.error())
+            })?
+        };
+        fs::write(path, data)
+            .with_context(|_| format!("cannot write {}", path.display()))
+    }
+
+    /// Look up the clean name for `original`.
+    pub fn get(&self, original: &str) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(o, _)| o == original)
+            .map(|(_, clean)| &clean[..])
+    }
+
+    /// Map `original` to `clean`, replacing any existing mapping.
+    pub fn insert(&mut self, original: String, clean: String) {
+        self.modified = true;
+        match self.names.iter_mut().find(|(o, _)| *o == original) {
+            Some(entry) => entry.1 = clean,
+            None => self.names.push((original, clean)),
+        }
+    }
+
+    /// Have we added any mappings since this map was loaded?
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+}
+
+/// Should we store the map at `path` as JSON?
+fn is_json(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "json").unwrap_or(false)
+}
+
+#[test]
+fn column_name_map_round_trips() {
+    use std::env;
+
+    let mut map = ColumnNameMap::new();
+    map.insert("Order ID".to_owned(), "order_id".to_owned());
+    map.insert("Name, First".to_owned(), "first_name".to_owned());
+    for ext in &["json", "csv"] {
+        let path = env::temp_dir().join(format!(
+            "scrubcsv_column_name_map_{}.{}",
+            std::process::id(),
+            ext
+        ));
+        map.to_path(&path).unwrap();
+        let loaded = ColumnNameMap::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get("Order ID"), Some("order_id"));
+        assert_eq!(loaded.get("Name, First"), Some("first_name"));
+        assert_eq!(loaded.get("order_id"), None);
+        assert!(!loaded.is_modified());
+    }
+}
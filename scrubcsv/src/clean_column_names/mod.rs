@@ -1,11 +1,21 @@
-use std::str::FromStr;
+use std::{collections::HashSet, str::FromStr};
 
 use crate::{format_err, Error, Result};
 
+pub use self::map::ColumnNameMap;
+use self::postgres::PostgresCleaner;
+use self::preserve::PreserveCleaner;
+use self::snake_case::SnakeCaseCleaner;
 use self::stable::StableCleaner;
+use self::transliterate::Transliterator;
 use self::unique::Uniquifier;
 
+mod map;
+mod postgres;
+mod preserve;
+mod snake_case;
 mod stable;
+mod transliterate;
 mod unique;
 
 #[derive(Debug, Clone, Copy)]
@@ -18,14 +28,34 @@ pub enum ColumnNameCleanerType {
     /// unique lowercase C identifier in an easily predictable fashion.
     /// This may fail if two conflicting column names are present.
     Stable,
+    /// Like `Unique`, but split words in names like `userID` and
+    /// `HTTPServer`, and collapse runs of punctuation into a single
+    /// underscore.
+    SnakeCase,
+    /// Like `SnakeCase`, but limit names to 63 bytes and avoid PostgreSQL
+    /// reserved words, so names can be used without quoting.
+    Postgres,
+    /// Keep names as they are, but add suffixes to make them unique.
+    Preserve,
 }
 
 impl ColumnNameCleanerType {
-    /// Construct an appropriate `ColumnNameCleaner` instance.
-    pub fn build_cleaner(self) -> Box<dyn ColumnNameCleaner> {
-        match self {
+    /// Construct an appropriate `ColumnNameCleaner` instance. If
+    /// `transliterate` is true, convert names to ASCII (so that "Straße"
+    /// becomes "Strasse") and collapse runs of punctuation before cleaning
+    /// them.
+    pub fn build_cleaner(self, transliterate: bool) -> Box<dyn ColumnNameCleaner> {
+        let cleaner: Box<dyn ColumnNameCleaner> = match self {
             ColumnNameCleanerType::Unique => Box::new(Uniquifier::default()),
             ColumnNameCleanerType::Stable => Box::new(StableCleaner::default()),
+            ColumnNameCleanerType::SnakeCase => Box::new(SnakeCaseCleaner::default()),
+            ColumnNameCleanerType::Postgres => Box::new(PostgresCleaner::default()),
+            ColumnNameCleanerType::Preserve => Box::new(PreserveCleaner::default()),
+        };
+        if transliterate {
+            Box::new(Transliterator::new(cleaner))
+        } else {
+            cleaner
         }
     }
 }
@@ -37,6 +67,9 @@ impl FromStr for ColumnNameCleanerType {
         match s {
             "unique" => Ok(ColumnNameCleanerType::Unique),
             "stable" => Ok(ColumnNameCleanerType::Stable),
+            "snake_case" => Ok(ColumnNameCleanerType::SnakeCase),
+            "postgres" => Ok(ColumnNameCleanerType::Postgres),
+            "preserve" => Ok(ColumnNameCleanerType::Preserve),
             _ => Err(format_err!(
                 "invalid --clean-column-names argument: {:?}",
                 s
@@ -49,4 +82,43 @@ impl FromStr for ColumnNameCleanerType {
 pub trait ColumnNameCleaner {
     /// Given a `name`, return an idenfitier to use as a column name.
     fn unique_id_for(&mut self, name: &str) -> Result<String>;
+
+    /// Mark `id` as already in use by another column, so that we never
+    /// return it from `unique_id_for`. The default implementation does
+    /// nothing.
+    fn reserve_id(&mut self, _id: &str) {}
+}
+
+/// Return `id`, or `id` with a numeric suffix if it's already in `used`, and
+/// add the result to `used`. If necessary, `id` is truncated so that the
+/// result is at most `max_len` bytes.
+fn make_unique(
+    used: &mut HashSet<String>,
+    id: String,
+    max_len: usize,
+) -> Result<String> {
+    if id.len() <= max_len && used.insert(id.clone()) {
+        return Ok(id);
+    }
+    for offset in 2..=50 {
+        let suffix = format!("_{}", offset);
+        let mut len = id.len().min(max_len.saturating_sub(suffix.len()));
+        while !id.is_char_boundary(len) {
+            len -= 1;
+        }
+        let alt_id = format!("{}{}", &id[..len], suffix);
+        if used.insert(alt_id.clone()) {
+            return Ok(alt_id);
+        }
+    }
+    Err(format_err!("too many column name collisions"))
+}
+
+#[test]
+fn make_unique_adds_suffixes() {
+    let mut used = HashSet::new();
+    let examples = &[("a", "a"), ("a", "a_2"), ("a", "a_3"), ("abcde", "ab_2")];
+    for &(id, expected) in examples {
+        assert_eq!(make_unique(&mut used, id.to_owned(), 4).unwrap(), expected);
+    }
 }
@@ -0,0 +1,175 @@
+//! Make column names which PostgreSQL accepts without quoting.
+
+use std::collections::HashSet;
+
+use super::{make_unique, snake_case::name_to_snake_case, ColumnNameCleaner};
+use crate::Result;
+
+/// The maximum length of a PostgreSQL identifier, in bytes. Longer names
+/// are silently truncated by the server, which can cause collisions.
+const MAX_LEN: usize = 63;
+
+/// Words which PostgreSQL does not allow as unquoted column names.
+const RESERVED_WORDS: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "authorization",
+    "binary",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "collation",
+    "column",
+    "concurrently",
+    "constraint",
+    "create",
+    "cross",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "freeze",
+    "from",
+    "full",
+    "grant",
+    "group",
+    "having",
+    "ilike",
+    "in",
+    "initially",
+    "inner",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "lateral",
+    "leading",
+    "left",
+    "like",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "natural",
+    "not",
+    "notnull",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "outer",
+    "overlaps",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "right",
+    "select",
+    "session_user",
+    "similar",
+    "some",
+    "symmetric",
+    "system_user",
+    "table",
+    "tablesample",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "verbose",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+/// Turns names into unique `snake_case` identifiers which are valid
+/// PostgreSQL column names. Reserved words get a trailing underscore, and
+/// long names are truncated and given a hash of the original name, so that
+/// names with a common prefix don't collide.
+#[derive(Debug, Default)]
+pub(crate) struct PostgresCleaner {
+    /// Identifiers that we have already generated.
+    used: HashSet<String>,
+}
+
+impl ColumnNameCleaner for PostgresCleaner {
+    fn unique_id_for(&mut self, name: &str) -> Result<String> {
+        let mut id = name_to_snake_case(name);
+        if RESERVED_WORDS.contains(&id.as_str()) {
+            id.push('_');
+        }
+        if id.len() > MAX_LEN {
+            // Our ID is pure ASCII, so we can truncate it anywhere.
+            let hash = format!("_{:08x}", fnv1a(name.as_bytes()));
+            id.truncate(MAX_LEN - hash.len());
+            id.push_str(&hash);
+        }
+        make_unique(&mut self.used, id, MAX_LEN)
+    }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.used.insert(id.to_owned());
+    }
+}
+
+/// A simple 32-bit hash, which will never change between versions of Rust.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[test]
+fn postgres_cleaner_generates_valid_ids() {
+    let long_a = format!("{} A", "x".repeat(70));
+    let long_b = format!("{} B", "x".repeat(70));
+    let mut cleaner = PostgresCleaner::default();
+    assert_eq!(cleaner.unique_id_for("Order").unwrap(), "order_");
+    assert_eq!(cleaner.unique_id_for("userID").unwrap(), "user_id");
+    let id_a = cleaner.unique_id_for(&long_a).unwrap();
+    let id_b = cleaner.unique_id_for(&long_b).unwrap();
+    assert_eq!(id_a.len(), MAX_LEN);
+    assert_eq!(id_b.len(), MAX_LEN);
+    assert_ne!(id_a, id_b);
+    assert!(id_a.starts_with(&"x".repeat(50)));
+    let mut again = PostgresCleaner::default();
+    assert_eq!(again.unique_id_for(&long_a).unwrap(), id_a);
+}
+
+#[test]
+fn fnv1a_is_stable() {
+    assert_eq!(fnv1a(b""), 0x811c_9dc5);
+    assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+}
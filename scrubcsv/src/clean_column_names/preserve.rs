@@ -0,0 +1,32 @@
+//! Keep column names as they are, except for duplicates.
+
+use std::collections::HashSet;
+
+use super::{make_unique, ColumnNameCleaner};
+use crate::Result;
+
+/// Leaves names unchanged, but adds numeric suffixes to duplicate names.
+#[derive(Debug, Default)]
+pub(crate) struct PreserveCleaner {
+    /// Names that we have already generated.
+    used: HashSet<String>,
+}
+
+impl ColumnNameCleaner for PreserveCleaner {
+    fn unique_id_for(&mut self, name: &str) -> Result<String> {
+        make_unique(&mut self.used, name.to_owned(), usize::MAX)
+    }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.used.insert(id.to_owned());
+    }
+}
+
+#[test]
+fn preserve_cleaner_only_uniquifies() {
+    let examples = &[("Order Date", "Order Date"), ("é", "é"), ("é", "é_2")];
+    let mut cleaner = PreserveCleaner::default();
+    for &(input, expected) in examples {
+        assert_eq!(cleaner.unique_id_for(input).unwrap(), expected);
+    }
+}
@@ -0,0 +1,87 @@
+//! Convert column names to `snake_case`.
+
+use std::collections::HashSet;
+
+use super::{make_unique, ColumnNameCleaner};
+use crate::Result;
+
+/// Turns names like `userID` or `Order Date` into unique `snake_case`
+/// identifiers.
+#[derive(Debug, Default)]
+pub(crate) struct SnakeCaseCleaner {
+    /// Identifiers that we have already generated.
+    used: HashSet<String>,
+}
+
+impl ColumnNameCleaner for SnakeCaseCleaner {
+    fn unique_id_for(&mut self, name: &str) -> Result<String> {
+        make_unique(&mut self.used, name_to_snake_case(name), usize::MAX)
+    }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.used.insert(id.to_owned());
+    }
+}
+
+/// Convert `name` to a lowercase ASCII identifier, starting a new word at
+/// each run of punctuation, at each lowercase-to-uppercase transition, and
+/// before the last capital letter in a run of capitals followed by a
+/// lowercase letter (so `HTTPServer` becomes `http_server`).
+pub(crate) fn name_to_snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut id = String::with_capacity(name.len());
+    let mut new_word = false;
+    for (idx, &c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            new_word = true;
+            continue;
+        }
+        if c.is_ascii_uppercase() && idx > 0 {
+            let prev = chars[idx - 1];
+            let next_is_lower = chars
+                .get(idx + 1)
+                .is_some_and(|next| next.is_ascii_lowercase());
+            if prev.is_ascii_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_ascii_uppercase() && next_is_lower)
+            {
+                new_word = true;
+            }
+        }
+        if new_word && !id.is_empty() {
+            id.push('_');
+        }
+        new_word = false;
+        id.push(c.to_ascii_lowercase());
+    }
+    if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+#[test]
+fn name_to_snake_case_splits_words() {
+    let examples = &[
+        ("", "_"),
+        ("userID", "user_id"),
+        ("HTTPServerName", "http_server_name"),
+        ("getHTTP", "get_http"),
+        ("Order Date", "order_date"),
+        ("price (USD)", "price_usd"),
+        ("already_snake_case", "already_snake_case"),
+        ("address2", "address2"),
+        ("2021 Total", "_2021_total"),
+        ("Prénom", "pr_nom"),
+    ];
+    for &(input, expected) in examples {
+        assert_eq!(name_to_snake_case(input), expected);
+    }
+}
+
+#[test]
+fn snake_case_cleaner_generates_unique_ids() {
+    let mut cleaner = SnakeCaseCleaner::default();
+    assert_eq!(cleaner.unique_id_for("userId").unwrap(), "user_id");
+    assert_eq!(cleaner.unique_id_for("User ID").unwrap(), "user_id_2");
+}
@@ -47,4 +47,8 @@ impl ColumnNameCleaner for StableCleaner {
         };
         Ok(normalized)
     }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.used.insert(id.to_owned(), id.to_owned());
+    }
 }
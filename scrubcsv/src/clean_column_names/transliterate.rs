@@ -0,0 +1,59 @@
+//! Convert non-ASCII column names to readable ASCII.
+
+use deunicode::deunicode_with_tofu;
+
+use super::ColumnNameCleaner;
+use crate::Result;
+
+/// Transliterates names to ASCII, and replaces each run of punctuation and
+/// whitespace with a single underscore, before passing them to another
+/// cleaner.
+pub(crate) struct Transliterator {
+    inner: Box<dyn ColumnNameCleaner>,
+}
+
+impl Transliterator {
+    /// Transliterate names before passing them to `inner`.
+    pub(crate) fn new(inner: Box<dyn ColumnNameCleaner>) -> Transliterator {
+        Transliterator { inner }
+    }
+}
+
+impl ColumnNameCleaner for Transliterator {
+    fn unique_id_for(&mut self, name: &str) -> Result<String> {
+        self.inner.unique_id_for(&transliterate(name))
+    }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.inner.reserve_id(id)
+    }
+}
+
+/// Convert `name` to ASCII letters, digits and underscores.
+fn transliterate(name: &str) -> String {
+    let ascii = deunicode_with_tofu(name, "_");
+    let mut out = String::with_capacity(ascii.len());
+    for c in ascii.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out
+}
+
+#[test]
+fn transliterate_converts_to_ascii() {
+    let examples = &[
+        ("Straße", "Strasse"),
+        ("Prénom", "Prenom"),
+        ("Año", "Ano"),
+        ("_id", "_id"),
+        ("a -- b", "a_b"),
+        ("userID", "userID"),
+    ];
+    for &(input, expected) in examples {
+        assert_eq!(transliterate(input), expected);
+    }
+}
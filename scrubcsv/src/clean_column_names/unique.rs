@@ -5,8 +5,8 @@
 
 use std::collections::HashSet;
 
-use super::ColumnNameCleaner;
-use crate::{format_err, Result};
+use super::{make_unique, ColumnNameCleaner};
+use crate::Result;
 
 /// Turns arbitrary Unicode names into unique, lowercase ASCII identifiers. All
 /// identifiers start with an underscore or a lowercase ASCII letter, followed
@@ -19,20 +19,11 @@ pub(crate) struct Uniquifier {
 
 impl ColumnNameCleaner for Uniquifier {
     fn unique_id_for(&mut self, name: &str) -> Result<String> {
-        let id = name_to_lowercase_id(name);
-        if self.used.insert(id.to_owned()) {
-            Ok(id)
-        } else {
-            let mut offset = 1;
-            while offset < 50 {
-                offset += 1;
-                let alt_id = format!("{}_{}", id, offset);
-                if self.used.insert(alt_id.to_owned()) {
-                    return Ok(alt_id);
-                }
-            }
-            Err(format_err!("too many column name collisions"))
-        }
+        make_unique(&mut self.used, name_to_lowercase_id(name), usize::MAX)
+    }
+
+    fn reserve_id(&mut self, id: &str) {
+        self.used.insert(id.to_owned());
     }
 }
 
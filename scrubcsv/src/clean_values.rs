@@ -0,0 +1,227 @@
+//! Cleaning up individual CSV values.
+
+use lazy_static::lazy_static;
+use regex::bytes::Regex as BytesRegex;
+use std::borrow::Cow;
+
+use crate::errors::*;
+use crate::schema::ColumnType;
+use crate::transforms::{ColumnTransforms, InvalidValue};
+use crate::unicode::{
+    normalize_unicode, strip_control_chars, trim_unicode_whitespace, UnicodeForm,
+};
+
+lazy_static! {
+    /// Either a CRLF newline, a LF newline, or a CR newline. Any of these
+    /// will break certain CSV parsers, including BigQuery's CSV importer.
+    static ref NEWLINE_RE: BytesRegex = BytesRegex::new(r#"\n|\r\n?"#)
+        .expect("regex in source code is unparseable");
+}
+
+/// Which of our global cleanups changed a value?
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cleanups {
+    /// The value matched `--null`.
+    pub null: bool,
+    /// `--trim-whitespace` removed whitespace.
+    pub trimmed: bool,
+    /// `--replace-newlines` replaced newlines.
+    pub newlines: bool,
+    /// `--strip-control-chars` removed characters.
+    pub control_chars: bool,
+    /// `--unicode-normalize` changed the value.
+    pub unicode_normalized: bool,
+    /// `--max-field-bytes` truncated the value.
+    pub truncated: bool,
+}
+
+/// The cleanups we apply to every value in a CSV file.
+#[derive(Debug, Default)]
+pub struct ValueCleaner {
+    /// Convert values matching this regex to empty strings.
+    null_re: Option<BytesRegex>,
+    /// Remove whitespace at beginning and end of each value.
+    trim_whitespace: bool,
+    /// When trimming, also remove Unicode whitespace.
+    unicode_whitespace: bool,
+    /// Replace newlines in values with spaces.
+    replace_newlines: bool,
+    /// Remove control characters and zero-width spaces.
+    strip_control_chars: bool,
+    /// Convert values to this Unicode normalization form.
+    unicode_normalize: Option<UnicodeForm>,
+    /// Per-column transforms, applied after our other cleanups.
+    column_transforms: ColumnTransforms,
+}
+
+impl ValueCleaner {
+    /// Create a new `ValueCleaner`. `null` is a regex which must match the
+    /// entire value.
+    pub fn new(
+        null: Option<&str>,
+        trim_whitespace: bool,
+        replace_newlines: bool,
+    ) -> Result<ValueCleaner> {
+        // Build a regex containing our `--null` value.
+        let null_re = if let Some(null_re_str) = null {
+            // Always match the full CSV value.
+            let s = format!("^{}$", null_re_str);
+            let re =
+                BytesRegex::new(&s).context("can't compile regular expression")?;
+            Some(re)
+        } else {
+            None
+        };
+        Ok(ValueCleaner {
+            null_re,
+            trim_whitespace,
+            unicode_whitespace: false,
+            replace_newlines,
+            strip_control_chars: false,
+            unicode_normalize: None,
+            column_transforms: ColumnTransforms::default(),
+        })
+    }
+
+    /// Set our Unicode-aware cleanups. If `unicode_whitespace` is true,
+    /// `trim_whitespace` also removes Unicode whitespace.
+    pub fn set_unicode_cleanups(
+        &mut self,
+        strip_control_chars: bool,
+        unicode_normalize: Option<UnicodeForm>,
+        unicode_whitespace: bool,
+    ) {
+        self.strip_control_chars = strip_control_chars;
+        self.unicode_normalize = unicode_normalize;
+        self.unicode_whitespace = unicode_whitespace;
+    }
+
+    /// Set the per-column transforms to apply.
+    pub fn set_column_transforms(&mut self, column_transforms: ColumnTransforms) {
+        self.column_transforms = column_transforms;
+    }
+
+    /// The type of the cleaned values in column `col`, if our transforms
+    /// normalize them.
+    pub fn column_type(&self, col: usize) -> Option<ColumnType> {
+        self.column_transforms.column_type(col)
+    }
+
+    /// Will `clean` always return its input unchanged?
+    pub fn is_noop(&self) -> bool {
+        self.null_re.is_none()
+            && !self.trim_whitespace
+            && !self.replace_newlines
+            && !self.strip_control_chars
+            && self.unicode_normalize.is_none()
+            && self.column_transforms.is_empty()
+    }
+
+    /// Can `clean_tracked` reject a value, causing us to drop the row?
+    pub fn can_reject(&self) -> bool {
+        self.column_transforms.can_reject()
+    }
+
+    /// Apply all our requested cleanups to a single value from column `col`.
+    /// This may only be used if `can_reject` is false.
+    pub fn clean<'a>(&self, col: usize, val: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.clean_tracked(col, val) {
+            Ok((val, _)) => val,
+            Err(err) => panic!("unexpected invalid value: {}", err.message),
+        }
+    }
+
+    /// Like `clean`, but also report which global cleanups changed the value.
+    /// Returns an error if the value is invalid.
+    pub fn clean_tracked<'a>(
+        &self,
+        col: usize,
+        val: &'a [u8],
+    ) -> std::result::Result<(Cow<'a, [u8]>, Cleanups), InvalidValue> {
+        let mut cleanups = Cleanups::default();
+        let val = self.clean_global(val, &mut cleanups);
+        Ok((self.column_transforms.apply(col, val)?, cleanups))
+    }
+
+    /// Apply the cleanups which affect every column.
+    fn clean_global<'a>(
+        &self,
+        val: &'a [u8],
+        cleanups: &mut Cleanups,
+    ) -> Cow<'a, [u8]> {
+        let mut val = Cow::Borrowed(val);
+
+        // Remove invisible characters and fix Unicode forms, so that `--null`
+        // and trimming see the same value as our output.
+        if self.strip_control_chars {
+            if let Some(stripped) = strip_control_chars(&val) {
+                cleanups.control_chars = true;
+                val = Cow::Owned(stripped);
+            }
+        }
+        if let Some(form) = self.unicode_normalize {
+            if let Some(normalized) = normalize_unicode(&val, form) {
+                cleanups.unicode_normalized = true;
+                val = Cow::Owned(normalized);
+            }
+        }
+
+        match val {
+            Cow::Borrowed(val) => self.clean_remaining(val, cleanups),
+            Cow::Owned(val) => {
+                Cow::Owned(self.clean_remaining(&val, cleanups).into_owned())
+            }
+        }
+    }
+
+    /// Apply the remaining cleanups which affect every column, after our
+    /// Unicode cleanups.
+    fn clean_remaining<'a>(
+        &self,
+        mut val: &'a [u8],
+        cleanups: &mut Cleanups,
+    ) -> Cow<'a, [u8]> {
+        // Convert values matching `--null` regex to empty strings.
+        if let Some(ref null_re) = self.null_re {
+            if null_re.is_match(val) {
+                cleanups.null = true;
+                val = &[]
+            }
+        }
+
+        // Remove whitespace from our cells.
+        if self.trim_whitespace {
+            let trimmed = if self.unicode_whitespace {
+                trim_unicode_whitespace(val)
+            } else {
+                trim_ascii_whitespace(val)
+            };
+            cleanups.trimmed = trimmed.len() != val.len();
+            val = trimmed;
+        }
+
+        // Fix newlines.
+        if self.replace_newlines && (val.contains(&b'\n') || val.contains(&b'\r')) {
+            cleanups.newlines = true;
+            NEWLINE_RE.replace_all(val, &b" "[..])
+        } else {
+            Cow::Borrowed(val)
+        }
+    }
+}
+
+/// Remove ASCII whitespace from the beginning and end of `val`.
+pub fn trim_ascii_whitespace(val: &[u8]) -> &[u8] {
+    // We do this manually, because the built-in `trim` only works on UTF-8
+    // strings, and we work on any "ASCII-compatible" encoding.
+    let first = val.iter().position(|c| !c.is_ascii_whitespace());
+    let last = val.iter().rposition(|c| !c.is_ascii_whitespace());
+    match (first, last) {
+        (Some(first), Some(last)) if first <= last => &val[first..=last],
+        (None, None) => &[],
+        _ => panic!(
+            "tried to trim {:?}, got impossible indices {:?} {:?}",
+            val, first, last,
+        ),
+    }
+}
@@ -0,0 +1,240 @@
+//! Reading and writing compressed files.
+
+use bzip2::{read::MultiBzDecoder, write::BzEncoder};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::errors::*;
+use crate::format_err;
+
+/// A compression format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, with the extension `.gz`.
+    Gzip,
+    /// Zstandard, with the extension `.zst`.
+    Zstd,
+    /// The Snappy framing format, with the extension `.sz`.
+    Snappy,
+    /// bzip2, with the extension `.bz2`.
+    Bzip2,
+}
+
+/// The number of bytes we need to recognize any of our formats.
+const MAGIC_LEN: usize = 10;
+
+impl Compression {
+    /// Guess the compression of `path` from its extension.
+    pub fn from_extension(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "sz" => Some(Compression::Snappy),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Recognize compressed data from its first few bytes.
+    pub fn from_magic(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(b"\x1f\x8b") {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(b"\xff\x06\x00\x00sNaPpY") {
+            Some(Compression::Snappy)
+        } else if bytes.len() >= MAGIC_LEN
+            && bytes.starts_with(b"BZh")
+            && (b'1'..=b'9').contains(&bytes[3])
+            // A compressed block or the end of the stream, which are
+            // unlikely to appear at the start of a CSV file.
+            && (bytes[4..10] == b"\x31\x41\x59\x26\x53\x59"[..]
+                || bytes[4..10] == b"\x17\x72\x45\x38\x50\x90"[..])
+        {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// Wrap `input` in a decoder for this format.
+    fn decoder<'a, R: Read + Send + 'a>(
+        self,
+        input: R,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(input)),
+            Compression::Zstd => {
+                Box::new(zstd::Decoder::new(input).context("cannot read zstd input")?)
+            }
+            Compression::Snappy => Box::new(snap::read::FrameDecoder::new(input)),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(input)),
+        })
+    }
+}
+
+/// A count of bytes, shared with the reader which updates it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    /// The number of bytes counted so far.
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Count `count` more bytes.
+    pub(crate) fn add(&self, count: u64) {
+        self.0.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// Counts the bytes read from a reader.
+struct CountingReader<R: Read> {
+    input: R,
+    count: ByteCount,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.input.read(buf)?;
+        self.count.add(count as u64);
+        Ok(count)
+    }
+}
+
+/// If `input` is compressed, decompress it. We recognize compressed data
+/// using its first few bytes, falling back to the extension of `path`, if
+/// any. If we decompress `input`, we also return a count of the compressed
+/// bytes read.
+pub(crate) fn decompress<'a>(
+    mut input: Box<dyn Read + Send + 'a>,
+    path: Option<&Path>,
+) -> Result<(Box<dyn Read + Send + 'a>, Option<ByteCount>)> {
+    // Read the first few bytes, and then put them back.
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    input
+        .by_ref()
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)
+        .context("cannot read input")?;
+    let compression = Compression::from_magic(&magic)
+        .or_else(|| path.and_then(Compression::from_extension));
+    let input = Box::new(io::Cursor::new(magic).chain(input));
+    match compression {
+        Some(compression) => {
+            let count = ByteCount::default();
+            let counted = CountingReader {
+                input,
+                count: count.clone(),
+            };
+            Ok((compression.decoder(counted)?, Some(count)))
+        }
+        None => Ok((input, None)),
+    }
+}
+
+/// A writer which optionally compresses its output. Call `finish` when done,
+/// to make sure everything gets written.
+pub enum CompressedWriter<W: Write> {
+    /// Write uncompressed output.
+    Plain(W),
+    /// Write gzip output.
+    Gzip(Box<GzEncoder<W>>),
+    /// Write Zstandard output.
+    Zstd(Box<zstd::Encoder<'static, W>>),
+    /// Write Snappy output.
+    Snappy(Box<snap::write::FrameEncoder<W>>),
+    /// Write bzip2 output.
+    Bzip2(Box<BzEncoder<W>>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Compress data written to `output` using `compression`, if specified.
+    pub fn new(
+        output: W,
+        compression: Option<Compression>,
+    ) -> Result<CompressedWriter<W>> {
+        Ok(match compression {
+            None => CompressedWriter::Plain(output),
+            Some(Compression::Gzip) => CompressedWriter::Gzip(Box::new(
+                GzEncoder::new(output, flate2::Compression::default()),
+            )),
+            Some(Compression::Zstd) => CompressedWriter::Zstd(Box::new(
+                zstd::Encoder::new(output, 0).context("cannot write zstd output")?,
+            )),
+            Some(Compression::Snappy) => CompressedWriter::Snappy(Box::new(
+                snap::write::FrameEncoder::new(output),
+            )),
+            Some(Compression::Bzip2) => CompressedWriter::Bzip2(Box::new(
+                BzEncoder::new(output, bzip2::Compression::default()),
+            )),
+        })
+    }
+
+    /// Finish compressing our output, and return the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        let context = "cannot finish writing compressed output";
+        Ok(match self {
+            CompressedWriter::Plain(output) => output,
+            CompressedWriter::Gzip(wtr) => wtr.finish().context(context)?,
+            CompressedWriter::Zstd(wtr) => wtr.finish().context(context)?,
+            CompressedWriter::Snappy(wtr) => wtr
+                .into_inner()
+                .map_err(|err| format_err!("{}: {}", context, err.error()))?,
+            CompressedWriter::Bzip2(wtr) => wtr.finish().context(context)?,
+        })
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(wtr) => wtr.write(buf),
+            CompressedWriter::Gzip(wtr) => wtr.write(buf),
+            CompressedWriter::Zstd(wtr) => wtr.write(buf),
+            CompressedWriter::Snappy(wtr) => wtr.write(buf),
+            CompressedWriter::Bzip2(wtr) => wtr.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(wtr) => wtr.flush(),
+            CompressedWriter::Gzip(wtr) => wtr.flush(),
+            CompressedWriter::Zstd(wtr) => wtr.flush(),
+            CompressedWriter::Snappy(wtr) => wtr.flush(),
+            CompressedWriter::Bzip2(wtr) => wtr.flush(),
+        }
+    }
+}
+
+#[test]
+fn round_trips_compressed_data() {
+    let data = b"a,b\n1,2\n".repeat(100);
+    for &compression in &[
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Snappy,
+        Compression::Bzip2,
+    ] {
+        let mut wtr = CompressedWriter::new(vec![], Some(compression)).unwrap();
+        wtr.write_all(&data).unwrap();
+        let compressed = wtr.finish().unwrap();
+        assert_eq!(Compression::from_magic(&compressed), Some(compression));
+
+        let (mut rdr, count) = decompress(Box::new(&compressed[..]), None).unwrap();
+        let mut decompressed = vec![];
+        rdr.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(count.unwrap().get(), compressed.len() as u64);
+    }
+    assert_eq!(Compression::from_magic(b"BZh,a,b\n1,2,3\n"), None);
+}
@@ -0,0 +1,166 @@
+//! Removing rows with duplicate keys.
+//!
+//! We only store a 128-bit hash of each key, so memory use depends on the
+//! number of distinct keys, not on their size. The chance of two different
+//! keys colliding is negligible, even for billions of rows. We refuse to track
+//! more than a fixed number of distinct keys, so that we fail with a clear
+//! error instead of running out of memory.
+
+use siphasher::sip::SipHasher13;
+use std::{collections::HashMap, hash::Hasher, str::FromStr};
+
+use crate::errors::*;
+use crate::format_err;
+
+/// Which copy of a duplicated row should we keep?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupeKeep {
+    /// Keep the first copy.
+    First,
+    /// Keep the last copy. This requires reading the input twice.
+    Last,
+}
+
+impl FromStr for DedupeKeep {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DedupeKeep> {
+        match s {
+            "first" => Ok(DedupeKeep::First),
+            "last" => Ok(DedupeKeep::Last),
+            _ => Err(format_err!("expected \"first\" or \"last\", found {:?}", s)),
+        }
+    }
+}
+
+/// The default maximum number of distinct keys to track. Each key uses
+/// roughly 40 bytes, so this is about 400 MB.
+pub const DEFAULT_DEDUPE_MAX_KEYS: usize = 10_000_000;
+
+/// The keys for our two hashers, chosen at random. They're fixed so that a
+/// given input is always deduplicated the same way.
+const HASH_KEYS: [(u64, u64); 2] = [
+    (0x0b3f_6ad4_1c97_e258, 0x7d21_94ce_53a0_f18b),
+    (0xc4e8_2f71_9a5d_063b, 0x36b9_d01e_8f42_7ac5),
+];
+
+/// A 128-bit hash of a row's key.
+type KeyHash = (u64, u64);
+
+/// Tracks which keys we've seen.
+#[derive(Debug)]
+pub struct Deduper {
+    /// The columns which make up our key.
+    key_cols: Vec<usize>,
+    /// Which copy of each row should we keep?
+    keep: DedupeKeep,
+    /// Are we scanning our input to find the last copy of each key?
+    scanning: bool,
+    /// The last row we've seen for each key. When keeping the first copy,
+    /// this is the first row we saw.
+    rows: HashMap<KeyHash, u64>,
+    /// The maximum number of distinct keys to track.
+    max_keys: usize,
+}
+
+impl Deduper {
+    /// Create a new `Deduper` using `key_cols` as the key, which tracks at
+    /// most `max_keys` distinct keys.
+    pub fn new(key_cols: Vec<usize>, keep: DedupeKeep, max_keys: usize) -> Deduper {
+        Deduper {
+            key_cols,
+            keep,
+            scanning: keep == DedupeKeep::Last,
+            rows: HashMap::new(),
+            max_keys,
+        }
+    }
+
+    /// Do we need to scan our input before writing any output?
+    pub fn needs_scan(&self) -> bool {
+        self.scanning
+    }
+
+    /// Call this after passing every row of our input to `keep_row`, so we
+    /// can start deduplicating for real.
+    pub fn finish_scan(&mut self) {
+        self.scanning = false;
+    }
+
+    /// Should we keep row number `row`, which contains `values`? While
+    /// scanning, this always returns `true`. Returns an error if this row has
+    /// a new key, and we're already tracking our maximum number of keys.
+    pub fn keep_row<V: AsRef<[u8]>>(
+        &mut self,
+        row: u64,
+        values: &[V],
+    ) -> Result<bool> {
+        let key = self.key_hash(values);
+        if self.keep == DedupeKeep::Last && !self.scanning {
+            return Ok(self.rows.get(&key) == Some(&row));
+        }
+        if self.rows.len() >= self.max_keys && !self.rows.contains_key(&key) {
+            return Err(format_err!(
+                "found more than {} distinct keys while deduplicating (use \
+                 --dedupe-max-keys to allow more)",
+                self.max_keys
+            ));
+        }
+        if self.scanning {
+            self.rows.insert(key, row);
+            return Ok(true);
+        }
+        Ok(*self.rows.entry(key).or_insert(row) == row)
+    }
+
+    /// Hash the key columns in `values`.
+    fn key_hash<V: AsRef<[u8]>>(&self, values: &[V]) -> KeyHash {
+        // Two independently-keyed 64-bit hashes give us 128 bits.
+        let [(k0, k1), (k2, k3)] = HASH_KEYS;
+        let mut hasher1 = SipHasher13::new_with_keys(k0, k1);
+        let mut hasher2 = SipHasher13::new_with_keys(k2, k3);
+        for &col in &self.key_cols {
+            let value = values.get(col).map_or(&b""[..], |val| val.as_ref());
+            // Include lengths, so that "ab","c" and "a","bc" are different.
+            for hasher in [&mut hasher1, &mut hasher2] {
+                hasher.write_usize(value.len());
+                hasher.write(value);
+            }
+        }
+        (hasher1.finish(), hasher2.finish())
+    }
+}
+
+#[test]
+fn keeps_first_or_last_copy() {
+    let rows: &[&[&str]] = &[
+        &["1", "a", "x"],
+        &["2", "b", "y"],
+        &["1", "a", "z"],
+        &["1", "ax", ""],
+    ];
+    let kept = |deduper: &mut Deduper| {
+        (0..rows.len() as u64)
+            .filter(|&row| deduper.keep_row(row, rows[row as usize]).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let mut first = Deduper::new(vec![0, 1], DedupeKeep::First, 10);
+    assert_eq!(kept(&mut first), vec![0, 1, 3]);
+
+    let mut last = Deduper::new(vec![0, 1], DedupeKeep::Last, 10);
+    assert!(last.needs_scan());
+    assert_eq!(kept(&mut last), vec![0, 1, 2, 3]);
+    last.finish_scan();
+    assert_eq!(kept(&mut last), vec![1, 2, 3]);
+
+    let mut full_row = Deduper::new(vec![0, 1, 2], DedupeKeep::First, 10);
+    assert_eq!(kept(&mut full_row), vec![0, 1, 2, 3]);
+
+    // Duplicates of keys we've seen are fine, but new keys are an error.
+    let mut bounded = Deduper::new(vec![0], DedupeKeep::First, 2);
+    assert!(bounded.keep_row(0, rows[0]).unwrap());
+    assert!(bounded.keep_row(1, rows[1]).unwrap());
+    assert!(!bounded.keep_row(2, rows[2]).unwrap());
+    assert!(bounded.keep_row(3, &["3"]).is_err());
+}
@@ -0,0 +1,107 @@
+//! CSV dialects used for reading and writing.
+//!
+//! We sometimes need to build more than one reader or writer (for example,
+//! one per worker thread), so we keep the configuration in these structs
+//! instead of passing `csv` builders around.
+
+use std::io::{Read, Write};
+
+use crate::util::{OutputQuoteStyle, OutputTerminator};
+use crate::BUFFER_SIZE;
+
+/// The dialect of our input CSV.
+#[derive(Clone, Debug)]
+pub struct InputDialect {
+    /// Character used to separate fields.
+    pub delimiter: u8,
+    /// Character used to quote fields, or `None` to disable quoting.
+    pub quote: Option<u8>,
+    /// Character used to escape quotes inside quoted fields, or `None` if
+    /// quotes are escaped by doubling them.
+    pub escape: Option<u8>,
+}
+
+impl InputDialect {
+    /// Create a CSV reader for this dialect.
+    pub fn csv_reader<R: Read>(&self, input: R, has_headers: bool) -> csv::Reader<R> {
+        let mut rdr_builder = csv::ReaderBuilder::new();
+        // Set a reasonable buffer size.
+        rdr_builder.buffer_capacity(BUFFER_SIZE);
+        // We need headers so that we can honor --drop-row-if-null.
+        rdr_builder.has_headers(has_headers);
+        // Allow records with the wrong number of columns.
+        rdr_builder.flexible(true);
+        // Configure our delimiter.
+        rdr_builder.delimiter(self.delimiter);
+        // Configure our quote character.
+        if let Some(quote) = self.quote {
+            rdr_builder.quote(quote);
+        } else {
+            rdr_builder.quoting(false);
+        }
+        // Configure our escape character.
+        if let Some(escape) = self.escape {
+            rdr_builder.double_quote(false).escape(Some(escape));
+        }
+        rdr_builder.from_reader(input)
+    }
+
+    /// Create a low-level `csv_core` reader for this dialect. This must
+    /// split records in exactly the same places as `csv_reader`.
+    pub fn csv_core_reader(&self) -> csv_core::Reader {
+        let mut rdr_builder = csv_core::ReaderBuilder::new();
+        rdr_builder.delimiter(self.delimiter);
+        if let Some(quote) = self.quote {
+            rdr_builder.quote(quote);
+        } else {
+            rdr_builder.quoting(false);
+        }
+        if let Some(escape) = self.escape {
+            rdr_builder.double_quote(false).escape(Some(escape));
+        }
+        rdr_builder.build()
+    }
+}
+
+/// The dialect of our output CSV.
+#[derive(Clone, Debug)]
+pub struct OutputDialect {
+    /// Character used to separate fields.
+    pub delimiter: u8,
+    /// When to quote fields.
+    pub quote_style: OutputQuoteStyle,
+    /// How to terminate records.
+    pub terminator: OutputTerminator,
+    /// Character used to escape quotes, or `None` to double them.
+    pub escape: Option<u8>,
+}
+
+impl OutputDialect {
+    /// Create a CSV writer for this dialect.
+    pub fn csv_writer<W: Write>(&self, output: W) -> csv::Writer<W> {
+        let mut wtr_builder = csv::WriterBuilder::new();
+        wtr_builder
+            .buffer_capacity(BUFFER_SIZE)
+            .delimiter(self.delimiter)
+            .quote_style(self.quote_style.to_csv())
+            .terminator(self.terminator.to_csv());
+        if let Some(escape) = self.escape {
+            wtr_builder.double_quote(false).escape(escape);
+        }
+        wtr_builder.from_writer(output)
+    }
+
+    /// Create a low-level `csv_core` writer for this dialect. This must
+    /// format records exactly the same way as `csv_writer`.
+    pub fn csv_core_writer(&self) -> csv_core::Writer {
+        let mut wtr_builder = csv_core::WriterBuilder::new();
+        wtr_builder
+            .delimiter(self.delimiter)
+            .quote_style(self.quote_style.to_csv_core())
+            .terminator(self.terminator.to_csv_core());
+        if let Some(escape) = self.escape {
+            wtr_builder.double_quote(false).escape(escape);
+        }
+        wtr_builder.build()
+    }
+}
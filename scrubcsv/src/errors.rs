@@ -5,7 +5,8 @@
 //! I just wanted to see how hard it was to roll an nice error API from scratch
 //! instead of depending on an unstable third-party library.
 
-use std::{error, fmt, result};
+use serde::Serialize;
+use std::{error, fmt, io, result};
 
 /// Our error type. We used a boxed dynamic error because we don't care much
 /// about the details and we're only going to print it for the user anyways.
@@ -33,6 +34,131 @@ impl error::Error for Context {
     }
 }
 
+/// Where in our input an error occurred.
+///
+/// Records count from the start of our CSV data, including any header.
+/// Approximate lines and byte offsets count from the start of our
+/// decompressed input, including any lines skipped before the header. Exact
+/// ones come from our CSV parser, and count from the start of the CSV data it
+/// sees, after skipping those lines and any comments.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Location {
+    /// The 1-based record number, counting the header as record 1, if the
+    /// error is about a specific record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<u64>,
+    /// The 1-based column number, if the error is about a specific value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// The 1-based line number.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    /// The byte offset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte: Option<u64>,
+    /// The raw input at (or just before) the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// Are `line`, `byte` and `snippet` only approximate? This happens when
+    /// we only know how far we'd read ahead of our CSV parser when an I/O
+    /// error occurred.
+    #[serde(skip_serializing_if = "is_false")]
+    pub approximate: bool,
+}
+
+/// Is `value` false? Used to omit `Location::approximate` from JSON.
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(record) = self.record {
+            parts.push(format!("record {}", record));
+        }
+        if let Some(column) = self.column {
+            parts.push(format!("column {}", column));
+        }
+        if let Some(line) = self.line {
+            if self.approximate {
+                parts.push(format!("approximately line {}", line));
+            } else {
+                parts.push(format!("line {}", line));
+            }
+        }
+        if let Some(byte) = self.byte {
+            parts.push(format!("byte {}", byte));
+        }
+        parts.join(", ").fmt(f)
+    }
+}
+
+/// An error which occurred at a known `Location`. This adds the location to
+/// the message of the original error.
+#[derive(Debug)]
+pub struct LocatedError {
+    location: Location,
+    source: Error,
+}
+
+impl LocatedError {
+    /// Wrap `err`, which occurred at `location`.
+    pub fn new(err: Error, location: Location) -> LocatedError {
+        LocatedError {
+            location,
+            source: err,
+        }
+    }
+
+    /// Where did this error occur?
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Mutable access to where this error occurred, so we can add details
+    /// which we only learn further up the stack.
+    pub fn location_mut(&mut self) -> &mut Location {
+        &mut self.location
+    }
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.source, self.location)
+    }
+}
+
+impl error::Error for LocatedError {
+    // We include the message of our source in our own message, so skip it.
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+/// Find the location of `err` or one of its causes, if we know it. This
+/// looks inside `io::Error`s, which is how we report the location of errors
+/// while reading our input.
+pub fn error_location<'a>(
+    err: &'a (dyn error::Error + 'static),
+) -> Option<&'a Location> {
+    let mut next = Some(err);
+    while let Some(err) = next {
+        if let Some(located) = err.downcast_ref::<LocatedError>() {
+            return Some(located.location());
+        }
+        if let Some(located) = err
+            .downcast_ref::<io::Error>()
+            .and_then(|err| err.get_ref())
+            .and_then(|err| err.downcast_ref::<LocatedError>())
+        {
+            return Some(located.location());
+        }
+        next = err.source();
+    }
+    None
+}
+
 /// Extend `Result` with methods that add context to errors.
 pub trait ResultExt<T, E>: Sized {
     /// If this result is an error, wrap that error with `context`.
@@ -0,0 +1,248 @@
+//! Decoding backslash-escaped input, like the output of MySQL's `SELECT INTO
+//! OUTFILE`.
+//!
+//! `csv` only understands escaped quotes inside quoted fields, and it replaces
+//! each escape sequence with the escaped character, so `\t` becomes `t`. So we
+//! decode escapes ourselves, in a single pass over the raw input, and pass
+//! `csv` an equivalent stream of ordinary CSV which quotes fields using `"`
+//! and doubles any quotes inside them.
+
+use std::io::{self, Read};
+
+use crate::dialect::InputDialect;
+
+/// Where are we in the current record?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    StartRecord,
+    StartField,
+    InField,
+    InQuotedField,
+    /// We've seen an escape character. We'll return to the enclosed state
+    /// after decoding the next character.
+    Escaped(EscapedIn),
+}
+
+/// Where did we see an escape character?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EscapedIn {
+    Field,
+    QuotedField,
+}
+
+/// A reader which decodes escape sequences, and outputs standard CSV.
+pub struct EscapeDecoder<R: Read> {
+    input: R,
+    buf: Vec<u8>,
+    state: State,
+    delimiter: u8,
+    quote: Option<u8>,
+    escape: u8,
+    /// The decoded value of the current field.
+    field: Vec<u8>,
+    /// Is the current field exactly `\N`, so far?
+    is_null: bool,
+    /// The number of fields we've output for the current record.
+    fields_in_record: usize,
+    /// Was the last field we output empty?
+    last_field_empty: bool,
+    /// Decoded output which we haven't returned yet.
+    output: Vec<u8>,
+    /// How much of `output` we've returned.
+    output_pos: usize,
+    /// Have we reached the end of `input`?
+    at_eof: bool,
+}
+
+impl<R: Read> EscapeDecoder<R> {
+    /// Decode escape sequences starting with `escape` in `input`, which uses
+    /// the delimiter and quote character of `dialect`. We output CSV using
+    /// `dialect.delimiter` and `"` as our quote character.
+    pub fn new(input: R, escape: u8, dialect: &InputDialect) -> EscapeDecoder<R> {
+        EscapeDecoder {
+            input,
+            buf: vec![0; 64 * 1024],
+            state: State::StartRecord,
+            delimiter: dialect.delimiter,
+            quote: dialect.quote,
+            escape,
+            field: vec![],
+            is_null: false,
+            fields_in_record: 0,
+            last_field_empty: false,
+            output: vec![],
+            output_pos: 0,
+            at_eof: false,
+        }
+    }
+
+    /// Update our state for `c`.
+    fn decode_byte(&mut self, c: u8) {
+        use self::State::*;
+        let is_term = c == b'\r' || c == b'\n';
+        let is_quote = self.quote == Some(c);
+        self.state = match self.state {
+            Escaped(escaped_in) => {
+                // `\N` is NULL, but only if it's the entire field.
+                self.is_null = c == b'N' && self.field.is_empty();
+                self.field.push(decode_escaped(c));
+                match escaped_in {
+                    EscapedIn::Field => InField,
+                    EscapedIn::QuotedField => InQuotedField,
+                }
+            }
+            StartRecord if is_term => {
+                // Pass through blank lines and the rest of `\r\n`.
+                self.output.push(c);
+                StartRecord
+            }
+            StartRecord | StartField if is_quote => InQuotedField,
+            InQuotedField if is_quote => InField,
+            InQuotedField if c == self.escape => Escaped(EscapedIn::QuotedField),
+            InQuotedField => self.push_literal(c, InQuotedField),
+            _ if c == self.escape => Escaped(EscapedIn::Field),
+            _ if c == self.delimiter => {
+                self.end_field();
+                self.output.push(c);
+                StartField
+            }
+            _ if is_term => {
+                self.end_field();
+                self.end_record();
+                self.output.push(c);
+                StartRecord
+            }
+            _ => self.push_literal(c, InField),
+        };
+    }
+
+    /// Add `c` to our field, and return `state`.
+    fn push_literal(&mut self, c: u8, state: State) -> State {
+        self.is_null = false;
+        self.field.push(c);
+        state
+    }
+
+    /// Output the current field, quoting it if necessary.
+    fn end_field(&mut self) {
+        if self.is_null {
+            self.field.clear();
+            self.is_null = false;
+        }
+        let needs_quotes = self
+            .field
+            .iter()
+            .any(|&b| b == b'"' || b == self.delimiter || b == b'\r' || b == b'\n');
+        if needs_quotes {
+            self.output.push(b'"');
+            for &b in &self.field {
+                if b == b'"' {
+                    self.output.push(b'"');
+                }
+                self.output.push(b);
+            }
+            self.output.push(b'"');
+        } else {
+            self.output.extend_from_slice(&self.field);
+        }
+        self.last_field_empty = self.field.is_empty();
+        self.field.clear();
+        self.fields_in_record += 1;
+    }
+
+    /// Finish the current record.
+    fn end_record(&mut self) {
+        // A record containing a single empty field would look like a blank
+        // line, which `csv` would skip.
+        if self.fields_in_record == 1 && self.last_field_empty {
+            self.output.extend_from_slice(b"\"\"");
+        }
+        self.fields_in_record = 0;
+    }
+
+    /// Finish any partial record at the end of our input.
+    fn finish(&mut self) {
+        match self.state {
+            State::StartRecord => return,
+            // Keep a trailing escape character.
+            State::Escaped(_) => {
+                let escape = self.escape;
+                self.push_literal(escape, State::InField);
+            }
+            _ => {}
+        }
+        self.end_field();
+        self.end_record();
+        self.state = State::StartRecord;
+    }
+}
+
+impl<R: Read> Read for EscapeDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output.len() && !self.at_eof {
+            self.output.clear();
+            self.output_pos = 0;
+            let count = self.input.read(&mut self.buf)?;
+            if count == 0 {
+                self.at_eof = true;
+                self.finish();
+            }
+            for i in 0..count {
+                self.decode_byte(self.buf[i]);
+            }
+        }
+        let pending = &self.output[self.output_pos..];
+        let len = pending.len().min(out.len());
+        out[..len].copy_from_slice(&pending[..len]);
+        self.output_pos += len;
+        Ok(len)
+    }
+}
+
+/// The dialect to use when parsing the output of an `EscapeDecoder` for input
+/// using `dialect`.
+pub fn decoded_dialect(dialect: &InputDialect) -> InputDialect {
+    InputDialect {
+        delimiter: dialect.delimiter,
+        quote: Some(b'"'),
+        escape: None,
+    }
+}
+
+/// Decode the character `c` following an escape character. `\n`, `\r`, `\t`
+/// and `\0` become the corresponding control characters, and any other
+/// character stands for itself.
+fn decode_escaped(c: u8) -> u8 {
+    match c {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'0' => b'\0',
+        other => other,
+    }
+}
+
+#[test]
+fn decodes_escapes() {
+    let decode = |input: &str, quote: Option<u8>| {
+        let dialect = InputDialect {
+            delimiter: b',',
+            quote,
+            escape: Some(b'\\'),
+        };
+        let mut decoder = EscapeDecoder::new(input.as_bytes(), b'\\', &dialect);
+        let mut output = String::new();
+        decoder.read_to_string(&mut output).unwrap();
+        output
+    };
+    assert_eq!(
+        decode(r#"a\tb,"x\ty",u\,v,"\N",\N,"say \"hi\"""#, Some(b'"')),
+        "a\tb,x\ty,\"u,v\",,,\"say \"\"hi\"\"\"",
+    );
+    assert_eq!(
+        decode("a\\nb,\\\\,\"q\\\nr\"\r\n\r\n\\N\n\\Nx,\\", Some(b'"')),
+        "\"a\nb\",\\,\"q\nr\"\r\n\r\n\"\"\nNx,\\",
+    );
+    assert_eq!(decode("'it\\'s','a,b'\n", Some(b'\'')), "it's,\"a,b\"\n");
+    assert_eq!(decode("a\\,b,\"c\n", None), "\"a,b\",\"\"\"c\"\n");
+}
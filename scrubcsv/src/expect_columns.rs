@@ -0,0 +1,181 @@
+//! Checking our input columns against an expected list.
+
+use log::debug;
+use std::{fs, path::Path, str::FromStr};
+
+use crate::errors::*;
+
+/// How should we compare our input columns to the expected columns?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpectMode {
+    /// The input must have exactly the expected columns, in the same order.
+    Exact,
+    /// The input must have all the expected columns, in any order, and may
+    /// have extra columns.
+    Superset,
+    /// Output the expected columns in the expected order, dropping any extra
+    /// input columns.
+    Reorder,
+}
+
+impl FromStr for ExpectMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExpectMode> {
+        match s {
+            "exact" => Ok(ExpectMode::Exact),
+            "superset" => Ok(ExpectMode::Superset),
+            "reorder" => Ok(ExpectMode::Reorder),
+            _ => Err(format_err!(
+                "expected \"exact\", \"superset\" or \"reorder\", found {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// The columns we expect our input to have, after cleaning.
+#[derive(Clone, Debug)]
+pub struct ExpectedColumns {
+    /// The expected column names, in order.
+    names: Vec<String>,
+    /// How to compare our input to `names`.
+    mode: ExpectMode,
+    /// In `Reorder` mode, output empty values for missing columns instead of
+    /// failing.
+    allow_missing: bool,
+}
+
+impl ExpectedColumns {
+    /// Expect the columns in `names`.
+    pub fn new(names: Vec<String>, mode: ExpectMode) -> ExpectedColumns {
+        ExpectedColumns {
+            names,
+            mode,
+            allow_missing: false,
+        }
+    }
+
+    /// Load expected columns from a text file with one column name per line.
+    /// Blank lines are ignored.
+    pub fn from_path(path: &Path, mode: ExpectMode) -> Result<ExpectedColumns> {
+        let data = fs::read_to_string(path)
+            .with_context(|_| format!("cannot read {}", path.display()))?;
+        let names = data
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_owned())
+            .collect();
+        Ok(ExpectedColumns::new(names, mode))
+    }
+
+    /// When reordering, output empty values for any missing columns instead of
+    /// failing.
+    pub fn allow_missing(&mut self, allow_missing: bool) -> &mut ExpectedColumns {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    /// How do we compare our input to the expected columns?
+    pub fn mode(&self) -> ExpectMode {
+        self.mode
+    }
+
+    /// The expected column names, in order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Check our input column `names` against the expected columns. In
+    /// `Reorder` mode, returns the input columns to output. Missing columns
+    /// are represented by `names.len()`, which should be output as an empty
+    /// value.
+    pub(crate) fn check(&self, names: &[String]) -> Result<Option<Vec<usize>>> {
+        let added = names
+            .iter()
+            .filter(|name| !self.names.contains(name))
+            .collect::<Vec<_>>();
+        let missing = self
+            .names
+            .iter()
+            .filter(|name| !names.contains(name))
+            .collect::<Vec<_>>();
+        match self.mode {
+            ExpectMode::Exact => {
+                if !added.is_empty() || !missing.is_empty() {
+                    Err(format_err!(
+                        "input columns do not match expected columns (added: {:?}, missing: {:?})",
+                        added,
+                        missing
+                    ))
+                } else if names != &self.names[..] {
+                    Err(format_err!(
+                        "input columns are not in the expected order (expected: {:?}, found: {:?})",
+                        self.names,
+                        names
+                    ))
+                } else {
+                    Ok(None)
+                }
+            }
+            ExpectMode::Superset => {
+                if missing.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(format_err!(
+                        "input is missing expected columns (missing: {:?})",
+                        missing
+                    ))
+                }
+            }
+            ExpectMode::Reorder => {
+                if !missing.is_empty() && !self.allow_missing {
+                    return Err(format_err!(
+                        "input is missing expected columns (added: {:?}, missing: {:?})",
+                        added,
+                        missing
+                    ));
+                }
+                if !added.is_empty() {
+                    debug!("dropping unexpected columns: {:?}", added);
+                }
+                Ok(Some(
+                    self.names
+                        .iter()
+                        .map(|name| {
+                            names.iter().position(|n| n == name).unwrap_or(names.len())
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+}
+
+#[test]
+fn checks_expected_columns() {
+    let names = |names: &[&str]| {
+        names
+            .iter()
+            .map(|n| (*n).to_owned())
+            .collect::<Vec<String>>()
+    };
+    let input = names(&["b", "a", "c"]);
+
+    let exact = ExpectedColumns::new(names(&["b", "a", "c"]), ExpectMode::Exact);
+    assert_eq!(exact.check(&input).unwrap(), None);
+    let exact = ExpectedColumns::new(names(&["a", "b", "c"]), ExpectMode::Exact);
+    assert!(exact.check(&input).is_err());
+
+    let superset = ExpectedColumns::new(names(&["c", "a"]), ExpectMode::Superset);
+    assert_eq!(superset.check(&input).unwrap(), None);
+    let superset = ExpectedColumns::new(names(&["a", "d"]), ExpectMode::Superset);
+    assert!(superset.check(&input).is_err());
+
+    let mut reorder =
+        ExpectedColumns::new(names(&["c", "d", "a"]), ExpectMode::Reorder);
+    assert!(reorder.check(&input).is_err());
+    reorder.allow_missing(true);
+    assert_eq!(reorder.check(&input).unwrap(), Some(vec![2, 3, 1]));
+}
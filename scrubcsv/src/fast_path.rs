@@ -0,0 +1,425 @@
+//! A zero-copy fast path for when we don't need to clean any values.
+//!
+//! We parse records using `csv_core` directly, keeping each record's raw bytes
+//! in our input buffer. Once we've seen all of a record's fields and checked
+//! that it has the right number of columns, we can usually write those raw
+//! bytes straight to our output. We only need to re-encode a record if it
+//! contains bytes which would be quoted or escaped differently in our output
+//! dialect.
+
+use csv::ByteRecord;
+use csv_core::{ReadRecordResult, WriteResult};
+use log::debug;
+use std::io::{self, Read, Write};
+
+use crate::dialect::{InputDialect, OutputDialect};
+use crate::errors::*;
+use crate::position::locate_read_error;
+use crate::progress::ProgressReporter;
+use crate::stats::StatsCollector;
+use crate::util::OutputQuoteStyle;
+use crate::BUFFER_SIZE;
+
+/// A CSV reader which keeps the raw bytes of the current record.
+pub struct FastReader<R: Read> {
+    input: R,
+    core: csv_core::Reader,
+    /// Our input buffer.
+    buf: Vec<u8>,
+    /// The start of the current record in `buf`.
+    record_start: usize,
+    /// The start of the unparsed data in `buf`.
+    start: usize,
+    /// The end of the valid data in `buf`.
+    end: usize,
+    /// Have we reached the end of `input`?
+    eof: bool,
+    /// The unescaped field data of the current record.
+    fields: Vec<u8>,
+    /// The end of each field in `fields`.
+    ends: Vec<usize>,
+    /// The number of fields in the current record.
+    field_count: usize,
+    /// The total number of bytes read from `input`.
+    bytes_read: u64,
+    /// The number of records we've read, including any header.
+    records: u64,
+}
+
+impl<R: Read> FastReader<R> {
+    /// Create a new reader.
+    pub fn new(input: R, dialect: &InputDialect) -> FastReader<R> {
+        FastReader {
+            input,
+            core: dialect.csv_core_reader(),
+            buf: vec![0; BUFFER_SIZE],
+            record_start: 0,
+            start: 0,
+            end: 0,
+            eof: false,
+            fields: vec![0; 4 * 1024],
+            ends: vec![0; 256],
+            field_count: 0,
+            bytes_read: 0,
+            records: 0,
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Read the next record. Returns `false` at the end of the input.
+    pub fn read_record(&mut self) -> Result<bool> {
+        self.record_start = self.start;
+        let (mut outlen, mut endlen) = (0, 0);
+        loop {
+            if self.start == self.end && !self.eof {
+                let record = self.records + 1;
+                self.fill_buf()
+                    .map_err(|err| locate_read_error(err, record))
+                    .context("cannot read record")?;
+            }
+            let (result, nin, nout, nend) = self.core.read_record(
+                &self.buf[self.start..self.end],
+                &mut self.fields[outlen..],
+                &mut self.ends[endlen..],
+            );
+            self.start += nin;
+            outlen += nout;
+            endlen += nend;
+            match result {
+                ReadRecordResult::InputEmpty => {}
+                ReadRecordResult::OutputFull => {
+                    let new_len = self.fields.len() * 2;
+                    self.fields.resize(new_len, 0);
+                }
+                ReadRecordResult::OutputEndsFull => {
+                    let new_len = self.ends.len() * 2;
+                    self.ends.resize(new_len, 0);
+                }
+                ReadRecordResult::Record => {
+                    self.field_count = endlen;
+                    self.records += 1;
+                    return Ok(true);
+                }
+                ReadRecordResult::End => return Ok(false),
+            }
+        }
+    }
+
+    /// Read more data into our buffer, keeping the current record.
+    fn fill_buf(&mut self) -> io::Result<()> {
+        // Move the current partial record to the front of our buffer, and
+        // make more room if a single record is larger than our buffer.
+        if self.record_start > 0 {
+            self.buf.copy_within(self.record_start..self.end, 0);
+            self.start -= self.record_start;
+            self.end -= self.record_start;
+            self.record_start = 0;
+        }
+        if self.end == self.buf.len() {
+            let new_len = self.buf.len() * 2;
+            self.buf.resize(new_len, 0);
+        }
+        loop {
+            match self.input.read(&mut self.buf[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => {
+                    self.end += n;
+                    self.bytes_read += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+            return Ok(());
+        }
+    }
+
+    /// The number of fields in the current record.
+    pub fn len(&self) -> usize {
+        self.field_count
+    }
+
+    /// The unescaped value of field `idx` of the current record, or an empty
+    /// value if there is no such field.
+    pub fn field(&self, idx: usize) -> &[u8] {
+        if idx >= self.field_count {
+            return &[];
+        }
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        &self.fields[start..self.ends[idx]]
+    }
+
+    /// The raw input bytes of the current record, without any leading blank
+    /// lines or trailing record terminator.
+    pub fn raw_record(&self) -> &[u8] {
+        let raw = &self.buf[self.record_start..self.start];
+        let first = raw.iter().position(|&b| b != b'\r' && b != b'\n');
+        let last = raw.iter().rposition(|&b| b != b'\r' && b != b'\n');
+        match (first, last) {
+            (Some(first), Some(last)) => &raw[first..=last],
+            _ => &[],
+        }
+    }
+
+    /// Copy the current record into a `ByteRecord`.
+    pub fn to_byte_record(&self) -> ByteRecord {
+        let mut record = ByteRecord::with_capacity(
+            self.ends[..self.field_count].last().copied().unwrap_or(0),
+            self.field_count,
+        );
+        for idx in 0..self.field_count {
+            record.push_field(self.field(idx));
+        }
+        record
+    }
+}
+
+/// A CSV writer which can write either fields or pre-formatted records.
+pub struct FastWriter<W: Write> {
+    output: W,
+    core: csv_core::Writer,
+    /// The terminator to write after raw records.
+    terminator: Vec<u8>,
+    /// Our output buffer.
+    buf: Vec<u8>,
+    /// The number of bytes used in `buf`.
+    len: usize,
+}
+
+impl<W: Write> FastWriter<W> {
+    /// Create a new writer.
+    pub fn new(output: W, dialect: &OutputDialect) -> FastWriter<W> {
+        let terminator = dialect.terminator.as_bytes().to_owned();
+        FastWriter {
+            output,
+            core: dialect.csv_core_writer(),
+            terminator,
+            buf: vec![0; BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Write a record that is already formatted for our output dialect,
+    /// followed by a terminator.
+    pub fn write_raw(&mut self, raw: &[u8]) -> io::Result<()> {
+        if self.buf.len() - self.len < raw.len() + 2 {
+            self.flush_buf()?;
+            if self.buf.len() < raw.len() + 2 {
+                self.output.write_all(raw)?;
+                return self.output.write_all(&self.terminator);
+            }
+        }
+        self.buf[self.len..self.len + raw.len()].copy_from_slice(raw);
+        self.len += raw.len();
+        self.buf[self.len..self.len + self.terminator.len()]
+            .copy_from_slice(&self.terminator);
+        self.len += self.terminator.len();
+        Ok(())
+    }
+
+    /// Encode and write a record.
+    pub fn write_fields<'a, I>(&mut self, fields: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        for (idx, mut field) in fields.into_iter().enumerate() {
+            if idx > 0 {
+                self.write_with(|core, out| core.delimiter(out))?;
+            }
+            loop {
+                let (result, nin, nout) =
+                    self.core.field(field, &mut self.buf[self.len..]);
+                field = &field[nin..];
+                self.len += nout;
+                match result {
+                    WriteResult::InputEmpty => break,
+                    WriteResult::OutputFull => self.flush_buf()?,
+                }
+            }
+        }
+        self.write_with(|core, out| core.terminator(out))
+    }
+
+    /// Call `f` to write to our buffer, flushing the buffer if it's full.
+    fn write_with<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&mut csv_core::Writer, &mut [u8]) -> (WriteResult, usize),
+    {
+        loop {
+            let (result, nout) = f(&mut self.core, &mut self.buf[self.len..]);
+            self.len += nout;
+            match result {
+                WriteResult::InputEmpty => return Ok(()),
+                WriteResult::OutputFull => self.flush_buf()?,
+            }
+        }
+    }
+
+    /// Write our buffer to our output.
+    fn flush_buf(&mut self) -> io::Result<()> {
+        self.output.write_all(&self.buf[..self.len])?;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Write all buffered data to our output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.output.flush()
+    }
+}
+
+/// Bytes which prevent us from copying a raw record to our output, because
+/// the input and output dialects would interpret them differently. Returns
+/// `None` if we can never copy raw records.
+fn raw_copy_blockers(
+    input: &InputDialect,
+    output: &OutputDialect,
+) -> Option<[bool; 256]> {
+    if input.delimiter != output.delimiter {
+        return None;
+    }
+    match output.quote_style {
+        OutputQuoteStyle::Necessary | OutputQuoteStyle::Never => {}
+        _ => return None,
+    }
+    let mut blockers = [false; 256];
+    // Anything that might need quoting or escaping in our output.
+    blockers[b'"' as usize] = true;
+    blockers[b'\r' as usize] = true;
+    blockers[b'\n' as usize] = true;
+    if let Some(escape) = output.escape {
+        blockers[escape as usize] = true;
+    }
+    for &b in output.terminator.as_bytes() {
+        blockers[b as usize] = true;
+    }
+    // Anything that our input parser treats specially, other than the
+    // delimiter.
+    if let Some(quote) = input.quote {
+        blockers[quote as usize] = true;
+    }
+    if let Some(escape) = input.escape {
+        blockers[escape as usize] = true;
+    }
+    Some(blockers)
+}
+
+/// Process all the remaining records in `rdr`, writing good records to
+/// `wtr`. `expected_cols` is the number of columns in each record, and
+/// `selected_cols` optionally lists the columns to output. Returns the
+/// number of rows, records bad rows in `stats`, and reports our progress to
+/// `progress`.
+#[allow(clippy::too_many_arguments)]
+pub fn process_fast<R: Read, W: Write>(
+    rdr: &mut FastReader<R>,
+    wtr: &mut FastWriter<W>,
+    first_row: u64,
+    expected_cols: usize,
+    selected_cols: Option<&[usize]>,
+    input_dialect: &InputDialect,
+    output_dialect: &OutputDialect,
+    stats: &mut StatsCollector,
+    mut progress: Option<&mut ProgressReporter<'_>>,
+) -> Result<u64> {
+    // We can only copy records if we're outputting all our columns.
+    let blockers = match selected_cols {
+        None => raw_copy_blockers(input_dialect, output_dialect),
+        Some(_) => None,
+    };
+
+    let mut row = first_row;
+    while rdr.read_record()? {
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.tick(row, stats);
+        }
+
+        // Check if we have the right number of columns in this row.
+        if rdr.len() != expected_cols {
+            stats.record_wrong_column_count(rdr.len());
+            debug!(
+                "row {}: expected {} columns, found {}",
+                row,
+                expected_cols,
+                rdr.len(),
+            );
+            row += 1;
+            continue;
+        }
+
+        // Copy the record if we can, or re-encode it if we must.
+        let raw = rdr.raw_record();
+        let can_copy = match &blockers {
+            Some(blockers) => {
+                !raw.is_empty() && !raw.iter().any(|&b| blockers[b as usize])
+            }
+            None => false,
+        };
+        if can_copy {
+            wtr.write_raw(raw)
+        } else if let Some(cols) = selected_cols {
+            wtr.write_fields(cols.iter().map(|&idx| rdr.field(idx)))
+        } else {
+            wtr.write_fields((0..rdr.len()).map(|idx| rdr.field(idx)))
+        }
+        .context("cannot write record")?;
+        row += 1;
+    }
+    Ok(row - first_row)
+}
+
+#[test]
+fn fast_path_matches_csv_writer() {
+    let input_dialect = InputDialect {
+        delimiter: b',',
+        quote: Some(b'"'),
+        escape: None,
+    };
+    let output_dialect = OutputDialect {
+        delimiter: b',',
+        quote_style: OutputQuoteStyle::Necessary,
+        terminator: crate::util::OutputTerminator::Byte(b'\n'),
+        escape: None,
+    };
+    let input = "a,b\r\n1,2\r\n\n\"x\ny\",\"q\"\"\"\nbad\n\"plain\",é\n,\nlast,row";
+
+    // Process our input using the fast path.
+    let mut rdr = FastReader::new(input.as_bytes(), &input_dialect);
+    assert!(rdr.read_record().unwrap());
+    let mut wtr = FastWriter::new(vec![], &output_dialect);
+    wtr.write_fields((0..rdr.len()).map(|idx| rdr.field(idx)))
+        .unwrap();
+    let mut stats = StatsCollector::new(None);
+    let rows = process_fast(
+        &mut rdr,
+        &mut wtr,
+        2,
+        2,
+        None,
+        &input_dialect,
+        &output_dialect,
+        &mut stats,
+        None,
+    )
+    .unwrap();
+    wtr.flush().unwrap();
+    assert_eq!((rows, stats.bad_rows()), (6, 1));
+    assert_eq!(rdr.bytes_read(), input.len() as u64);
+
+    // Process our input using `csv`.
+    let mut csv_rdr = input_dialect.csv_reader(input.as_bytes(), false);
+    let mut csv_wtr = output_dialect.csv_writer(vec![]);
+    for record in csv_rdr.byte_records() {
+        let record = record.unwrap();
+        if record.len() == 2 {
+            csv_wtr.write_byte_record(&record).unwrap();
+        }
+    }
+    assert_eq!(
+        String::from_utf8(wtr.output).unwrap(),
+        String::from_utf8(csv_wtr.into_inner().unwrap()).unwrap(),
+    );
+}
@@ -0,0 +1,68 @@
+//! Remove bad lines from large CSV files and normalize the rest.
+//!
+//! This is the library behind the `scrubcsv` command-line tool. Configure a
+//! `ScrubOptions`, use it to create a `Scrubber` for your input, and then
+//! either write the cleaned CSV to any `Write`, or read the cleaned records
+//! one at a time.
+
+#![warn(clippy::all)]
+#![forbid(unsafe_code)]
+
+// Modules defined in separate files.
+mod clean_column_names;
+mod clean_values;
+mod comments;
+mod compression;
+mod dedupe;
+mod dialect;
+mod escapes;
+#[macro_use]
+pub mod errors;
+mod expect_columns;
+mod fast_path;
+mod limits;
+mod normalize;
+mod options;
+mod output_format;
+mod parallel;
+mod position;
+mod progress;
+mod record_processor;
+mod sample;
+mod schema;
+mod scrubber;
+mod select;
+mod skip;
+mod sniff;
+mod stats;
+mod transforms;
+mod unicode;
+mod util;
+
+// Our public API.
+pub use crate::clean_column_names::{
+    ColumnNameCleaner, ColumnNameCleanerType, ColumnNameMap,
+};
+pub use crate::compression::{CompressedWriter, Compression};
+pub use crate::dedupe::DedupeKeep;
+pub use crate::errors::{Error, Result};
+pub use crate::expect_columns::{ExpectMode, ExpectedColumns};
+pub use crate::limits::{FieldLimit, LongFieldAction};
+pub use crate::options::ScrubOptions;
+pub use crate::output_format::OutputFormat;
+pub use crate::progress::Progress;
+pub use crate::schema::{ColumnType, Schema};
+pub use crate::scrubber::{ByteRecordsIter, Scrubber};
+pub use crate::select::{ColumnSpec, Rename};
+pub use crate::sniff::SniffedDialect;
+pub use crate::stats::{ColumnProfile, Rejections, SampleStats, Stats};
+pub use crate::transforms::TransformRules;
+pub use crate::unicode::UnicodeForm;
+pub use crate::util::{
+    CharSpecifier, DelimiterSpecifier, OutputQuoteStyle, OutputTerminator,
+};
+
+/// Use reasonably large input and output buffers. This seems to give us a
+/// performance boost of around 5-10% compared to the standard 8 KiB buffer used
+/// by `csv`.
+const BUFFER_SIZE: usize = 256 * 1024;
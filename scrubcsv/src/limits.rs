@@ -0,0 +1,233 @@
+//! Limiting the size of output fields and rows, which many database loaders
+//! reject.
+
+use log::debug;
+use std::{borrow::Cow, str::FromStr};
+
+use crate::clean_values::Cleanups;
+use crate::errors::*;
+use crate::select::ColumnSpec;
+use crate::util::truncate_bytes;
+
+/// What should we do with a value which is longer than its limit?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LongFieldAction {
+    /// Truncate the value, without splitting any UTF-8 characters.
+    Truncate,
+    /// Drop the row, and count it as a bad row.
+    Drop,
+}
+
+impl FromStr for LongFieldAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LongFieldAction> {
+        match s {
+            "truncate" => Ok(LongFieldAction::Truncate),
+            "drop" => Ok(LongFieldAction::Drop),
+            _ => Err(format_err!(
+                "expected \"truncate\" or \"drop\", found {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// A maximum value size in bytes, specified as `N` for every column or
+/// `COL=N` for specific columns.
+#[derive(Debug, Clone)]
+pub struct FieldLimit {
+    /// The columns to limit, or `None` for every column.
+    pub columns: Option<ColumnSpec>,
+    /// The maximum size of a value.
+    pub max_bytes: usize,
+}
+
+impl FromStr for FieldLimit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FieldLimit> {
+        if let Ok(max_bytes) = s.parse::<usize>() {
+            return Ok(FieldLimit {
+                columns: None,
+                max_bytes,
+            });
+        }
+        match s.rsplit_once('=') {
+            Some((col, max_bytes)) if !col.is_empty() => Ok(FieldLimit {
+                columns: Some(col.parse()?),
+                max_bytes: max_bytes.parse().with_context(|_| {
+                    format!("cannot parse field limit {:?}", max_bytes)
+                })?,
+            }),
+            _ => Err(format_err!("expected N or COL=N, found {:?}", s)),
+        }
+    }
+}
+
+/// Our field and row size limits, resolved against our output columns.
+#[derive(Debug)]
+pub struct Limits {
+    /// The maximum size of a value in each input column. Only columns which
+    /// we output have limits.
+    max_field_bytes: Vec<Option<usize>>,
+    /// What to do with values which are too long.
+    long_field_action: LongFieldAction,
+    /// The maximum size of an output row.
+    max_row_bytes: Option<usize>,
+}
+
+impl Limits {
+    /// Resolve `field_limits` against our input column `names`, where
+    /// `out_cols` are the indices of the input columns we output. Later
+    /// limits override earlier ones, so a limit for every column can be
+    /// followed by exceptions for specific columns.
+    pub fn new(
+        names: &[String],
+        out_cols: &[usize],
+        field_limits: &[FieldLimit],
+        long_field_action: LongFieldAction,
+        max_row_bytes: Option<usize>,
+    ) -> Result<Limits> {
+        let mut max_field_bytes = vec![None; names.len()];
+        for limit in field_limits {
+            let cols = match &limit.columns {
+                None => (0..names.len()).collect(),
+                Some(spec) => spec.matching_indices(names)?,
+            };
+            for idx in cols {
+                if out_cols.contains(&idx) {
+                    max_field_bytes[idx] = Some(limit.max_bytes);
+                }
+            }
+        }
+        Ok(Limits {
+            max_field_bytes,
+            long_field_action,
+            max_row_bytes,
+        })
+    }
+
+    /// Do we have any limits to enforce?
+    pub fn is_empty(&self) -> bool {
+        self.max_field_bytes.iter().all(|limit| limit.is_none())
+            && self.max_row_bytes.is_none()
+    }
+
+    /// Enforce the limit for column `col` on `val`, which is from row number
+    /// `row`. Returns `false` if the row should be dropped.
+    pub fn limit_field(
+        &self,
+        row: u64,
+        col: usize,
+        val: &mut Cow<'_, [u8]>,
+        cleanups: &mut Cleanups,
+    ) -> bool {
+        let max_len = match self.max_field_bytes.get(col) {
+            Some(&Some(max_len)) if val.len() > max_len => max_len,
+            _ => return true,
+        };
+        match self.long_field_action {
+            LongFieldAction::Truncate => {
+                *val = match std::mem::take(val) {
+                    Cow::Borrowed(v) => Cow::Borrowed(truncate_bytes(v, max_len)),
+                    Cow::Owned(v) => {
+                        Cow::Owned(truncate_bytes(&v, max_len).to_owned())
+                    }
+                };
+                cleanups.truncated = true;
+                true
+            }
+            LongFieldAction::Drop => {
+                debug!(
+                    "row {}, column {}: value is longer than {} bytes",
+                    row,
+                    col + 1,
+                    max_len
+                );
+                false
+            }
+        }
+    }
+
+    /// Does an output row containing `values` fit within our row limit? We
+    /// count one byte for each delimiter, but don't count quotes or the
+    /// record terminator.
+    pub fn row_fits<'a, I>(&self, row: u64, values: I) -> bool
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let max_len = match self.max_row_bytes {
+            Some(max_len) => max_len,
+            None => return true,
+        };
+        let mut len = 0;
+        let mut count = 0;
+        for val in values {
+            len += val.len();
+            count += 1;
+        }
+        len += count.max(1) - 1;
+        if len > max_len {
+            debug!("row {}: row is {} bytes, limit is {}", row, len, max_len);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[test]
+fn limits_fields_and_rows() {
+    let names = ["id", "notes"]
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+    let field_limits = ["2".parse().unwrap(), "notes=4".parse().unwrap()];
+    let limits = Limits::new(
+        &names,
+        &[0, 1],
+        &field_limits,
+        LongFieldAction::Truncate,
+        Some(6),
+    )
+    .unwrap();
+    let mut cleanups = Cleanups::default();
+
+    let mut val = Cow::Borrowed(&b"123"[..]);
+    assert!(limits.limit_field(1, 0, &mut val, &mut cleanups));
+    assert_eq!(&*val, b"12");
+    assert!(cleanups.truncated);
+
+    let mut val = Cow::Borrowed("aaé".as_bytes());
+    assert!(limits.limit_field(1, 1, &mut val, &mut cleanups));
+    assert_eq!(&*val, "aaé".as_bytes());
+    let mut val = Cow::Owned("aaaé".as_bytes().to_owned());
+    assert!(limits.limit_field(1, 1, &mut val, &mut cleanups));
+    assert_eq!(&*val, b"aaa");
+
+    assert!(limits.row_fits(1, vec![&b"12"[..], &b"abc"[..]]));
+    assert!(!limits.row_fits(1, vec![&b"12"[..], &b"abcd"[..]]));
+
+    let limits =
+        Limits::new(&names, &[0, 1], &field_limits, LongFieldAction::Drop, None)
+            .unwrap();
+    let mut val = Cow::Borrowed(&b"123"[..]);
+    assert!(!limits.limit_field(1, 0, &mut val, &mut cleanups));
+
+    // We don't limit columns we don't output.
+    let limits = Limits::new(
+        &names,
+        &[1],
+        &field_limits[..1],
+        LongFieldAction::Drop,
+        None,
+    )
+    .unwrap();
+    assert!(limits.limit_field(1, 0, &mut val, &mut cleanups));
+    assert!(!limits.is_empty());
+    let limits =
+        Limits::new(&names, &[], &field_limits, LongFieldAction::Drop, None).unwrap();
+    assert!(limits.is_empty());
+    assert!("notes=x".parse::<FieldLimit>().is_err());
+}
@@ -5,34 +5,26 @@
 use clap::Parser;
 use csv::ByteRecord;
 use humansize::{format_size, BINARY};
-use lazy_static::lazy_static;
 use log::debug;
 use regex::{bytes::Regex as BytesRegex, Regex};
-use serde_json::json;
 use std::{
-    borrow::Cow,
     fs,
-    io::{self, prelude::*},
+    io::{self, IsTerminal, Write},
     path::PathBuf,
     process,
-    time::Instant,
+    str::FromStr,
+    time::Duration,
 };
 
-// Modules defined in separate files.
-mod clean_column_names;
-#[macro_use]
-mod errors;
-mod util;
-
-// Import from our own crates.
-use crate::clean_column_names::ColumnNameCleanerType;
-use crate::errors::*;
-use crate::util::CharSpecifier;
-
-/// Use reasonably large input and output buffers. This seems to give us a
-/// performance boost of around 5-10% compared to the standard 8 KiB buffer used
-/// by `csv`.
-const BUFFER_SIZE: usize = 256 * 1024;
+// Import from our library.
+use scrubcsv::errors::*;
+use scrubcsv::{
+    format_err, CharSpecifier, ColumnNameCleanerType, ColumnNameMap, ColumnSpec,
+    CompressedWriter, Compression, DedupeKeep, DelimiterSpecifier, ExpectMode,
+    ExpectedColumns, FieldLimit, LongFieldAction, OutputFormat, OutputQuoteStyle,
+    OutputTerminator, Progress, Rename, Schema, ScrubOptions, Scrubber,
+    TransformRules, UnicodeForm,
+};
 
 /// Our command-line arguments.
 #[derive(Debug, Parser)]
@@ -55,18 +47,72 @@ Exit code:
     2 if more than 10% of rows were bad"
 )]
 struct Opt {
-    /// Input file (uses stdin if omitted).
+    /// Input file (uses stdin if omitted). Input compressed using gzip,
+    /// Zstandard, Snappy or bzip2 is decompressed automatically.
     input: Option<PathBuf>,
 
     /// Character used to separate fields in a row (must be a single ASCII
-    /// byte, or "tab").
+    /// byte, or "tab"). Use "auto" to detect the dialect, like `--sniff`.
     #[arg(
         value_name = "CHAR",
         short = 'd',
         long = "delimiter",
         default_value = ","
     )]
-    delimiter: CharSpecifier,
+    delimiter: DelimiterSpecifier,
+
+    /// Detect the input's delimiter, quote character and escape style from
+    /// the start of the input, instead of using `--delimiter`, `--quote` and
+    /// `--escape`, and detect whether the input has a header row. If it
+    /// doesn't, `--header` must be used to supply column names. The detected
+    /// dialect is included in `--output-stats-to-file`.
+    #[arg(long = "sniff")]
+    sniff: bool,
+
+    /// The number of KiB at the start of the input to use when sniffing.
+    #[arg(value_name = "KIB", long = "sniff-size", default_value = "64")]
+    sniff_size: usize,
+
+    /// The input has no header row. Column names must be supplied using
+    /// `--header` or `--header-file`.
+    #[arg(long = "no-headers", requires = "header_source")]
+    no_headers: bool,
+
+    /// Use these comma-separated column names instead of the input's header
+    /// row, and write them to the output.
+    #[arg(value_name = "NAMES", long = "header", group = "header_source")]
+    header: Option<String>,
+
+    /// Read column names from the first line of PATH instead of the input's
+    /// header row, and write them to the output. PATH uses the same
+    /// delimiter and quote character as the input.
+    #[arg(value_name = "PATH", long = "header-file", group = "header_source")]
+    header_file: Option<PathBuf>,
+
+    /// Skip N lines at the start of the input, before the header.
+    #[arg(value_name = "N", long = "skip-lines", default_value = "0")]
+    skip_lines: usize,
+
+    /// Skip lines at the start of the input until one matches REGEX, and use
+    /// that line as the header. Applied after `--skip-lines`.
+    #[arg(value_name = "REGEX", long = "header-regex")]
+    header_regex: Option<BytesRegex>,
+
+    /// Ignore records matching REGEX at the end of the input. Fields are
+    /// joined using the delimiter before matching. If REGEX has a capture
+    /// group named "rows", check that it matches the number of rows read:
+    /// `--footer-regex '^TOTAL ROWS: (?P<rows>\d+)$'`.
+    #[arg(value_name = "REGEX", long = "footer-regex")]
+    footer_regex: Option<BytesRegex>,
+
+    /// Ignore the last N records of the input.
+    #[arg(value_name = "N", long = "skip-trailing", default_value = "0")]
+    skip_trailing: usize,
+
+    /// Ignore lines starting with CHAR. Comment lines are counted separately
+    /// from bad rows.
+    #[arg(value_name = "CHAR", long = "comment")]
+    comment: Option<CharSpecifier>,
 
     /// Convert values matching NULL_REGEX to an empty string. For a case-insensitive
     /// match, use `(?i)`: `--null '(?i)NULL'`.
@@ -83,13 +129,79 @@ struct Opt {
     #[arg(long = "trim-whitespace")]
     trim_whitespace: bool,
 
+    /// When trimming whitespace, also remove Unicode whitespace like
+    /// non-breaking spaces, zero-width spaces and byte order marks.
+    #[arg(long = "unicode-whitespace", requires = "trim_whitespace")]
+    unicode_whitespace: bool,
+
+    /// Remove control characters (except tabs and newlines), zero-width spaces
+    /// and byte order marks from each cell.
+    #[arg(long = "strip-control-chars")]
+    strip_control_chars: bool,
+
+    /// Convert cells to Unicode normalization form FORM, either "nfc" or
+    /// "nfkc". "nfkc" also replaces compatibility characters like "ﬁ" and
+    /// non-breaking spaces with their plain equivalents.
+    #[arg(value_name = "FORM", long = "unicode-normalize")]
+    unicode_normalize: Option<UnicodeForm>,
+
+    /// Apply per-column transforms from a TOML or JSON rules file. Rules can
+    /// replace regex matches, change case, trim whitespace, convert values to
+    /// NULL, and truncate values. Uses the cleaned form of column names.
+    #[arg(value_name = "PATH", long = "transform-rules")]
+    transform_rules: Option<PathBuf>,
+
     /// Make sure column names are unique, and use only lowercase letters,
     /// numbers and underscores. "unique" (the default) will assign number
     /// prefixes to make names unique. "stable" will use a simple, predictable
     /// mapping, and fail with an error if the resulting names are not unique.
+    /// "snake_case" is like "unique", but splits words like "userID".
+    /// "postgres" is like "snake_case", but also limits names to 63 bytes and
+    /// avoids reserved words. "preserve" only makes names unique.
     #[arg(value_name = "CLEANER_TYPE", long = "clean-column-names")]
     clean_column_names: Option<Option<ColumnNameCleanerType>>,
 
+    /// Before cleaning column names, convert them to ASCII (so that "Straße"
+    /// becomes "strasse"), and replace runs of punctuation and whitespace
+    /// with a single underscore.
+    #[arg(long = "transliterate-column-names", requires = "clean_column_names")]
+    transliterate_column_names: bool,
+
+    /// Look up column names in a CSV file with "original" and "clean"
+    /// columns, or a JSON object (if PATH ends in ".json"), before cleaning
+    /// them. Fails if an input column is missing from the map, unless
+    /// `--update-column-name-map` is passed.
+    #[arg(value_name = "PATH", long = "column-name-map")]
+    column_name_map: Option<PathBuf>,
+
+    /// Clean any input columns which are missing from `--column-name-map`,
+    /// and add them to the map file.
+    #[arg(long = "update-column-name-map", requires = "column_name_map")]
+    update_column_name_map: bool,
+
+    /// Check the (cleaned) input column names against a text file with one
+    /// column name per line, and fail with a list of added and missing
+    /// columns if they don't match.
+    #[arg(value_name = "PATH", long = "expect-columns")]
+    expect_columns: Option<PathBuf>,
+
+    /// How to compare input columns to `--expect-columns`. "exact" requires
+    /// the same columns in the same order. "superset" allows extra columns,
+    /// in any order. "reorder" outputs the expected columns in the expected
+    /// order, and drops any extra columns.
+    #[arg(
+        value_name = "MODE",
+        long = "expect-columns-mode",
+        default_value = "exact",
+        requires = "expect_columns"
+    )]
+    expect_columns_mode: ExpectMode,
+
+    /// With `--expect-columns-mode=reorder`, output empty values for any
+    /// missing columns instead of failing.
+    #[arg(long = "allow-missing-columns", requires = "expect_columns")]
+    allow_missing_columns: bool,
+
     /// Fail if the output CSV file would contain any column names matching the
     /// specified regular expression.
     #[arg(long = "reserve-column-names")]
@@ -101,21 +213,248 @@ struct Opt {
     #[arg(value_name = "COL", long = "drop-row-if-null")]
     drop_row_if_null: Vec<String>,
 
-    /// Do not print performance information.
+    /// Only output the specified columns, in the specified order. Columns
+    /// may be specified using their cleaned name, a 1-based index, or a
+    /// `/regex/` matching names. Can be passed more than once.
+    #[arg(value_name = "COL", long = "select")]
+    select: Vec<ColumnSpec>,
+
+    /// Do not output the specified columns. Uses the same syntax as
+    /// `--select`. Can be passed more than once.
+    #[arg(value_name = "COL", long = "exclude")]
+    exclude: Vec<ColumnSpec>,
+
+    /// Rename the column OLD to NEW in the output. OLD is the cleaned form of
+    /// the column name. Can be passed more than once.
+    #[arg(value_name = "OLD=NEW", long = "rename")]
+    rename: Vec<Rename>,
+
+    /// Drop rows with the same values in the specified columns as another
+    /// row. Columns use the same syntax as `--select`, separated by commas,
+    /// and values are compared after cleaning.
+    #[arg(
+        value_name = "COL,...",
+        long = "dedupe-on",
+        value_delimiter = ',',
+        conflicts_with = "dedupe"
+    )]
+    dedupe_on: Vec<ColumnSpec>,
+
+    /// Drop rows which are identical to another output row after cleaning.
+    #[arg(long = "dedupe")]
+    dedupe: bool,
+
+    /// Which copy of a duplicated row to keep: "first" or "last". Keeping the
+    /// last copy requires an input file, because we read it twice.
+    #[arg(value_name = "KEEP", long = "dedupe-keep", default_value = "first")]
+    dedupe_keep: DedupeKeep,
+
+    /// The maximum number of distinct keys to track when deduplicating. Each
+    /// key uses about 40 bytes of memory, and we fail if we find more.
+    #[arg(value_name = "N", long = "dedupe-max-keys", default_value = "10000000")]
+    dedupe_max_keys: usize,
+
+    /// Limit output values to N bytes, or use COL=N to limit specific columns,
+    /// using the same syntax as `--select`. Can be passed more than once,
+    /// and later limits override earlier ones. Values are measured after
+    /// cleaning.
+    #[arg(value_name = "N|COL=N", long = "max-field-bytes")]
+    max_field_bytes: Vec<FieldLimit>,
+
+    /// What to do with values longer than `--max-field-bytes`: "truncate"
+    /// them without splitting UTF-8 characters, or "drop" the row.
+    #[arg(
+        value_name = "ACTION",
+        long = "long-field-action",
+        default_value = "truncate"
+    )]
+    long_field_action: LongFieldAction,
+
+    /// Drop output rows longer than N bytes, counting delimiters but not
+    /// quotes or line endings.
+    #[arg(value_name = "N", long = "max-row-bytes")]
+    max_row_bytes: Option<usize>,
+
+    /// Do not print progress or performance information.
     #[arg(short = 'q', long = "quiet")]
     quiet: bool,
 
+    /// How often to report progress, in seconds. When stderr is a terminal, we
+    /// redraw a progress bar every second by default. Otherwise, we print a
+    /// line of progress every 30 seconds by default.
+    #[arg(value_name = "SECONDS", long = "progress-interval")]
+    progress_interval: Option<f64>,
+
     /// Character used to quote entries. May be set to "none" to ignore all
     /// quoting.
     #[arg(value_name = "CHAR", long = "quote", default_value = "\"")]
     quote: CharSpecifier,
 
-    /// Output statistics to a JSON file at the specified path.
+    /// Character used to escape special characters (typically a backslash),
+    /// instead of doubling quotes. Escapes work in both quoted and unquoted
+    /// fields, so "\," is a literal delimiter. Also decodes escape sequences
+    /// like "\n" and "\t" in values, and treats "\N" as NULL.
+    #[arg(value_name = "CHAR", long = "escape")]
+    escape: Option<CharSpecifier>,
+
+    /// Stop after writing N good rows.
+    #[arg(value_name = "N", long = "limit")]
+    limit: Option<u64>,
+
+    /// Only check a random fraction F of the input rows, from 0 to 1, and skip
+    /// the rest. The same --seed always selects the same rows. The stats
+    /// estimate the number of bad rows in the entire input.
+    #[arg(value_name = "F", long = "sample-rate")]
+    sample_rate: Option<f64>,
+
+    /// Write a uniform random sample of N good rows from the entire input, in
+    /// their original order.
+    #[arg(value_name = "N", long = "reservoir")]
+    reservoir: Option<usize>,
+
+    /// The random seed used by --sample-rate and --reservoir.
+    #[arg(value_name = "N", long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Declare the types of output columns using a TOML or JSON schema file,
+    /// with a "columns" table mapping column names to "string", "integer",
+    /// "number", "boolean", "date" or "timestamp". Rows with values which
+    /// don't match their column's type are dropped.
+    #[arg(value_name = "PATH", long = "schema")]
+    schema: Option<PathBuf>,
+
+    /// The output format: "csv", "jsonl" (one JSON object per line) or
+    /// "parquet". JSON Lines and Parquet use the output column names as field
+    /// names, write empty values as nulls, and use the types from --schema or
+    /// the "number", "boolean" and "date" transform rules.
+    #[arg(value_name = "FORMAT", long = "output-format", default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// Character used to separate fields in the output (must be a single ASCII
+    /// byte, or "tab").
+    #[arg(value_name = "CHAR", long = "output-delimiter", default_value = ",")]
+    output_delimiter: CharSpecifier,
+
+    /// When to quote output fields: "necessary", "always", "non-numeric" or
+    /// "never".
+    #[arg(
+        value_name = "STYLE",
+        long = "output-quote-style",
+        default_value = "necessary"
+    )]
+    output_quote_style: OutputQuoteStyle,
+
+    /// How to terminate output records: "lf", "crlf", "cr" or a single ASCII
+    /// byte.
+    #[arg(
+        value_name = "TERMINATOR",
+        long = "output-terminator",
+        default_value = "lf"
+    )]
+    output_terminator: OutputTerminator,
+
+    /// Escape quotes in output fields using CHAR (typically a backslash) instead
+    /// of doubling them.
+    #[arg(value_name = "CHAR", long = "output-escape")]
+    output_escape: Option<CharSpecifier>,
+
+    /// Number of worker threads to use for cleaning records. Use 0 to pick a
+    /// value based on the number of available CPUs.
+    #[arg(value_name = "N", long = "threads", default_value = "1")]
+    threads: usize,
+
+    /// Disable the zero-copy fast path, even when we don't need to clean any
+    /// values. Only useful for benchmarking and debugging.
+    #[arg(long = "no-fast-path", hide = true)]
+    no_fast_path: bool,
+
+    /// Write the output to PATH instead of stdout. If PATH ends in ".gz",
+    /// ".zst", ".sz" or ".bz2", the output is compressed using gzip,
+    /// Zstandard, Snappy or bzip2.
+    #[arg(value_name = "PATH", short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    /// Output statistics to a JSON file at the specified path, including
+    /// rejection counts.
     #[arg(value_name = "PATH", long = "output-stats-to-file")]
     output_stats_to_file: Option<PathBuf>,
+
+    /// Include a profile of the output values in each column in
+    /// `--output-stats-to-file`. This is noticeably slower, because it
+    /// disables our fast path.
+    #[arg(long = "profile-columns", requires = "output_stats_to_file")]
+    profile_columns: bool,
+
+    /// How to report errors on stderr: "text", or "json" for a single JSON
+    /// object with the error, its causes and its location in the input (if
+    /// known).
+    #[arg(value_name = "FORMAT", long = "error-format", default_value = "text")]
+    error_format: ErrorFormat,
+}
+
+/// How should we report errors?
+#[derive(Clone, Copy, Debug)]
+enum ErrorFormat {
+    /// Human-readable text.
+    Text,
+    /// A JSON object.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ErrorFormat> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format_err!("expected \"text\" or \"json\", found {:?}", s)),
+        }
+    }
 }
 
 impl Opt {
+    /// Were we asked to remove duplicate rows?
+    fn dedupe_enabled(&self) -> bool {
+        self.dedupe || !self.dedupe_on.is_empty()
+    }
+
+    /// Get the header specified using `--header` or `--header-file`, if any.
+    fn supplied_header(&self) -> Result<Option<ByteRecord>> {
+        let mut hdr = ByteRecord::new();
+        if let Some(header) = &self.header {
+            csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(header.as_bytes())
+                .read_byte_record(&mut hdr)
+                .context("cannot parse --header")?;
+        } else if let Some(path) = &self.header_file {
+            if self.sniffing() {
+                return Err(format_err!(
+                    "cannot use --header-file when sniffing the input dialect"
+                ));
+            }
+            let file = fs::File::open(path)
+                .with_context(|_| format!("cannot open {}", path.display()))?;
+            let mut rdr_builder = csv::ReaderBuilder::new();
+            rdr_builder.has_headers(false).delimiter(self.delimiter()?);
+            match self.quote.char() {
+                Some(quote) => rdr_builder.quote(quote),
+                None => rdr_builder.quoting(false),
+            };
+            rdr_builder
+                .from_reader(file)
+                .read_byte_record(&mut hdr)
+                .with_context(|_| format!("cannot read {}", path.display()))?;
+        } else {
+            return Ok(None);
+        }
+        if hdr.is_empty() {
+            return Err(format_err!("supplied header must not be empty"));
+        }
+        Ok(Some(hdr))
+    }
+
     /// Get the value of `clean_column_names`, defaulting as necessary.
     fn column_name_cleaner_type(&self) -> Option<ColumnNameCleanerType> {
         match self.clean_column_names {
@@ -124,261 +463,197 @@ impl Opt {
             None => None,
         }
     }
-}
 
-lazy_static! {
-    /// Either a CRLF newline, a LF newline, or a CR newline. Any of these
-    /// will break certain CSV parsers, including BigQuery's CSV importer.
-    static ref NEWLINE_RE: BytesRegex = BytesRegex::new(r#"\n|\r\n?"#)
-        .expect("regex in source code is unparseable");
+    /// Were we asked to detect the input dialect?
+    fn sniffing(&self) -> bool {
+        self.sniff || matches!(self.delimiter, DelimiterSpecifier::Auto)
+    }
+
+    /// Get our input delimiter. If we're sniffing, this is only used if our
+    /// input is empty.
+    fn delimiter(&self) -> Result<u8> {
+        match &self.delimiter {
+            DelimiterSpecifier::Auto => Ok(b','),
+            DelimiterSpecifier::Char(delimiter) => delimiter
+                .char()
+                .ok_or_else(|| format_err!("field delimiter is required")),
+        }
+    }
+
+    /// Convert our command-line options to `ScrubOptions`.
+    fn scrub_options(&self) -> Result<ScrubOptions> {
+        let output_delimiter = self
+            .output_delimiter
+            .char()
+            .ok_or_else(|| format_err!("output field delimiter is required"))?;
+        let output_escape =
+            match &self.output_escape {
+                Some(escape) => Some(escape.char().ok_or_else(|| {
+                    format_err!("output escape character is required")
+                })?),
+                None => None,
+            };
+        let transform_rules = match &self.transform_rules {
+            Some(path) => Some(TransformRules::from_path(path)?),
+            None => None,
+        };
+        let schema = match &self.schema {
+            Some(path) => Some(Schema::from_path(path)?),
+            None => None,
+        };
+        let column_name_map = match &self.column_name_map {
+            Some(path) => Some(ColumnNameMap::from_path(path)?),
+            None => None,
+        };
+        let expect_columns = match &self.expect_columns {
+            Some(path) => {
+                let mut expected =
+                    ExpectedColumns::from_path(path, self.expect_columns_mode)?;
+                expected.allow_missing(self.allow_missing_columns);
+                Some(expected)
+            }
+            None => None,
+        };
+        let mut options = ScrubOptions::new();
+        options
+            .sniff(self.sniffing())
+            .sniff_bytes(self.sniff_size * 1024)
+            .delimiter(self.delimiter()?)
+            .quote(self.quote.char())
+            .escape(self.escape.as_ref().and_then(|e| e.char()))
+            .has_headers(!self.no_headers)
+            .header(self.supplied_header()?)
+            .skip_lines(self.skip_lines)
+            .header_regex(self.header_regex.clone())
+            .footer_regex(self.footer_regex.clone())
+            .skip_trailing(self.skip_trailing)
+            .comment(self.comment.as_ref().and_then(|c| c.char()))
+            .null(self.null.as_deref())
+            .replace_newlines(self.replace_newlines)
+            .trim_whitespace(self.trim_whitespace)
+            .unicode_whitespace(self.unicode_whitespace)
+            .strip_control_chars(self.strip_control_chars)
+            .unicode_normalize(self.unicode_normalize)
+            .transform_rules(transform_rules)
+            .clean_column_names(self.column_name_cleaner_type())
+            .transliterate_column_names(self.transliterate_column_names)
+            .column_name_map(column_name_map)
+            .update_column_name_map(self.update_column_name_map)
+            .expect_columns(expect_columns)
+            .reserve_column_names(self.reserve_column_names.clone())
+            .drop_row_if_null(self.drop_row_if_null.clone())
+            .select(self.select.clone())
+            .exclude(self.exclude.clone())
+            .rename(self.rename.clone())
+            .dedupe_on(self.dedupe_on.clone())
+            .dedupe(self.dedupe)
+            .dedupe_keep(self.dedupe_keep)
+            .dedupe_max_keys(self.dedupe_max_keys)
+            .max_field_bytes(self.max_field_bytes.clone())
+            .long_field_action(self.long_field_action)
+            .max_row_bytes(self.max_row_bytes)
+            .limit(self.limit)
+            .sample_rate(self.sample_rate)
+            .reservoir(self.reservoir)
+            .seed(self.seed)
+            .schema(schema)
+            .output_format(self.output_format)
+            .output_delimiter(output_delimiter)
+            .output_quote_style(self.output_quote_style)
+            .output_terminator(self.output_terminator)
+            .output_escape(output_escape)
+            .threads(self.threads)
+            .fast_path(!self.no_fast_path)
+            .profile_columns(self.profile_columns);
+        Ok(options)
+    }
 }
 
 /// This is a helper function called by our `main` function.  Unlike
 /// `main`, we return a `Result`, which means that we can use `?` and other
 /// standard error-handling machinery.
-fn run() -> Result<()> {
+fn run(opt: &Opt) -> Result<()> {
     // Set up logging.
     env_logger::init();
-
-    // Parse our command-line arguments using `clap`.
-    let opt: Opt = Opt::parse();
     debug!("Options: {:#?}", opt);
 
-    // Remember the time we started.
-    let start_time = Instant::now();
-
-    // Build a regex containing our `--null` value.
-    let null_re = if let Some(null_re_str) = opt.null.as_ref() {
-        // Always match the full CSV value.
-        let s = format!("^{}$", null_re_str);
-        let re = BytesRegex::new(&s).context("can't compile regular expression")?;
-        Some(re)
-    } else {
-        None
-    };
-
-    // Fetch our input from either standard input or a file.  The only tricky
-    // detail here is that we use a `Box<dyn Read>` to represent "some object
-    // implementing `Read`, stored on the heap."  This allows us to do runtime
-    // dispatch (as if Rust were object oriented).  But because `csv` wraps a
-    // `BufReader` around the box, we only do that dispatch once per buffer
-    // flush, not on every tiny write.
-    let stdin = io::stdin();
-    let input: Box<dyn Read> = if let Some(ref path) = opt.input {
-        Box::new(
-            fs::File::open(path)
-                .with_context(|_| format!("cannot open {}", path.display()))?,
-        )
+    // Build our scrubber, which reads our header. The only tricky detail here
+    // is that we need a file if we're going to read our input twice.
+    let options = opt.scrub_options()?;
+    let mut scrubber = if let Some(ref path) = opt.input {
+        Scrubber::from_path(&options, path)?
     } else {
-        Box::new(stdin.lock())
+        Scrubber::new(&options, io::stdin())?
     };
 
-    // Create our CSV reader.
-    let mut rdr_builder = csv::ReaderBuilder::new();
-    // Set a reasonable buffer size.
-    rdr_builder.buffer_capacity(BUFFER_SIZE);
-    // We need headers so that we can honor --drop-row-if-null.
-    rdr_builder.has_headers(true);
-    // Allow records with the wrong number of columns.
-    rdr_builder.flexible(true);
-    // Configure our delimiter.
-    if let Some(delimiter) = opt.delimiter.char() {
-        rdr_builder.delimiter(delimiter);
-    } else {
-        return Err(format_err!("field delimiter is required"));
-    }
-    // Configure our quote character.
-    if let Some(quote) = opt.quote.char() {
-        rdr_builder.quote(quote);
-    } else {
-        rdr_builder.quoting(false);
-    }
-    let mut rdr = rdr_builder.from_reader(input);
-
-    // We lock `stdout`, giving us exclusive access. In the past, this has made
-    // an enormous difference in performance.
-    let stdout = io::stdout();
-    let output = stdout.lock();
-
-    // Create our CSV writer.  Note that we _don't_ allow variable numbers
-    // of columns, non-standard delimiters, or other nonsense: We want our
-    // output to be highly normalized.
-    let mut wtr = csv::WriterBuilder::new()
-        .buffer_capacity(BUFFER_SIZE)
-        .from_writer(output);
-
-    // Get our header and, if we were asked, make sure all the column names are unique.
-    let mut hdr = rdr
-        .byte_headers()
-        .context("cannot read headers")?
-        .to_owned();
-    if let Some(cleaner_type) = opt.column_name_cleaner_type() {
-        let mut cleaner = cleaner_type.build_cleaner();
-        let mut new_hdr = ByteRecord::default();
-        for col in hdr.into_iter() {
-            // Convert from bytes to UTF-8, make unique (and clean), and convert back to bytes.
-            let col = String::from_utf8_lossy(col);
-            let col = cleaner.unique_id_for(&col)?.to_owned();
-            if let Some(reserved_re) = &opt.reserve_column_names {
-                if reserved_re.is_match(&col[..]) {
-                    return Err(format_err!(
-                        "file used reserved column name {:?}",
-                        col
-                    ));
-                }
-            }
-            new_hdr.push_field(col.as_bytes());
-        }
-        hdr = new_hdr;
+    // Report our progress on stderr, using a progress bar if it's a terminal.
+    let progress_bar = io::stderr().is_terminal();
+    if !opt.quiet {
+        let default_interval = if progress_bar { 1.0 } else { 30.0 };
+        let interval = opt.progress_interval.unwrap_or(default_interval);
+        let interval = Duration::try_from_secs_f64(interval).map_err(|_| {
+            format_err!("--progress-interval must be a number of seconds")
+        })?;
+        scrubber.on_progress(interval, move |progress| {
+            report_progress(progress, progress_bar)
+        });
     }
 
-    // Write our header to our output.
-    wtr.write_byte_record(&hdr)
-        .context("cannot write headers")?;
-
-    // Calculate the number of expected columns.
-    let expected_cols = hdr.len();
-
-    // Just in case --drop-row-if-null was passed, precompute which columns are
-    // required to contain a value.
-    let required_cols = hdr
-        .iter()
-        .map(|name| -> bool {
-            opt.drop_row_if_null
-                .iter()
-                .any(|requried_name| requried_name.as_bytes() == name)
-        })
-        .collect::<Vec<bool>>();
-
-    // Keep track of total rows and malformed rows seen. We count the header as
-    // a row for backwards compatibility.
-    let mut rows: u64 = 1;
-    let mut bad_rows: u64 = 0;
-
-    // Can we use the fast path and copy the data through unchanged? Or do we
-    // need to clean up emebedded newlines in our data? (These break BigQuery,
-    // for example.)
-    let use_fast_path = null_re.is_none()
-        && !opt.replace_newlines
-        && !opt.trim_whitespace
-        && opt.drop_row_if_null.is_empty();
-
-    // Iterate over all the rows, checking to make sure they look reasonable.
-    //
-    // If we use the lowest-level, zero-copy API for `csv`, we can process about
-    // 225 MB/s.  But it turns out we can't do that, because we need to count
-    // all the row's fields before deciding whether or not to write it out.
-    'next_row: for record in rdr.byte_records() {
-        let record = record.context("cannot read record")?;
-
-        // Keep track of how many rows we've seen.
-        rows += 1;
-
-        // Check if we have the right number of columns in this row.
-        if record.len() != expected_cols {
-            bad_rows += 1;
-            debug!(
-                "row {}: expected {} columns, found {}",
-                rows,
-                expected_cols,
-                record.len(),
-            );
-            continue 'next_row;
-        }
+    // Remember any new column names, so we can save them if we succeed.
+    let updated_column_name_map = scrubber
+        .column_name_map()
+        .filter(|map| map.is_modified())
+        .cloned();
+
+    // Write our output, compressing it if requested.
+    let stats = if let Some(path) = &opt.output {
+        let file = fs::File::create(path)
+            .with_context(|_| format!("cannot create {}", path.display()))?;
+        let mut wtr = CompressedWriter::new(
+            io::BufWriter::new(file),
+            Compression::from_extension(path),
+        )?;
+        let stats = scrubber.scrub(&mut wtr)?;
+        wtr.finish()?
+            .flush()
+            .with_context(|_| format!("cannot write {}", path.display()))?;
+        stats
+    } else {
+        // We lock `stdout`, giving us exclusive access. In the past, this has
+        // made an enormous difference in performance.
+        let stdout = io::stdout();
+        scrubber.scrub(stdout.lock())?
+    };
 
-        // Decide how to handle this row.
-        if use_fast_path {
-            // We don't need to do anything fancy, so just pass it through.
-            // I'm not sure how much this actually buys us in current Rust
-            // versions, but it seemed like a good idea at the time.
-            wtr.write_record(record.into_iter())
-                .context("cannot write record")?;
-        } else {
-            // We need to apply one or more cleanups, so run the slow path.
-            let cleaned = record.into_iter().map(|mut val: &[u8]| -> Cow<'_, [u8]> {
-                // Convert values matching `--null` regex to empty strings.
-                if let Some(ref null_re) = null_re {
-                    if null_re.is_match(val) {
-                        val = &[]
-                    }
-                }
-
-                // Remove whitespace from our cells.
-                if opt.trim_whitespace {
-                    // We do this manually, because the built-in `trim` only
-                    // works on UTF-8 strings, and we work on any
-                    // "ASCII-compatible" encoding.
-                    let first = val.iter().position(|c| !c.is_ascii_whitespace());
-                    let last = val.iter().rposition(|c| !c.is_ascii_whitespace());
-                    val = match (first, last) {
-                        (Some(first), Some(last)) if first <= last => {
-                            &val[first..=last]
-                        }
-                        (None, None) => &[],
-                        _ => panic!(
-                            "tried to trim {:?}, got impossible indices {:?} {:?}",
-                            val, first, last,
-                        ),
-                    };
-                }
-
-                // Fix newlines.
-                if opt.replace_newlines
-                    && (val.contains(&b'\n') || val.contains(&b'\r'))
-                {
-                    NEWLINE_RE.replace_all(val, &b" "[..])
-                } else {
-                    Cow::Borrowed(val)
-                }
-            });
-            if opt.drop_row_if_null.is_empty() {
-                // Still somewhat fast!
-                wtr.write_record(cleaned).context("cannot write record")?;
-            } else {
-                // We need to rebuild the record, check for null columns,
-                // and only output the record if everything's OK.
-                let row = cleaned.collect::<Vec<Cow<'_, [u8]>>>();
-                for (value, &is_required_col) in row.iter().zip(required_cols.iter()) {
-                    // If the column is NULL but shouldn't be, bail on this row.
-                    if is_required_col && value.is_empty() {
-                        bad_rows += 1;
-                        debug!("row {}: required column is empty", rows);
-                        continue 'next_row;
-                    }
-                }
-                wtr.write_record(row).context("cannot write record")?;
-            }
-        }
+    // Save any new column names.
+    if let (Some(path), Some(map)) = (&opt.column_name_map, &updated_column_name_map) {
+        map.to_path(path)?;
     }
 
-    // Flush all our buffers.
-    wtr.flush().context("error writing records")?;
-
-    // Calculate statistics.
-    let ellapsed = start_time.elapsed().as_secs_f64();
-    let bytes_processed = rdr.position().byte();
-    let bytes_per_second = (bytes_processed as f64 / ellapsed) as i64;
-
     // Print out some information about our run.
     if !opt.quiet {
+        if progress_bar {
+            // Erase our progress bar.
+            eprint!("\r\x1b[K");
+        }
+        let mut rejected = format!("{} bad", stats.bad_rows);
+        if opt.dedupe_enabled() {
+            rejected.push_str(&format!(", {} duplicate", stats.duplicate_rows));
+        }
+        if opt.comment.is_some() {
+            rejected.push_str(&format!(", {} comments", stats.comment_lines));
+        }
         eprintln!(
-            "{} rows ({} bad) in {:.2} seconds, {}/sec",
-            rows,
-            bad_rows,
-            ellapsed,
-            format_size(bytes_per_second as u64, BINARY),
+            "{} rows ({}) in {:.2} seconds, {}/sec",
+            stats.rows,
+            rejected,
+            stats.elapsed_seconds,
+            format_size(stats.bytes_per_second as u64, BINARY),
         );
     }
 
     // Output statistics to file if requested.
     if let Some(stats_path) = &opt.output_stats_to_file {
-        let stats = json!({
-            "rows": rows,
-            "bad_rows": bad_rows,
-            "elapsed_seconds": ellapsed,
-            "bytes_processed": bytes_processed,
-            "bytes_per_second": bytes_per_second,
-        });
         fs::write(
             stats_path,
             serde_json::to_string_pretty(&stats)
@@ -390,23 +665,114 @@ fn run() -> Result<()> {
     }
 
     // If more than 10% of rows are bad, assume something has gone horribly
-    // wrong.
+    // wrong. If we only checked a sample, use the rows we checked.
+    let (bad_rows, rows) = match &stats.sample {
+        Some(sample) => (stats.bad_rows, sample.rows_checked),
+        None => (stats.bad_rows, stats.rows),
+    };
     if bad_rows.checked_mul(10).expect("multiplication overflow") > rows {
-        eprintln!("Too many rows ({} of {}) were bad", bad_rows, rows);
+        let message = format!("Too many rows ({} of {}) were bad", bad_rows, rows);
+        match opt.error_format {
+            ErrorFormat::Text => eprintln!("{}", message),
+            ErrorFormat::Json => {
+                eprintln!("{}", serde_json::json!({ "error": message, "causes": [] }))
+            }
+        }
         process::exit(2);
     }
 
     Ok(())
 }
 
+/// Print `progress` to stderr, either as a progress bar which we redraw in
+/// place, or as a log line.
+fn report_progress(progress: &Progress, progress_bar: bool) {
+    let mut message = format!(
+        "{} rows ({} bad), {}, {}/sec",
+        progress.rows,
+        progress.bad_rows,
+        format_size(progress.bytes_processed, BINARY),
+        format_size(progress.bytes_per_second() as u64, BINARY),
+    );
+    if let (Some(fraction), Some(eta)) =
+        (progress.fraction_done(), progress.eta_seconds())
+    {
+        message.push_str(&format!(
+            ", {:.1}% done, ETA {}",
+            fraction * 100.0,
+            format_seconds(eta)
+        ));
+    }
+    if progress_bar {
+        const WIDTH: usize = 20;
+        let bar = match progress.fraction_done() {
+            Some(fraction) => {
+                let filled = (fraction * WIDTH as f64) as usize;
+                format!("[{}{}] ", "#".repeat(filled), "-".repeat(WIDTH - filled))
+            }
+            None => String::new(),
+        };
+        eprint!("\r\x1b[K{}{}", bar, message);
+    } else {
+        eprintln!("progress: {}", message);
+    }
+}
+
+/// Format `seconds` like "1h02m03s".
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Print `err` to stderr in the requested `format`.
+fn report_error(err: &Error, format: ErrorFormat) {
+    let mut causes = vec![];
+    let mut source = err.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    match format {
+        ErrorFormat::Text => {
+            eprintln!("ERROR: {}", err);
+            for cause in &causes {
+                eprintln!("  caused by: {}", cause);
+            }
+            if let Some(snippet) = error_location(err.as_ref())
+                .and_then(|location| location.snippet.as_ref())
+            {
+                eprintln!("  input: {:?}", snippet);
+            }
+        }
+        ErrorFormat::Json => {
+            let mut json = serde_json::json!({
+                "error": err.to_string(),
+                "causes": causes,
+            });
+            if let Some(location) = error_location(err.as_ref()) {
+                json["location"] = serde_json::json!(location);
+            }
+            eprintln!("{}", json);
+        }
+    }
+}
+
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("ERROR: {}", err);
-        let mut source = err.source();
-        while let Some(cause) = source {
-            eprintln!("  caused by: {}", cause);
-            source = cause.source();
+    // Parse our command-line arguments using `clap`.
+    let opt: Opt = Opt::parse();
+    if let Err(err) = run(&opt) {
+        if !opt.quiet && io::stderr().is_terminal() {
+            // Erase any progress bar.
+            eprint!("\r\x1b[K");
         }
+        report_error(&err, opt.error_format);
         process::exit(1);
     }
 }
@@ -0,0 +1,377 @@
+//! Normalizing numbers, booleans and dates to canonical formats.
+
+use chrono::{
+    format::{Item, Numeric, StrftimeItems},
+    DateTime, NaiveDate, NaiveDateTime,
+};
+use serde::Deserialize;
+
+use crate::errors::*;
+use crate::schema::ColumnType;
+
+/// Currency symbols which we remove from numbers.
+const CURRENCY_SYMBOLS: &[char] = &['$', '€', '£', '¥', '₹', '₩', '₽', '¢', '₺', '₪'];
+
+/// Values which we treat as true, if none are specified.
+const DEFAULT_TRUTHY: &[&str] = &["true", "t", "yes", "y", "1", "on"];
+
+/// Values which we treat as false, if none are specified.
+const DEFAULT_FALSY: &[&str] = &["false", "f", "no", "n", "0", "off"];
+
+/// What to do with a value which a normalizer cannot parse.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnInvalid {
+    /// Replace the value with an empty string.
+    Null,
+    /// Drop the row, and count it as a bad row.
+    #[default]
+    Drop,
+    /// Stop with an error.
+    Fail,
+}
+
+/// Options for normalizing numbers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NumberConfig {
+    /// The decimal separator, either "." (the default) or ",". The other
+    /// character is treated as a thousands separator.
+    #[serde(default = "default_decimal")]
+    decimal: char,
+}
+
+/// The default decimal separator.
+fn default_decimal() -> char {
+    '.'
+}
+
+/// Options for normalizing booleans.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BooleanConfig {
+    /// Values which mean true, compared without regard to case.
+    truthy: Option<Vec<String>>,
+    /// Values which mean false, compared without regard to case.
+    falsy: Option<Vec<String>>,
+}
+
+/// Options for normalizing dates and timestamps.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DateConfig {
+    /// `strftime`-style input formats, tried in order.
+    formats: Vec<String>,
+}
+
+/// Converts values to a canonical format.
+#[derive(Debug)]
+pub enum Normalizer {
+    /// Output numbers like `-1234.5`.
+    Number { decimal: char },
+    /// Output `true` or `false`.
+    Boolean {
+        truthy: Vec<String>,
+        falsy: Vec<String>,
+    },
+    /// Output ISO 8601 dates, or timestamps if `has_time` is true.
+    Date {
+        formats: Vec<String>,
+        /// Does any of our formats include a time? If so, we output dates
+        /// as timestamps at midnight, so that every value has the same type.
+        has_time: bool,
+    },
+}
+
+impl Normalizer {
+    /// Build a normalizer from the configuration for a rule, which may specify
+    /// at most one normalizer.
+    pub fn from_config(
+        number: Option<&NumberConfig>,
+        boolean: Option<&BooleanConfig>,
+        date: Option<&DateConfig>,
+    ) -> Result<Option<Normalizer>> {
+        match (number, boolean, date) {
+            (None, None, None) => Ok(None),
+            (Some(number), None, None) => {
+                if number.decimal != '.' && number.decimal != ',' {
+                    return Err(format_err!(
+                        "decimal separator must be \".\" or \",\", found {:?}",
+                        number.decimal
+                    ));
+                }
+                Ok(Some(Normalizer::Number {
+                    decimal: number.decimal,
+                }))
+            }
+            (None, Some(boolean), None) => {
+                let values =
+                    |values: &Option<Vec<String>>, default: &[&str]| match values {
+                        Some(values) => {
+                            values.iter().map(|v| v.to_lowercase()).collect()
+                        }
+                        None => default.iter().map(|v| (*v).to_owned()).collect(),
+                    };
+                Ok(Some(Normalizer::Boolean {
+                    truthy: values(&boolean.truthy, DEFAULT_TRUTHY),
+                    falsy: values(&boolean.falsy, DEFAULT_FALSY),
+                }))
+            }
+            (None, None, Some(date)) => {
+                if date.formats.is_empty() {
+                    return Err(format_err!("date rules must specify some formats"));
+                }
+                for format in &date.formats {
+                    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                        return Err(format_err!("invalid date format {:?}", format));
+                    }
+                }
+                let has_time = date.formats.iter().any(|format| {
+                    StrftimeItems::new(format).any(|item| {
+                        matches!(
+                            item,
+                            Item::Numeric(
+                                Numeric::Hour
+                                    | Numeric::Hour12
+                                    | Numeric::Minute
+                                    | Numeric::Second
+                                    | Numeric::Timestamp,
+                                _
+                            )
+                        )
+                    })
+                });
+                Ok(Some(Normalizer::Date {
+                    formats: date.formats.clone(),
+                    has_time,
+                }))
+            }
+            _ => Err(format_err!(
+                "a rule may only use one of \"number\", \"boolean\" and \"date\""
+            )),
+        }
+    }
+
+    /// The type of the values we output. Dates become timestamps if any of
+    /// our formats includes a time.
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            Normalizer::Number { .. } => ColumnType::Number,
+            Normalizer::Boolean { .. } => ColumnType::Boolean,
+            Normalizer::Date { has_time: true, .. } => ColumnType::Timestamp,
+            Normalizer::Date {
+                has_time: false, ..
+            } => ColumnType::Date,
+        }
+    }
+
+    /// Normalize `val`, or return an error message if we can't parse it.
+    pub fn normalize(&self, val: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        let parsed = std::str::from_utf8(val).ok().and_then(|s| {
+            let s = s.trim();
+            match self {
+                Normalizer::Number { decimal } => normalize_number(s, *decimal),
+                Normalizer::Boolean { truthy, falsy } => {
+                    let s = s.to_lowercase();
+                    if truthy.contains(&s) {
+                        Some("true".to_owned())
+                    } else if falsy.contains(&s) {
+                        Some("false".to_owned())
+                    } else {
+                        None
+                    }
+                }
+                Normalizer::Date { formats, has_time } => {
+                    normalize_date(s, formats, *has_time)
+                }
+            }
+        });
+        parsed.map(|s| s.into_bytes()).ok_or_else(|| {
+            let kind = match self {
+                Normalizer::Number { .. } => "number",
+                Normalizer::Boolean { .. } => "boolean",
+                Normalizer::Date { .. } => "date",
+            };
+            format!(
+                "cannot parse {:?} as a {}",
+                String::from_utf8_lossy(val),
+                kind
+            )
+        })
+    }
+}
+
+/// Convert `s` to a plain decimal number, removing currency symbols,
+/// thousands separators and whitespace at either end or next to a currency
+/// symbol. Negative numbers may be written using a sign or parentheses, but
+/// not both.
+fn normalize_number(s: &str, decimal: char) -> Option<String> {
+    let thousands = if decimal == ',' { '.' } else { ',' };
+    let s = s.trim();
+    let (in_parens, s) = match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        Some(inner) => (true, inner.trim()),
+        None => (false, s),
+    };
+    let mut negative = in_parens;
+    let chars = s.chars().collect::<Vec<char>>();
+    let mut digits = String::with_capacity(s.len());
+    let mut seen_sign = false;
+    let mut seen_decimal = false;
+    // The number of integer digits since the last thousands separator, if
+    // we've seen one. Groups must have exactly 3 digits, so that we don't
+    // misread "1,5" as 15.
+    let mut group_len: Option<usize> = None;
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            '0'..='9' => {
+                digits.push(c);
+                if let Some(len) = group_len.as_mut().filter(|_| !seen_decimal) {
+                    *len += 1;
+                }
+            }
+            _ if c == decimal && !seen_decimal => {
+                if group_len.is_some_and(|len| len != 3) {
+                    return None;
+                }
+                seen_decimal = true;
+                digits.push('.');
+            }
+            _ if c == thousands && !seen_decimal && !digits.is_empty() => {
+                let len = group_len.unwrap_or(digits.len());
+                if !(1..=3).contains(&len) || (group_len.is_some() && len != 3) {
+                    return None;
+                }
+                group_len = Some(0);
+            }
+            '-' | '+' if digits.is_empty() && !seen_sign && !in_parens => {
+                seen_sign = true;
+                negative = c == '-';
+            }
+            _ if CURRENCY_SYMBOLS.contains(&c) => {}
+            // Allow "$ 12" and "12 €", but not "12 34".
+            _ if c.is_whitespace() && next_to_currency_symbol(&chars, i) => {}
+            _ => return None,
+        }
+    }
+    if !seen_decimal && group_len.is_some_and(|len| len != 3) {
+        return None;
+    }
+
+    // Tidy up leading zeros and decimal points.
+    if !digits.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if digits.ends_with('.') {
+        digits.pop();
+    }
+    if digits.starts_with('.') {
+        digits.insert(0, '0');
+    }
+    while digits.len() > 1
+        && digits.starts_with('0')
+        && digits[1..].starts_with(|c: char| c.is_ascii_digit())
+    {
+        digits.remove(0);
+    }
+    if negative && digits.bytes().any(|b| b.is_ascii_digit() && b != b'0') {
+        digits.insert(0, '-');
+    }
+    Some(digits)
+}
+
+/// Is `chars[i]` next to a currency symbol?
+fn next_to_currency_symbol(chars: &[char], i: usize) -> bool {
+    let prev = i.checked_sub(1).map(|prev| chars[prev]);
+    let next = chars.get(i + 1).copied();
+    prev.into_iter()
+        .chain(next)
+        .any(|c| CURRENCY_SYMBOLS.contains(&c))
+}
+
+/// Parse `s` using the first matching format in `formats`, and convert it to
+/// an ISO 8601 timestamp if the format includes a time or `as_timestamp` is
+/// true, or an ISO 8601 date otherwise.
+fn normalize_date(s: &str, formats: &[String], as_timestamp: bool) -> Option<String> {
+    for format in formats {
+        if let Ok(timestamp) = DateTime::parse_from_str(s, format) {
+            return Some(timestamp.format("%Y-%m-%dT%H:%M:%S%.f%:z").to_string());
+        } else if let Ok(timestamp) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string());
+        } else if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            let date_format = if as_timestamp {
+                "%Y-%m-%dT00:00:00"
+            } else {
+                "%Y-%m-%d"
+            };
+            return Some(date.format(date_format).to_string());
+        }
+    }
+    None
+}
+
+#[test]
+fn normalizes_numbers() {
+    let number = |s| normalize_number(s, '.');
+    assert_eq!(number("1,234.50").as_deref(), Some("1234.50"));
+    assert_eq!(number("$12").as_deref(), Some("12"));
+    assert_eq!(number("-$0.5").as_deref(), Some("-0.5"));
+    assert_eq!(number("(1,000)").as_deref(), Some("-1000"));
+    assert_eq!(number("007.").as_deref(), Some("7"));
+    assert_eq!(number("-0").as_deref(), Some("0"));
+    assert_eq!(number("12 €").as_deref(), Some("12"));
+    assert_eq!(number("1.2.3"), None);
+    assert_eq!(number("12abc"), None);
+    assert_eq!(number("$"), None);
+    assert_eq!(normalize_number("1.234,5", ',').as_deref(), Some("1234.5"));
+    assert_eq!(number("1,234,567").as_deref(), Some("1234567"));
+    assert_eq!(number("1,5"), None);
+    assert_eq!(number("1,2345"), None);
+    assert_eq!(number("1234,567"), None);
+    assert_eq!(number("1,234,56.7"), None);
+    assert_eq!(normalize_number("1.5", ','), None);
+    assert_eq!(number(" $ 1,000 ").as_deref(), Some("1000"));
+    assert_eq!(number("( 5 )").as_deref(), Some("-5"));
+    assert_eq!(number("12 34"), None);
+    assert_eq!(number("1, 234"), None);
+    assert_eq!(number("(-5)"), None);
+    assert_eq!(number("(+5)"), None);
+}
+
+#[test]
+fn normalizes_booleans_and_dates() {
+    let boolean: BooleanConfig = toml::from_str(r#"truthy = ["Ja"]"#).unwrap();
+    let boolean = Normalizer::from_config(None, Some(&boolean), None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(boolean.normalize(b"JA").unwrap(), b"true");
+    assert_eq!(boolean.normalize(b" no ").unwrap(), b"false");
+    assert!(boolean.normalize(b"yes").is_err());
+
+    let date: DateConfig =
+        toml::from_str(r#"formats = ["%m/%d/%Y %H:%M", "%m/%d/%Y", "%Y-%m-%d"]"#)
+            .unwrap();
+    let date = Normalizer::from_config(None, None, Some(&date))
+        .unwrap()
+        .unwrap();
+    assert_eq!(date.column_type(), ColumnType::Timestamp);
+    assert_eq!(
+        date.normalize(b"03/04/2021").unwrap(),
+        b"2021-03-04T00:00:00"
+    );
+    assert_eq!(date.normalize(b"2021-3-4").unwrap(), b"2021-03-04T00:00:00");
+    assert_eq!(
+        date.normalize(b"03/04/2021 17:05").unwrap(),
+        b"2021-03-04T17:05:00"
+    );
+
+    let date: DateConfig = toml::from_str(r#"formats = ["%m/%d/%Y"]"#).unwrap();
+    let date = Normalizer::from_config(None, None, Some(&date))
+        .unwrap()
+        .unwrap();
+    assert_eq!(date.column_type(), ColumnType::Date);
+    assert_eq!(date.normalize(b"03/04/2021").unwrap(), b"2021-03-04");
+    assert_eq!(
+        date.normalize(b"yesterday").unwrap_err(),
+        "cannot parse \"yesterday\" as a date"
+    );
+}
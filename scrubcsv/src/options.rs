@@ -0,0 +1,552 @@
+//! Options for scrubbing a CSV file.
+
+use csv::ByteRecord;
+use regex::{bytes::Regex as BytesRegex, Regex};
+use std::thread;
+
+use crate::clean_column_names::{ColumnNameCleanerType, ColumnNameMap};
+use crate::dedupe::{DedupeKeep, DEFAULT_DEDUPE_MAX_KEYS};
+use crate::dialect::{InputDialect, OutputDialect};
+use crate::expect_columns::ExpectedColumns;
+use crate::limits::{FieldLimit, LongFieldAction};
+use crate::output_format::OutputFormat;
+use crate::schema::Schema;
+use crate::select::{ColumnSpec, Rename};
+use crate::skip::FooterFilter;
+use crate::transforms::TransformRules;
+use crate::unicode::UnicodeForm;
+use crate::util::{OutputQuoteStyle, OutputTerminator};
+
+/// Builds a `Scrubber`. Most options correspond to the `scrubcsv`
+/// command-line flag of the same name, and have the same defaults.
+///
+/// ```
+/// use scrubcsv::ScrubOptions;
+///
+/// let mut options = ScrubOptions::new();
+/// options.delimiter(b'\t').null(Some("(?i)NULL")).trim_whitespace(true);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ScrubOptions {
+    pub(crate) input_dialect: InputDialect,
+    pub(crate) sniff: bool,
+    pub(crate) sniff_bytes: usize,
+    pub(crate) has_headers: bool,
+    pub(crate) header: Option<ByteRecord>,
+    pub(crate) skip_lines: usize,
+    pub(crate) header_regex: Option<BytesRegex>,
+    pub(crate) footer_regex: Option<BytesRegex>,
+    pub(crate) skip_trailing: usize,
+    pub(crate) comment: Option<u8>,
+    pub(crate) null: Option<String>,
+    pub(crate) replace_newlines: bool,
+    pub(crate) trim_whitespace: bool,
+    pub(crate) unicode_whitespace: bool,
+    pub(crate) strip_control_chars: bool,
+    pub(crate) unicode_normalize: Option<UnicodeForm>,
+    pub(crate) transform_rules: Option<TransformRules>,
+    pub(crate) clean_column_names: Option<ColumnNameCleanerType>,
+    pub(crate) transliterate_column_names: bool,
+    pub(crate) column_name_map: Option<ColumnNameMap>,
+    pub(crate) update_column_name_map: bool,
+    pub(crate) expect_columns: Option<ExpectedColumns>,
+    pub(crate) reserve_column_names: Option<Regex>,
+    pub(crate) drop_row_if_null: Vec<String>,
+    pub(crate) select: Vec<ColumnSpec>,
+    pub(crate) exclude: Vec<ColumnSpec>,
+    pub(crate) rename: Vec<Rename>,
+    pub(crate) dedupe_on: Vec<ColumnSpec>,
+    pub(crate) dedupe: bool,
+    pub(crate) dedupe_keep: DedupeKeep,
+    pub(crate) dedupe_max_keys: usize,
+    pub(crate) max_field_bytes: Vec<FieldLimit>,
+    pub(crate) long_field_action: LongFieldAction,
+    pub(crate) max_row_bytes: Option<usize>,
+    pub(crate) limit: Option<u64>,
+    pub(crate) sample_rate: Option<f64>,
+    pub(crate) reservoir: Option<usize>,
+    pub(crate) seed: u64,
+    pub(crate) schema: Option<Schema>,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) output_dialect: OutputDialect,
+    pub(crate) threads: usize,
+    pub(crate) fast_path: bool,
+    pub(crate) profile_columns: bool,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> ScrubOptions {
+        ScrubOptions {
+            input_dialect: InputDialect {
+                delimiter: b',',
+                quote: Some(b'"'),
+                escape: None,
+            },
+            sniff: false,
+            sniff_bytes: 64 * 1024,
+            has_headers: true,
+            header: None,
+            skip_lines: 0,
+            header_regex: None,
+            footer_regex: None,
+            skip_trailing: 0,
+            comment: None,
+            null: None,
+            replace_newlines: false,
+            trim_whitespace: false,
+            unicode_whitespace: false,
+            strip_control_chars: false,
+            unicode_normalize: None,
+            transform_rules: None,
+            clean_column_names: None,
+            transliterate_column_names: false,
+            column_name_map: None,
+            update_column_name_map: false,
+            expect_columns: None,
+            reserve_column_names: None,
+            drop_row_if_null: vec![],
+            select: vec![],
+            exclude: vec![],
+            rename: vec![],
+            dedupe_on: vec![],
+            dedupe: false,
+            dedupe_keep: DedupeKeep::First,
+            dedupe_max_keys: DEFAULT_DEDUPE_MAX_KEYS,
+            max_field_bytes: vec![],
+            long_field_action: LongFieldAction::Truncate,
+            max_row_bytes: None,
+            limit: None,
+            sample_rate: None,
+            reservoir: None,
+            seed: 0,
+            schema: None,
+            output_format: OutputFormat::Csv,
+            output_dialect: OutputDialect {
+                delimiter: b',',
+                quote_style: OutputQuoteStyle::Necessary,
+                terminator: OutputTerminator::Byte(b'\n'),
+                escape: None,
+            },
+            threads: 1,
+            fast_path: true,
+            profile_columns: false,
+        }
+    }
+}
+
+impl ScrubOptions {
+    /// Create a new `ScrubOptions` with the default settings.
+    pub fn new() -> ScrubOptions {
+        ScrubOptions::default()
+    }
+
+    /// The character used to separate input fields.
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut ScrubOptions {
+        self.input_dialect.delimiter = delimiter;
+        self
+    }
+
+    /// The character used to quote input fields, or `None` to disable
+    /// quoting.
+    pub fn quote(&mut self, quote: Option<u8>) -> &mut ScrubOptions {
+        self.input_dialect.quote = quote;
+        self
+    }
+
+    /// The character used to escape special characters in input fields, or
+    /// `None` if quotes are escaped by doubling them. Escapes work in both
+    /// quoted and unquoted fields. This also decodes escape sequences like
+    /// `\n` and `\t`, and converts `\N` to NULL.
+    pub fn escape(&mut self, escape: Option<u8>) -> &mut ScrubOptions {
+        self.input_dialect.escape = escape;
+        self
+    }
+
+    /// Detect the input's delimiter, quote and escape characters from a
+    /// sample, ignoring any values we were given. Unless `has_headers` is
+    /// false, also detect whether the input has a header row. If it doesn't,
+    /// a `header` must be supplied.
+    pub fn sniff(&mut self, sniff: bool) -> &mut ScrubOptions {
+        self.sniff = sniff;
+        self
+    }
+
+    /// The number of bytes at the start of the input to use when sniffing.
+    pub fn sniff_bytes(&mut self, sniff_bytes: usize) -> &mut ScrubOptions {
+        self.sniff_bytes = sniff_bytes;
+        self
+    }
+
+    /// Does the input start with a header row? If not, column names must be
+    /// supplied using `header`.
+    pub fn has_headers(&mut self, has_headers: bool) -> &mut ScrubOptions {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Use these column names instead of the input's header row.
+    pub fn header(&mut self, header: Option<ByteRecord>) -> &mut ScrubOptions {
+        self.header = header;
+        self
+    }
+
+    /// Skip this many lines at the start of the input.
+    pub fn skip_lines(&mut self, skip_lines: usize) -> &mut ScrubOptions {
+        self.skip_lines = skip_lines;
+        self
+    }
+
+    /// Skip lines until one matches this regex, and use it as the header.
+    pub fn header_regex(
+        &mut self,
+        header_regex: Option<BytesRegex>,
+    ) -> &mut ScrubOptions {
+        self.header_regex = header_regex;
+        self
+    }
+
+    /// Ignore records matching this regex at the end of the input.
+    pub fn footer_regex(
+        &mut self,
+        footer_regex: Option<BytesRegex>,
+    ) -> &mut ScrubOptions {
+        self.footer_regex = footer_regex;
+        self
+    }
+
+    /// Ignore this many records at the end of the input.
+    pub fn skip_trailing(&mut self, skip_trailing: usize) -> &mut ScrubOptions {
+        self.skip_trailing = skip_trailing;
+        self
+    }
+
+    /// Ignore lines starting with this character.
+    pub fn comment(&mut self, comment: Option<u8>) -> &mut ScrubOptions {
+        self.comment = comment;
+        self
+    }
+
+    /// Convert values matching this regex to empty strings.
+    pub fn null(&mut self, null: Option<&str>) -> &mut ScrubOptions {
+        self.null = null.map(|null| null.to_owned());
+        self
+    }
+
+    /// Replace newlines in values with spaces.
+    pub fn replace_newlines(&mut self, replace_newlines: bool) -> &mut ScrubOptions {
+        self.replace_newlines = replace_newlines;
+        self
+    }
+
+    /// Remove whitespace at the beginning and end of each value.
+    pub fn trim_whitespace(&mut self, trim_whitespace: bool) -> &mut ScrubOptions {
+        self.trim_whitespace = trim_whitespace;
+        self
+    }
+
+    /// When trimming whitespace, also remove Unicode whitespace like
+    /// non-breaking spaces, zero-width spaces and byte order marks.
+    pub fn unicode_whitespace(
+        &mut self,
+        unicode_whitespace: bool,
+    ) -> &mut ScrubOptions {
+        self.unicode_whitespace = unicode_whitespace;
+        self
+    }
+
+    /// Remove control characters (except tabs and newlines), zero-width spaces
+    /// and byte order marks from each value.
+    pub fn strip_control_chars(
+        &mut self,
+        strip_control_chars: bool,
+    ) -> &mut ScrubOptions {
+        self.strip_control_chars = strip_control_chars;
+        self
+    }
+
+    /// Convert values which are valid UTF-8 to this Unicode normalization
+    /// form.
+    pub fn unicode_normalize(
+        &mut self,
+        unicode_normalize: Option<UnicodeForm>,
+    ) -> &mut ScrubOptions {
+        self.unicode_normalize = unicode_normalize;
+        self
+    }
+
+    /// Apply these per-column transforms.
+    pub fn transform_rules(
+        &mut self,
+        transform_rules: Option<TransformRules>,
+    ) -> &mut ScrubOptions {
+        self.transform_rules = transform_rules;
+        self
+    }
+
+    /// Clean column names using this type of cleaner.
+    pub fn clean_column_names(
+        &mut self,
+        cleaner_type: Option<ColumnNameCleanerType>,
+    ) -> &mut ScrubOptions {
+        self.clean_column_names = cleaner_type;
+        self
+    }
+
+    /// Convert column names to ASCII before cleaning them.
+    pub fn transliterate_column_names(
+        &mut self,
+        transliterate: bool,
+    ) -> &mut ScrubOptions {
+        self.transliterate_column_names = transliterate;
+        self
+    }
+
+    /// Look up column names in this map before cleaning them. Unless
+    /// `update_column_name_map` is set, every input column must be in the
+    /// map.
+    pub fn column_name_map(
+        &mut self,
+        map: Option<ColumnNameMap>,
+    ) -> &mut ScrubOptions {
+        self.column_name_map = map;
+        self
+    }
+
+    /// Add columns which aren't in our column name map to the map. Use
+    /// `Scrubber::column_name_map` to get the updated map.
+    pub fn update_column_name_map(&mut self, update: bool) -> &mut ScrubOptions {
+        self.update_column_name_map = update;
+        self
+    }
+
+    /// Check our (cleaned) input column names against these expected columns,
+    /// and possibly reorder them.
+    pub fn expect_columns(
+        &mut self,
+        expected: Option<ExpectedColumns>,
+    ) -> &mut ScrubOptions {
+        self.expect_columns = expected;
+        self
+    }
+
+    /// Fail if any output column name matches this regex.
+    pub fn reserve_column_names(
+        &mut self,
+        reserved: Option<Regex>,
+    ) -> &mut ScrubOptions {
+        self.reserve_column_names = reserved;
+        self
+    }
+
+    /// Drop any rows where one of these columns is empty.
+    pub fn drop_row_if_null(&mut self, cols: Vec<String>) -> &mut ScrubOptions {
+        self.drop_row_if_null = cols;
+        self
+    }
+
+    /// Only output these columns, in this order.
+    pub fn select(&mut self, cols: Vec<ColumnSpec>) -> &mut ScrubOptions {
+        self.select = cols;
+        self
+    }
+
+    /// Do not output these columns.
+    pub fn exclude(&mut self, cols: Vec<ColumnSpec>) -> &mut ScrubOptions {
+        self.exclude = cols;
+        self
+    }
+
+    /// Rename columns in the output.
+    pub fn rename(&mut self, renames: Vec<Rename>) -> &mut ScrubOptions {
+        self.rename = renames;
+        self
+    }
+
+    /// Drop rows with the same values in these columns as another row.
+    pub fn dedupe_on(&mut self, cols: Vec<ColumnSpec>) -> &mut ScrubOptions {
+        self.dedupe_on = cols;
+        self
+    }
+
+    /// Drop rows which are identical to another output row.
+    pub fn dedupe(&mut self, dedupe: bool) -> &mut ScrubOptions {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// Which copy of a duplicated row to keep.
+    pub fn dedupe_keep(&mut self, keep: DedupeKeep) -> &mut ScrubOptions {
+        self.dedupe_keep = keep;
+        self
+    }
+
+    /// The maximum number of distinct keys to track when deduplicating.
+    /// Scrubbing fails if we find more. Defaults to 10,000,000, which uses
+    /// about 400 MB of memory.
+    pub fn dedupe_max_keys(&mut self, max_keys: usize) -> &mut ScrubOptions {
+        self.dedupe_max_keys = max_keys;
+        self
+    }
+
+    /// Limit the size of output values, in bytes. Later limits override
+    /// earlier ones.
+    pub fn max_field_bytes(&mut self, limits: Vec<FieldLimit>) -> &mut ScrubOptions {
+        self.max_field_bytes = limits;
+        self
+    }
+
+    /// What to do with values which are longer than `max_field_bytes`.
+    pub fn long_field_action(&mut self, action: LongFieldAction) -> &mut ScrubOptions {
+        self.long_field_action = action;
+        self
+    }
+
+    /// Drop output rows longer than this many bytes, counting delimiters but
+    /// not quotes or record terminators.
+    pub fn max_row_bytes(
+        &mut self,
+        max_row_bytes: Option<usize>,
+    ) -> &mut ScrubOptions {
+        self.max_row_bytes = max_row_bytes;
+        self
+    }
+
+    /// Stop after outputting this many good rows.
+    pub fn limit(&mut self, limit: Option<u64>) -> &mut ScrubOptions {
+        self.limit = limit;
+        self
+    }
+
+    /// Check a random fraction of our input rows, from 0.0 to 1.0, and skip
+    /// the rest.
+    pub fn sample_rate(&mut self, sample_rate: Option<f64>) -> &mut ScrubOptions {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Output a uniform random sample of this many good rows from our entire
+    /// input, in their original order. This only affects `Scrubber::scrub`.
+    pub fn reservoir(&mut self, reservoir: Option<usize>) -> &mut ScrubOptions {
+        self.reservoir = reservoir;
+        self
+    }
+
+    /// The random seed used by `sample_rate` and `reservoir`. The same seed
+    /// always chooses the same rows.
+    pub fn seed(&mut self, seed: u64) -> &mut ScrubOptions {
+        self.seed = seed;
+        self
+    }
+
+    /// Declare the types of some output columns. Rows with values which
+    /// don't match their column's type are dropped.
+    pub fn schema(&mut self, schema: Option<Schema>) -> &mut ScrubOptions {
+        self.schema = schema;
+        self
+    }
+
+    /// The format of our output. JSON Lines and Parquet use our column types,
+    /// and write empty values as nulls.
+    pub fn output_format(&mut self, format: OutputFormat) -> &mut ScrubOptions {
+        self.output_format = format;
+        self
+    }
+
+    /// The character used to separate output fields.
+    pub fn output_delimiter(&mut self, delimiter: u8) -> &mut ScrubOptions {
+        self.output_dialect.delimiter = delimiter;
+        self
+    }
+
+    /// When to quote output fields.
+    pub fn output_quote_style(
+        &mut self,
+        style: OutputQuoteStyle,
+    ) -> &mut ScrubOptions {
+        self.output_dialect.quote_style = style;
+        self
+    }
+
+    /// How to terminate output records.
+    pub fn output_terminator(
+        &mut self,
+        terminator: OutputTerminator,
+    ) -> &mut ScrubOptions {
+        self.output_dialect.terminator = terminator;
+        self
+    }
+
+    /// Escape quotes in output fields using this character instead of
+    /// doubling them.
+    pub fn output_escape(&mut self, escape: Option<u8>) -> &mut ScrubOptions {
+        self.output_dialect.escape = escape;
+        self
+    }
+
+    /// The number of worker threads to use when writing output, or 0 to pick
+    /// a value based on the number of available CPUs.
+    pub fn threads(&mut self, threads: usize) -> &mut ScrubOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Use the zero-copy fast path when we don't need to clean any values.
+    pub fn fast_path(&mut self, fast_path: bool) -> &mut ScrubOptions {
+        self.fast_path = fast_path;
+        self
+    }
+
+    /// Collect statistics about the values in each column.
+    pub fn profile_columns(&mut self, profile_columns: bool) -> &mut ScrubOptions {
+        self.profile_columns = profile_columns;
+        self
+    }
+
+    /// Get the number of worker threads to use.
+    pub(crate) fn thread_count(&self) -> usize {
+        if self.threads == 0 {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            self.threads
+        }
+    }
+
+    /// Were we asked to remove duplicate rows?
+    pub(crate) fn dedupe_enabled(&self) -> bool {
+        self.dedupe || !self.dedupe_on.is_empty()
+    }
+
+    /// Do we need to limit the size of values or rows?
+    pub(crate) fn has_limits(&self) -> bool {
+        !self.max_field_bytes.is_empty() || self.max_row_bytes.is_some()
+    }
+
+    /// Are we only checking or outputting some of our rows?
+    pub(crate) fn is_sampled(&self) -> bool {
+        self.limit.is_some() || self.sample_rate.is_some() || self.reservoir.is_some()
+    }
+
+    /// Do we need to look for footer records at the end of our input?
+    pub(crate) fn has_footer(&self) -> bool {
+        self.footer_regex.is_some() || self.skip_trailing > 0
+    }
+
+    /// Create a filter to remove footer records, if we need one. `dialect`
+    /// is the dialect we actually parse our input with, after any sniffing.
+    pub(crate) fn footer_filter(
+        &self,
+        dialect: &InputDialect,
+    ) -> Option<FooterFilter> {
+        if self.has_footer() {
+            Some(FooterFilter::new(
+                self.footer_regex.clone(),
+                self.skip_trailing,
+                dialect.delimiter,
+            ))
+        } else {
+            None
+        }
+    }
+}
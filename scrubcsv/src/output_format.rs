@@ -0,0 +1,445 @@
+//! Writing our output as JSON Lines or Parquet, instead of CSV.
+//!
+//! Both formats use our output header as field names, and write empty values
+//! as nulls. Each column has a type, which is either declared in our schema,
+//! inferred from our transform rules, or `string`.
+
+use csv::ByteRecord;
+use parquet::{
+    basic::{
+        Compression as ParquetCompression, LogicalType, Repetition, TimeUnit,
+        Type as PhysicalType,
+    },
+    data_type::{
+        BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type,
+    },
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    format::MicroSeconds,
+    schema::types::Type,
+};
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::errors::*;
+use crate::record_processor::RecordSink;
+use crate::schema::{
+    parse_boolean, parse_date, parse_integer, parse_number, parse_timestamp_micros,
+    ColumnType,
+};
+use crate::BUFFER_SIZE;
+
+/// How many rows to put in each Parquet row group.
+const ROW_GROUP_ROWS: usize = 1024 * 1024;
+
+/// How many bytes of values to buffer before we write a Parquet row group.
+const ROW_GROUP_BYTES: usize = 128 * 1024 * 1024;
+
+/// The format of our output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// CSV, using our output dialect.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// An Apache Parquet file.
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format_err!(
+                "expected \"csv\", \"jsonl\" or \"parquet\", found {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// An error for a value which doesn't match its column type. We check values
+/// with declared types before we write them, so this should only happen if
+/// a transform rule outputs something unexpected.
+fn conversion_error(val: &[u8], column_type: ColumnType) -> Error {
+    format_err!(
+        "cannot convert {:?} to {}",
+        String::from_utf8_lossy(val),
+        column_type
+    )
+}
+
+/// Writes records as JSON objects, one per line.
+pub(crate) struct JsonLinesWriter<W: Write> {
+    output: io::BufWriter<W>,
+    /// The JSON-encoded key for each column, followed by a colon.
+    keys: Vec<Vec<u8>>,
+    /// The type of each column.
+    types: Vec<ColumnType>,
+    /// Scratch space for building a line.
+    line: Vec<u8>,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// Create a writer which uses the names in `headers` as keys.
+    pub(crate) fn new(
+        output: W,
+        headers: &ByteRecord,
+        types: &[ColumnType],
+    ) -> Result<JsonLinesWriter<W>> {
+        let mut keys = vec![];
+        for name in headers {
+            let mut key = serde_json::to_vec(&String::from_utf8_lossy(name))
+                .context("cannot encode column name")?;
+            key.push(b':');
+            keys.push(key);
+        }
+        Ok(JsonLinesWriter {
+            output: io::BufWriter::with_capacity(BUFFER_SIZE, output),
+            keys,
+            types: types.to_owned(),
+            line: vec![],
+        })
+    }
+
+    /// Flush any buffered output.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.output.flush().context("error writing records")
+    }
+
+    /// Append `val` to `self.line` as a JSON value of type `column_type`.
+    fn push_value(&mut self, val: &[u8], column_type: ColumnType) -> Result<()> {
+        let line = &mut self.line;
+        if val.is_empty() {
+            line.extend_from_slice(b"null");
+            return Ok(());
+        }
+        let written = match column_type {
+            ColumnType::String | ColumnType::Date | ColumnType::Timestamp
+                if column_type.is_valid(val) =>
+            {
+                serde_json::to_writer(&mut *line, &String::from_utf8_lossy(val))
+            }
+            ColumnType::Integer => match parse_integer(val) {
+                Some(n) => serde_json::to_writer(&mut *line, &n),
+                None => return Err(conversion_error(val, column_type)),
+            },
+            ColumnType::Number => match parse_number(val) {
+                Some(n) => serde_json::to_writer(&mut *line, &n),
+                None => return Err(conversion_error(val, column_type)),
+            },
+            ColumnType::Boolean => match parse_boolean(val) {
+                Some(b) => serde_json::to_writer(&mut *line, &b),
+                None => return Err(conversion_error(val, column_type)),
+            },
+            _ => return Err(conversion_error(val, column_type)),
+        };
+        written.context("cannot encode value")?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordSink for JsonLinesWriter<W> {
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        self.line.clear();
+        self.line.push(b'{');
+        for (idx, val) in record.into_iter().enumerate() {
+            if idx > 0 {
+                self.line.push(b',');
+            }
+            let key = self
+                .keys
+                .get(idx)
+                .ok_or_else(|| format_err!("record has more fields than header"))?;
+            self.line.extend_from_slice(key);
+            self.push_value(val.as_ref(), self.types[idx])?;
+        }
+        self.line.extend_from_slice(b"}\n");
+        self.output
+            .write_all(&self.line)
+            .context("cannot write record")?;
+        Ok(())
+    }
+}
+
+/// The buffered values of a Parquet column.
+enum ColumnValues {
+    ByteArray(Vec<ByteArray>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Double(Vec<f64>),
+    Boolean(Vec<bool>),
+}
+
+/// A Parquet column, and the values we haven't written yet.
+struct ParquetColumn {
+    column_type: ColumnType,
+    values: ColumnValues,
+    /// 1 for each value, and 0 for each null.
+    def_levels: Vec<i16>,
+}
+
+impl ParquetColumn {
+    /// Create an empty column of type `column_type`.
+    fn new(column_type: ColumnType) -> ParquetColumn {
+        let values = match column_type {
+            ColumnType::String => ColumnValues::ByteArray(vec![]),
+            ColumnType::Integer | ColumnType::Timestamp => ColumnValues::Int64(vec![]),
+            ColumnType::Number => ColumnValues::Double(vec![]),
+            ColumnType::Boolean => ColumnValues::Boolean(vec![]),
+            ColumnType::Date => ColumnValues::Int32(vec![]),
+        };
+        ParquetColumn {
+            column_type,
+            values,
+            def_levels: vec![],
+        }
+    }
+
+    /// Describe this column in our Parquet schema.
+    fn parquet_type(&self, name: &str) -> Result<Type> {
+        let (physical_type, logical_type) = match self.column_type {
+            ColumnType::String => {
+                (PhysicalType::BYTE_ARRAY, Some(LogicalType::String))
+            }
+            ColumnType::Integer => (PhysicalType::INT64, None),
+            ColumnType::Number => (PhysicalType::DOUBLE, None),
+            ColumnType::Boolean => (PhysicalType::BOOLEAN, None),
+            ColumnType::Date => (PhysicalType::INT32, Some(LogicalType::Date)),
+            ColumnType::Timestamp => (
+                PhysicalType::INT64,
+                Some(LogicalType::Timestamp {
+                    is_adjusted_to_u_t_c: true,
+                    unit: TimeUnit::MICROS(MicroSeconds {}),
+                }),
+            ),
+        };
+        Type::primitive_type_builder(name, physical_type)
+            .with_repetition(Repetition::OPTIONAL)
+            .with_logical_type(logical_type)
+            .build()
+            .with_context(|_| format!("cannot create Parquet column {:?}", name))
+    }
+
+    /// Add `val` to this column, returning the number of bytes we buffered.
+    fn push(&mut self, val: &[u8]) -> Result<usize> {
+        if val.is_empty() {
+            self.def_levels.push(0);
+            return Ok(0);
+        }
+        let column_type = self.column_type;
+        let error = || conversion_error(val, column_type);
+        let size = match &mut self.values {
+            ColumnValues::ByteArray(values) => {
+                values.push(ByteArray::from(val.to_owned()));
+                val.len()
+            }
+            ColumnValues::Int32(values) => {
+                let date = parse_date(val).ok_or_else(error)?;
+                values.push(date.to_epoch_days());
+                4
+            }
+            ColumnValues::Int64(values) => {
+                values.push(match column_type {
+                    ColumnType::Timestamp => {
+                        parse_timestamp_micros(val).ok_or_else(error)?
+                    }
+                    _ => parse_integer(val).ok_or_else(error)?,
+                });
+                8
+            }
+            ColumnValues::Double(values) => {
+                values.push(parse_number(val).ok_or_else(error)?);
+                8
+            }
+            ColumnValues::Boolean(values) => {
+                values.push(parse_boolean(val).ok_or_else(error)?);
+                1
+            }
+        };
+        self.def_levels.push(1);
+        Ok(size)
+    }
+}
+
+/// Writes records to a Parquet file.
+///
+/// The Parquet writer needs an output which is `Send`, so it writes to a
+/// buffer, which we copy to our real output after each row group.
+pub(crate) struct ParquetWriter<W: Write> {
+    output: W,
+    writer: SerializedFileWriter<Vec<u8>>,
+    columns: Vec<ParquetColumn>,
+    /// The number of rows we haven't written yet.
+    rows: usize,
+    /// The approximate size of the values we haven't written yet.
+    bytes: usize,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    /// Create a writer which uses the names in `headers` as column names.
+    pub(crate) fn new(
+        output: W,
+        headers: &ByteRecord,
+        types: &[ColumnType],
+    ) -> Result<ParquetWriter<W>> {
+        let columns = types
+            .iter()
+            .map(|&column_type| ParquetColumn::new(column_type))
+            .collect::<Vec<_>>();
+        let mut fields = vec![];
+        for (name, column) in headers.iter().zip(&columns) {
+            let name = String::from_utf8_lossy(name);
+            fields.push(Arc::new(column.parquet_type(&name)?));
+        }
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()
+            .context("cannot create Parquet schema")?;
+        let properties = WriterProperties::builder()
+            .set_compression(ParquetCompression::SNAPPY)
+            .build();
+        let writer =
+            SerializedFileWriter::new(vec![], Arc::new(schema), Arc::new(properties))
+                .context("cannot create Parquet writer")?;
+        Ok(ParquetWriter {
+            output,
+            writer,
+            columns,
+            rows: 0,
+            bytes: 0,
+        })
+    }
+
+    /// Write all our buffered rows as a row group.
+    fn write_row_group(&mut self) -> Result<()> {
+        let mut row_group = self
+            .writer
+            .next_row_group()
+            .context("cannot write Parquet row group")?;
+        for column in &mut self.columns {
+            let mut writer = row_group
+                .next_column()
+                .context("cannot write Parquet column")?
+                .ok_or_else(|| format_err!("Parquet schema has too few columns"))?;
+            let levels = Some(&column.def_levels[..]);
+            let written = match &mut column.values {
+                ColumnValues::ByteArray(values) => writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, levels, None),
+                ColumnValues::Int32(values) => writer
+                    .typed::<Int32Type>()
+                    .write_batch(values, levels, None),
+                ColumnValues::Int64(values) => writer
+                    .typed::<Int64Type>()
+                    .write_batch(values, levels, None),
+                ColumnValues::Double(values) => writer
+                    .typed::<DoubleType>()
+                    .write_batch(values, levels, None),
+                ColumnValues::Boolean(values) => {
+                    writer.typed::<BoolType>().write_batch(values, levels, None)
+                }
+            };
+            written.context("cannot write Parquet column")?;
+            writer.close().context("cannot write Parquet column")?;
+            match &mut column.values {
+                ColumnValues::ByteArray(values) => values.clear(),
+                ColumnValues::Int32(values) => values.clear(),
+                ColumnValues::Int64(values) => values.clear(),
+                ColumnValues::Double(values) => values.clear(),
+                ColumnValues::Boolean(values) => values.clear(),
+            }
+            column.def_levels.clear();
+        }
+        row_group
+            .close()
+            .context("cannot write Parquet row group")?;
+        self.rows = 0;
+        self.bytes = 0;
+
+        // Copy what we've written so far to our real output.
+        let buffer = self.writer.inner_mut();
+        self.output
+            .write_all(buffer)
+            .context("cannot write Parquet data")?;
+        buffer.clear();
+        Ok(())
+    }
+
+    /// Write any buffered rows and the Parquet footer, and return our output.
+    pub(crate) fn finish(mut self) -> Result<W> {
+        if self.rows > 0 {
+            self.write_row_group()?;
+        }
+        let buffer = self
+            .writer
+            .into_inner()
+            .context("cannot finish Parquet file")?;
+        self.output
+            .write_all(&buffer)
+            .context("cannot write Parquet data")?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> RecordSink for ParquetWriter<W> {
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let mut count = 0;
+        for (column, val) in self.columns.iter_mut().zip(record) {
+            self.bytes += column.push(val.as_ref())?;
+            count += 1;
+        }
+        if count != self.columns.len() {
+            return Err(format_err!("record has fewer fields than header"));
+        }
+        self.rows += 1;
+        if self.rows >= ROW_GROUP_ROWS || self.bytes >= ROW_GROUP_BYTES {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn writes_json_lines() {
+    let headers = ByteRecord::from(vec!["id", "name", "score", "ok", "day"]);
+    let types = [
+        ColumnType::Integer,
+        ColumnType::String,
+        ColumnType::Number,
+        ColumnType::Boolean,
+        ColumnType::Date,
+    ];
+    let mut output = vec![];
+    let mut wtr = JsonLinesWriter::new(&mut output, &headers, &types).unwrap();
+    wtr.write_record(vec!["1", "a \"b\"", "1.5", "true", "2021-03-04"])
+        .unwrap();
+    wtr.write_record(vec!["", "", "", "", ""]).unwrap();
+    assert!(wtr.write_record(vec!["x", "", "", "", ""]).is_err());
+    wtr.flush().unwrap();
+    drop(wtr);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        concat!(
+            r#"{"id":1,"name":"a \"b\"","score":1.5,"ok":true,"day":"2021-03-04"}"#,
+            "\n",
+            r#"{"id":null,"name":null,"score":null,"ok":null,"day":null}"#,
+            "\n",
+        )
+    );
+}
@@ -0,0 +1,291 @@
+//! Multi-threaded processing.
+//!
+//! The main thread splits the input into chunks of complete records, and
+//! sends them to a pool of workers. Each worker parses, cleans and writes its
+//! chunk to an in-memory buffer, and the main thread writes those buffers to
+//! our output in the original order.
+//!
+//! Finding record boundaries requires a real CSV parser, because newlines may
+//! appear inside quoted fields. We use `csv_core` configured with the same
+//! dialect as our workers, which is much cheaper than building records.
+
+use csv::ByteRecord;
+use csv_core::ReadRecordResult;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::dialect::{InputDialect, OutputDialect};
+use crate::errors::*;
+use crate::position::locate_read_error;
+use crate::progress::ProgressReporter;
+use crate::record_processor::RecordProcessor;
+use crate::stats::StatsCollector;
+
+/// Approximately how many bytes of input should we put in each chunk?
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A chunk of input containing only complete records.
+#[derive(Debug)]
+pub struct Chunk {
+    /// The raw bytes of our records.
+    pub data: Vec<u8>,
+    /// The number of records in `data`.
+    pub records: u64,
+}
+
+/// Splits CSV input into chunks at record boundaries.
+pub struct RecordSplitter<R: BufRead> {
+    input: R,
+    core: csv_core::Reader,
+    /// Scratch space for parsed field data, which we ignore.
+    output: Vec<u8>,
+    /// Scratch space for parsed field boundaries, which we ignore.
+    ends: Vec<usize>,
+    /// Total number of bytes read from `input`.
+    bytes_read: u64,
+    /// Total number of records read from `input`, including any header.
+    records_read: u64,
+    /// Have we reached the end of our input?
+    done: bool,
+}
+
+impl<R: BufRead> RecordSplitter<R> {
+    /// Create a new splitter for `input`.
+    pub fn new(input: R, dialect: &InputDialect) -> RecordSplitter<R> {
+        RecordSplitter {
+            input,
+            core: dialect.csv_core_reader(),
+            output: vec![0; 64 * 1024],
+            ends: vec![0; 1024],
+            bytes_read: 0,
+            records_read: 0,
+            done: false,
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Read the next chunk, stopping after `max_records` records or once we
+    /// have at least `target_size` bytes. Returns `None` at end of input.
+    pub fn next_chunk(
+        &mut self,
+        target_size: usize,
+        max_records: u64,
+    ) -> Result<Option<Chunk>> {
+        let mut data = Vec::with_capacity(target_size.min(2 * CHUNK_SIZE));
+        let mut records = 0;
+        while !self.done {
+            let record = self.records_read + 1;
+            let input = self
+                .input
+                .fill_buf()
+                .map_err(|err| locate_read_error(err, record))
+                .context("cannot read input")?;
+            let (result, nin, _, _) =
+                self.core
+                    .read_record(input, &mut self.output, &mut self.ends);
+            data.extend_from_slice(&input[..nin]);
+            self.input.consume(nin);
+            self.bytes_read += nin as u64;
+            match result {
+                // Keep going. We don't care about the parsed output.
+                ReadRecordResult::InputEmpty
+                | ReadRecordResult::OutputFull
+                | ReadRecordResult::OutputEndsFull => {}
+                ReadRecordResult::Record => {
+                    records += 1;
+                    self.records_read += 1;
+                    if records >= max_records || data.len() >= target_size {
+                        break;
+                    }
+                }
+                ReadRecordResult::End => self.done = true,
+            }
+        }
+        if records == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Chunk { data, records }))
+        }
+    }
+}
+
+/// The result of processing a chunk.
+struct ChunkOutput {
+    /// The CSV data to write.
+    data: Vec<u8>,
+    /// Statistics for this chunk.
+    stats: StatsCollector,
+    /// The row number of the last record in this chunk.
+    last_row: u64,
+}
+
+/// Parse, clean and write all the records in `chunk`, where `first_row` is
+/// the row number of the first record. `stats` is an empty `StatsCollector`
+/// to fill in.
+fn process_chunk(
+    chunk: &Chunk,
+    first_row: u64,
+    processor: &RecordProcessor,
+    mut stats: StatsCollector,
+    input_dialect: &InputDialect,
+    output_dialect: &OutputDialect,
+) -> Result<ChunkOutput> {
+    let mut rdr = input_dialect.csv_reader(&chunk.data[..], false);
+    let mut wtr = output_dialect.csv_writer(Vec::with_capacity(chunk.data.len()));
+    let mut record = ByteRecord::new();
+    let mut row = first_row;
+    while rdr
+        .read_byte_record(&mut record)
+        .context("cannot read record")?
+    {
+        processor.process(row, &record, None, &mut stats, &mut wtr)?;
+        row += 1;
+    }
+    let data = wtr
+        .into_inner()
+        .map_err(|err| format_err!("cannot write records: {}", err.error()))?;
+    Ok(ChunkOutput {
+        data,
+        stats,
+        last_row: row - 1,
+    })
+}
+
+/// Process all the remaining records from `splitter` using `threads` worker
+/// threads, writing them to `output` in order. `first_row` is the row number of
+/// the first record. Returns the number of rows, merges our statistics into
+/// `stats`, and reports our progress to `progress`.
+#[allow(clippy::too_many_arguments)]
+pub fn process_parallel<R: BufRead, W: Write>(
+    splitter: &mut RecordSplitter<R>,
+    first_row: u64,
+    threads: usize,
+    processor: &RecordProcessor,
+    stats: &mut StatsCollector,
+    input_dialect: &InputDialect,
+    output_dialect: &OutputDialect,
+    output: &mut W,
+    mut progress: Option<&mut ProgressReporter<'_>>,
+) -> Result<u64> {
+    let empty_stats =
+        StatsCollector::new(stats.columns.as_ref().map(|cols| cols.len()));
+    thread::scope(|scope| -> Result<u64> {
+        // Set up our workers. The work queue is bounded, which prevents us
+        // from reading too far ahead of our workers.
+        let (work_tx, work_rx) = mpsc::sync_channel::<(u64, u64, Chunk)>(threads * 2);
+        let work_rx = Arc::new(Mutex::new(work_rx));
+        let (result_tx, result_rx) = mpsc::channel::<(u64, Result<ChunkOutput>)>();
+        for _ in 0..threads {
+            let work_rx = work_rx.clone();
+            let result_tx = result_tx.clone();
+            let empty_stats = &empty_stats;
+            scope.spawn(move || loop {
+                let next = work_rx.lock().expect("lock poisoned").recv();
+                let (seq, first_row, chunk) = match next {
+                    Ok(work) => work,
+                    Err(_) => break,
+                };
+                let output = process_chunk(
+                    &chunk,
+                    first_row,
+                    processor,
+                    empty_stats.clone(),
+                    input_dialect,
+                    output_dialect,
+                );
+                if result_tx.send((seq, output)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+
+        // Write out any results we have, in order.
+        let mut pending = BTreeMap::new();
+        let mut next_to_write = 0;
+        let mut write_ready =
+            |pending: &mut BTreeMap<u64, ChunkOutput>| -> Result<()> {
+                while let Some(chunk_output) = pending.remove(&next_to_write) {
+                    output
+                        .write_all(&chunk_output.data)
+                        .context("cannot write record")?;
+                    stats.merge(&chunk_output.stats);
+                    if let Some(progress) = progress.as_deref_mut() {
+                        progress.check(chunk_output.last_row, stats);
+                    }
+                    next_to_write += 1;
+                }
+                Ok(())
+            };
+
+        // Read our input and hand it out to our workers.
+        let mut seq = 0;
+        let mut row = first_row;
+        while let Some(chunk) = splitter.next_chunk(CHUNK_SIZE, u64::MAX)? {
+            let records = chunk.records;
+            if work_tx.send((seq, row, chunk)).is_err() {
+                return Err(format_err!("worker threads exited unexpectedly"));
+            }
+            seq += 1;
+            row += records;
+
+            // Collect any finished work, blocking if we're getting too far
+            // ahead of our output.
+            while let Ok((done_seq, output)) = result_rx.try_recv() {
+                pending.insert(done_seq, output?);
+            }
+            while pending.len() > threads * 4 {
+                let (done_seq, output) = result_rx
+                    .recv()
+                    .map_err(|_| format_err!("worker threads exited unexpectedly"))?;
+                pending.insert(done_seq, output?);
+            }
+            write_ready(&mut pending)?;
+        }
+
+        // Tell our workers to exit once the queue is empty, and collect the
+        // remaining results.
+        drop(work_tx);
+        for (done_seq, output) in result_rx {
+            pending.insert(done_seq, output?);
+            write_ready(&mut pending)?;
+        }
+        if next_to_write != seq {
+            return Err(format_err!("worker threads exited unexpectedly"));
+        }
+        Ok(row - first_row)
+    })
+}
+
+#[test]
+fn splits_chunks_at_record_boundaries() {
+    let dialect = InputDialect {
+        delimiter: b',',
+        quote: Some(b'"'),
+        escape: None,
+    };
+    let input = b"a,b\n\"1\n2\",3\r\n4,\"5,\"\"6\"\n7,8";
+    let mut splitter = RecordSplitter::new(&input[..], &dialect);
+    let mut chunks = vec![];
+    while let Some(chunk) = splitter.next_chunk(1, 2).unwrap() {
+        chunks.push((String::from_utf8(chunk.data).unwrap(), chunk.records));
+    }
+    assert_eq!(
+        chunks,
+        vec![
+            ("a,b\n".to_owned(), 1),
+            ("\"1\n2\",3\r".to_owned(), 1),
+            ("\n4,\"5,\"\"6\"\n".to_owned(), 1),
+            ("7,8".to_owned(), 1),
+        ]
+    );
+    assert_eq!(splitter.bytes_read(), input.len() as u64);
+}
@@ -0,0 +1,184 @@
+//! Tracking our position in the input, so that we can report where read
+//! errors occur.
+//!
+//! Our input is read ahead of our CSV parser in large blocks, so the position
+//! of an I/O error in the raw input only tells us roughly where the current
+//! record is. We report it as approximate, and add the number of the record
+//! being parsed. When `csv` reports a position of its own, we use that
+//! instead.
+
+use std::io::{self, Read};
+
+use crate::compression::ByteCount;
+use crate::errors::*;
+
+/// How many bytes of recent input to include in error messages.
+const SNIPPET_LEN: usize = 64;
+
+/// Adds the current line, byte offset and most recent input to any errors
+/// returned by `input`.
+pub struct TrackingReader<R: Read> {
+    input: R,
+    /// The number of bytes read, shared with anybody who wants to report our
+    /// progress.
+    bytes: ByteCount,
+    /// The number of newlines read.
+    newlines: u64,
+    /// The last `SNIPPET_LEN` bytes read.
+    recent: Vec<u8>,
+}
+
+impl<R: Read> TrackingReader<R> {
+    /// Track our position in `input`.
+    pub fn new(input: R) -> TrackingReader<R> {
+        TrackingReader {
+            input,
+            bytes: ByteCount::default(),
+            newlines: 0,
+            recent: Vec::with_capacity(2 * SNIPPET_LEN),
+        }
+    }
+
+    /// A shared count of the bytes we've read.
+    pub fn byte_count(&self) -> ByteCount {
+        self.bytes.clone()
+    }
+
+    /// Where are we in our input? This is only approximate, because our
+    /// readers buffer input well ahead of the record they're parsing.
+    fn location(&self) -> Location {
+        Location {
+            line: Some(self.newlines + 1),
+            byte: Some(self.bytes.get()),
+            snippet: Some(String::from_utf8_lossy(&self.recent).into_owned()),
+            approximate: true,
+            ..Location::default()
+        }
+    }
+}
+
+impl<R: Read> Read for TrackingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.input.read(buf) {
+            Ok(count) => {
+                let data = &buf[..count];
+                self.bytes.add(count as u64);
+                self.newlines += data.iter().filter(|&&b| b == b'\n').count() as u64;
+                if count >= SNIPPET_LEN {
+                    self.recent.clear();
+                    self.recent.extend_from_slice(&data[count - SNIPPET_LEN..]);
+                } else {
+                    self.recent.extend_from_slice(data);
+                    let excess = self.recent.len().saturating_sub(SNIPPET_LEN);
+                    self.recent.drain(..excess);
+                }
+                Ok(count)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Err(err),
+            Err(err) => Err(io::Error::new(
+                err.kind(),
+                LocatedError::new(Box::new(err), self.location()),
+            )),
+        }
+    }
+}
+
+/// Add a location to `err`, which occurred while reading the 1-based
+/// `record`. We keep any approximate location added by `TrackingReader`.
+pub fn locate_read_error(err: io::Error, record: u64) -> LocatedError {
+    match err.downcast::<LocatedError>() {
+        Ok(mut located) => {
+            located.location_mut().record = Some(record);
+            located
+        }
+        Err(err) => {
+            let location = Location {
+                record: Some(record),
+                ..Location::default()
+            };
+            LocatedError::new(Box::new(err), location)
+        }
+    }
+}
+
+/// Add a location to `err`, which occurred while `csv` was reading the record
+/// at `pos`. If `err` has a position of its own, we use that.
+pub fn locate_csv_error(err: csv::Error, pos: &csv::Position) -> LocatedError {
+    if let Some(pos) = err.position() {
+        let location = Location {
+            record: Some(pos.record() + 1),
+            line: Some(pos.line()),
+            byte: Some(pos.byte()),
+            ..Location::default()
+        };
+        return LocatedError::new(Box::new(err), location);
+    }
+    let record = pos.record() + 1;
+    if !err.is_io_error() {
+        let location = Location {
+            record: Some(record),
+            ..Location::default()
+        };
+        return LocatedError::new(Box::new(err), location);
+    }
+    match err.into_kind() {
+        csv::ErrorKind::Io(err) => locate_read_error(err, record),
+        _ => unreachable!("expected an I/O error"),
+    }
+}
+
+#[test]
+fn locates_read_errors() {
+    let input = io::Cursor::new(b"a,b\n1,2\n".to_vec()).chain(FailingReader);
+    let mut rdr = TrackingReader::new(input);
+    let err = rdr.read_to_end(&mut vec![]).unwrap_err();
+    let location = error_location(&err).unwrap();
+    assert_eq!(location.line, Some(3));
+    assert_eq!(location.byte, Some(8));
+    assert_eq!(location.snippet.as_deref(), Some("a,b\n1,2\n"));
+    assert!(location.approximate);
+    assert_eq!(
+        err.to_string(),
+        "disk on fire (approximately line 3, byte 8)"
+    );
+}
+
+#[test]
+fn locates_csv_errors() {
+    // I/O errors get the number of the record being parsed, and keep their
+    // approximate position.
+    let input = io::Cursor::new(b"a,b\n1,2\n3,".to_vec()).chain(FailingReader);
+    let mut rdr = csv::Reader::from_reader(TrackingReader::new(input));
+    let mut record = csv::ByteRecord::new();
+    assert!(rdr.read_byte_record(&mut record).unwrap());
+    let err = rdr.read_byte_record(&mut record).unwrap_err();
+    let err = locate_csv_error(err, rdr.position());
+    assert_eq!(err.location().record, Some(3));
+    assert_eq!(err.location().line, Some(3));
+    assert!(err.location().approximate);
+    assert_eq!(
+        err.to_string(),
+        "disk on fire (record 3, approximately line 3, byte 10)",
+    );
+
+    // Errors with a position of their own use it.
+    let mut rdr = csv::Reader::from_reader(&b"a,b\n1,2\n3\n"[..]);
+    assert!(rdr.read_byte_record(&mut record).unwrap());
+    let err = rdr.read_byte_record(&mut record).unwrap_err();
+    let err = locate_csv_error(err, rdr.position());
+    assert_eq!(err.location().record, Some(3));
+    assert_eq!(err.location().line, Some(3));
+    assert_eq!(err.location().byte, Some(8));
+    assert!(!err.location().approximate);
+}
+
+/// A reader which always fails.
+#[cfg(test)]
+struct FailingReader;
+
+#[cfg(test)]
+impl Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk on fire"))
+    }
+}
@@ -0,0 +1,164 @@
+//! Reporting our progress through long-running scrubs.
+
+use std::time::{Duration, Instant};
+
+use crate::compression::ByteCount;
+use crate::stats::StatsCollector;
+
+/// How many rows to process between checks of the clock.
+const ROWS_PER_CHECK: u32 = 1024;
+
+/// A snapshot of our progress, passed to the callback registered with
+/// `Scrubber::on_progress`.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    /// The number of rows we've read so far, including the header.
+    pub rows: u64,
+    /// The number of rows we've rejected as bad so far.
+    pub bad_rows: u64,
+    /// The number of (uncompressed) bytes we've read so far.
+    pub bytes_processed: u64,
+    /// The number of compressed bytes we've read so far, if our input is
+    /// compressed.
+    pub compressed_bytes_processed: Option<u64>,
+    /// The size of our input file, if known. For compressed input, this is
+    /// the compressed size.
+    pub input_size: Option<u64>,
+    /// How long we've been running.
+    pub elapsed_seconds: f64,
+}
+
+impl Progress {
+    /// How many (uncompressed) bytes we're reading per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        if self.elapsed_seconds > 0.0 {
+            self.bytes_processed as f64 / self.elapsed_seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// How much of our input we've read, from 0.0 to 1.0, if we know the size
+    /// of our input.
+    pub fn fraction_done(&self) -> Option<f64> {
+        fraction_read(
+            self.bytes_processed,
+            self.compressed_bytes_processed,
+            self.input_size,
+        )
+    }
+
+    /// Our estimate of the number of seconds until we finish, if we know the
+    /// size of our input.
+    pub fn eta_seconds(&self) -> Option<f64> {
+        let fraction = self.fraction_done().filter(|&f| f > 0.0)?;
+        Some(self.elapsed_seconds * (1.0 - fraction) / fraction)
+    }
+}
+
+/// How much of an input of `input_size` bytes we've read, from 0.0 to 1.0,
+/// given the number of bytes we've read. For compressed input, we compare
+/// `compressed_bytes_processed` to `input_size`.
+pub(crate) fn fraction_read(
+    bytes_processed: u64,
+    compressed_bytes_processed: Option<u64>,
+    input_size: Option<u64>,
+) -> Option<f64> {
+    let size = input_size.filter(|&size| size > 0)?;
+    let read = compressed_bytes_processed.unwrap_or(bytes_processed);
+    Some((read as f64 / size as f64).min(1.0))
+}
+
+/// Calls a progress callback at regular intervals.
+pub(crate) struct ProgressReporter<'a> {
+    /// The function to call with our progress.
+    callback: Box<dyn FnMut(&Progress) + Send + 'a>,
+    /// How often to call `callback`.
+    interval: Duration,
+    /// When we started.
+    start_time: Instant,
+    /// When we should next call `callback`.
+    next_report: Instant,
+    /// How many more rows to process before we check the clock again.
+    rows_until_check: u32,
+    /// The number of (uncompressed) bytes we've read.
+    bytes: ByteCount,
+    /// The number of compressed bytes we've read, if our input is compressed.
+    compressed_bytes: Option<ByteCount>,
+    /// The size of our input file, if known.
+    input_size: Option<u64>,
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Create a reporter which calls `callback` every `interval`.
+    pub(crate) fn new(
+        callback: Box<dyn FnMut(&Progress) + Send + 'a>,
+        interval: Duration,
+        start_time: Instant,
+        bytes: ByteCount,
+        compressed_bytes: Option<ByteCount>,
+        input_size: Option<u64>,
+    ) -> ProgressReporter<'a> {
+        ProgressReporter {
+            callback,
+            interval,
+            start_time,
+            next_report: start_time + interval,
+            rows_until_check: ROWS_PER_CHECK,
+            bytes,
+            compressed_bytes,
+            input_size,
+        }
+    }
+
+    /// Call this once per row. We only check the clock every so often, so
+    /// this is cheap enough for our fastest loops.
+    #[inline]
+    pub(crate) fn tick(&mut self, rows: u64, stats: &StatsCollector) {
+        self.rows_until_check -= 1;
+        if self.rows_until_check == 0 {
+            self.rows_until_check = ROWS_PER_CHECK;
+            self.check(rows, stats);
+        }
+    }
+
+    /// Call our callback if it's time.
+    pub(crate) fn check(&mut self, rows: u64, stats: &StatsCollector) {
+        let now = Instant::now();
+        if now < self.next_report {
+            return;
+        }
+        self.next_report = now + self.interval;
+        (self.callback)(&Progress {
+            rows,
+            bad_rows: stats.bad_rows(),
+            bytes_processed: self.bytes.get(),
+            compressed_bytes_processed: self
+                .compressed_bytes
+                .as_ref()
+                .map(|count| count.get()),
+            input_size: self.input_size,
+            elapsed_seconds: now.duration_since(self.start_time).as_secs_f64(),
+        });
+    }
+}
+
+#[test]
+fn estimates_time_remaining() {
+    let mut progress = Progress {
+        rows: 100,
+        bad_rows: 0,
+        bytes_processed: 400,
+        compressed_bytes_processed: Some(25),
+        input_size: Some(100),
+        elapsed_seconds: 10.0,
+    };
+    assert_eq!(progress.bytes_per_second(), 40.0);
+    assert_eq!(progress.fraction_done(), Some(0.25));
+    assert_eq!(progress.eta_seconds(), Some(30.0));
+
+    progress.compressed_bytes_processed = None;
+    assert_eq!(progress.fraction_done(), Some(1.0));
+    progress.input_size = None;
+    assert_eq!(progress.eta_seconds(), None);
+}
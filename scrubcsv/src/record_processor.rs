@@ -0,0 +1,251 @@
+//! Validating, cleaning and writing individual records.
+
+use csv::ByteRecord;
+use log::debug;
+use std::{borrow::Cow, io::Write};
+
+use crate::clean_values::ValueCleaner;
+use crate::dedupe::Deduper;
+use crate::errors::*;
+use crate::limits::Limits;
+use crate::schema::ColumnType;
+use crate::stats::StatsCollector;
+
+/// Somewhere to put the records we keep.
+pub trait RecordSink {
+    /// Write a single record.
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>;
+}
+
+impl<W: Write> RecordSink for csv::Writer<W> {
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        csv::Writer::write_record(self, record).context("cannot write record")
+    }
+}
+
+impl RecordSink for ByteRecord {
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        self.clear();
+        for field in record {
+            self.push_field(field.as_ref());
+        }
+        Ok(())
+    }
+}
+
+/// Everything we need to know to process a single record. This is shared
+/// between threads, so it must not contain any mutable state.
+#[derive(Debug)]
+pub struct RecordProcessor {
+    /// The number of columns in our input.
+    pub expected_cols: usize,
+    /// The input columns to output, in order, or `None` to output all of them.
+    /// Columns past the end of our input are output as empty values.
+    pub selected_cols: Option<Vec<usize>>,
+    /// For each input column, is it required to contain a value?
+    pub required_cols: Vec<bool>,
+    /// Cleanups to apply to each value.
+    pub value_cleaner: ValueCleaner,
+    /// Limits on the size of output values and rows.
+    pub limits: Limits,
+    /// For each input column, the type declared in our schema, if any.
+    pub schema_types: Vec<Option<ColumnType>>,
+}
+
+impl RecordProcessor {
+    /// Can we use the fast path and copy the data through unchanged? Or do we
+    /// need to clean up emebedded newlines in our data? (These break
+    /// BigQuery, for example.)
+    fn use_fast_path(&self) -> bool {
+        self.value_cleaner.is_noop()
+            && !self.required_cols.contains(&true)
+            && self.limits.is_empty()
+            && !self.checks_types()
+    }
+
+    /// Do we need to check the types of any values?
+    fn checks_types(&self) -> bool {
+        self.schema_types.iter().any(|t| t.is_some())
+    }
+
+    /// Process `record`, which is row number `row` of our input, and write it
+    /// to `wtr` if it's good and not a duplicate according to `deduper`.
+    /// Records rejected rows and column statistics in `stats`. Returns `true`
+    /// if we wrote the record.
+    pub fn process<S: RecordSink>(
+        &self,
+        row: u64,
+        record: &ByteRecord,
+        deduper: Option<&mut Deduper>,
+        stats: &mut StatsCollector,
+        wtr: &mut S,
+    ) -> Result<bool> {
+        // Check if we have the right number of columns in this row.
+        if record.len() != self.expected_cols {
+            debug!(
+                "row {}: expected {} columns, found {}",
+                row,
+                self.expected_cols,
+                record.len(),
+            );
+            stats.record_wrong_column_count(record.len());
+            return Ok(false);
+        }
+
+        // Decide how to handle this row.
+        let value_cleaner = &self.value_cleaner;
+        let simple = deduper.is_none() && !stats.profiles_columns();
+        if self.use_fast_path() && simple {
+            // We don't need to do anything fancy, so just pass it through.
+            // I'm not sure how much this actually buys us in current Rust
+            // versions, but it seemed like a good idea at the time.
+            if let Some(cols) = &self.selected_cols {
+                wtr.write_record(
+                    cols.iter().map(|&idx| record.get(idx).unwrap_or_default()),
+                )
+            } else {
+                wtr.write_record(record)
+            }?;
+        } else if !self.required_cols.contains(&true)
+            && !value_cleaner.can_reject()
+            && self.limits.is_empty()
+            && !self.checks_types()
+            && simple
+        {
+            // Still somewhat fast!
+            if let Some(cols) = &self.selected_cols {
+                wtr.write_record(cols.iter().map(|&idx| match record.get(idx) {
+                    Some(val) => value_cleaner.clean(idx, val),
+                    None => Cow::Borrowed(&b""[..]),
+                }))
+            } else {
+                wtr.write_record(
+                    record
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, val)| value_cleaner.clean(idx, val)),
+                )
+            }?;
+        } else {
+            // We need to rebuild the record, check for invalid values, null
+            // columns and duplicates, and only output the record if
+            // everything's OK.
+            let mut cleanups = Vec::with_capacity(record.len());
+            let mut cleaned = Vec::with_capacity(record.len());
+            for (idx, val) in record.iter().enumerate() {
+                match value_cleaner.clean_tracked(idx, val) {
+                    Ok((val, val_cleanups)) => {
+                        cleaned.push(val);
+                        cleanups.push(val_cleanups);
+                    }
+                    Err(err) if err.fail => {
+                        let location = Location {
+                            record: Some(row),
+                            column: Some(idx + 1),
+                            snippet: Some(String::from_utf8_lossy(val).into_owned()),
+                            ..Location::default()
+                        };
+                        return Err(Box::new(LocatedError::new(
+                            err.message.into(),
+                            location,
+                        )));
+                    }
+                    Err(err) => {
+                        debug!("row {}, column {}: {}", row, idx + 1, err.message);
+                        stats.rejections.invalid_value += 1;
+                        return Ok(false);
+                    }
+                }
+            }
+            // Only limit the values we actually output.
+            let fields_fit = match &self.selected_cols {
+                Some(cols) => cols.iter().all(|&idx| {
+                    match (cleaned.get_mut(idx), cleanups.get_mut(idx)) {
+                        (Some(val), Some(val_cleanups)) => {
+                            self.limits.limit_field(row, idx, val, val_cleanups)
+                        }
+                        _ => true,
+                    }
+                }),
+                None => cleaned.iter_mut().zip(cleanups.iter_mut()).enumerate().all(
+                    |(idx, (val, val_cleanups))| {
+                        self.limits.limit_field(row, idx, val, val_cleanups)
+                    },
+                ),
+            };
+            if !fields_fit {
+                stats.rejections.field_too_long += 1;
+                return Ok(false);
+            }
+            for (idx, (val, column_type)) in
+                cleaned.iter().zip(self.schema_types.iter()).enumerate()
+            {
+                if let Some(column_type) = column_type {
+                    if !column_type.is_valid(val) {
+                        debug!(
+                            "row {}, column {}: {:?} is not a valid {}",
+                            row,
+                            idx + 1,
+                            String::from_utf8_lossy(val),
+                            column_type,
+                        );
+                        stats.rejections.invalid_value += 1;
+                        return Ok(false);
+                    }
+                }
+            }
+            for (value, &is_required_col) in
+                cleaned.iter().zip(self.required_cols.iter())
+            {
+                // If the column is NULL but shouldn't be, bail on this row.
+                if is_required_col && value.is_empty() {
+                    debug!("row {}: required column is empty", row);
+                    stats.rejections.missing_required_value += 1;
+                    return Ok(false);
+                }
+            }
+            let row_fits = match &self.selected_cols {
+                Some(cols) => self.limits.row_fits(
+                    row,
+                    cols.iter()
+                        .map(|&idx| cleaned.get(idx).map_or(&b""[..], |val| val)),
+                ),
+                None => self
+                    .limits
+                    .row_fits(row, cleaned.iter().map(|val| &val[..])),
+            };
+            if !row_fits {
+                stats.rejections.row_too_long += 1;
+                return Ok(false);
+            }
+            if let Some(deduper) = deduper {
+                if !deduper.keep_row(row, &cleaned)? {
+                    debug!("row {}: duplicate key", row);
+                    stats.rejections.duplicate += 1;
+                    return Ok(false);
+                }
+            }
+            if let Some(cols) = &self.selected_cols {
+                wtr.write_record(
+                    cols.iter()
+                        .map(|&idx| cleaned.get(idx).map_or(&b""[..], |val| val)),
+                )
+            } else {
+                wtr.write_record(&cleaned)
+            }?;
+            stats.record_row(&cleaned, &cleanups);
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,189 @@
+//! Sampling our input, so that we can take a quick look at large files.
+
+use csv::ByteRecord;
+
+use crate::errors::*;
+use crate::record_processor::RecordSink;
+
+/// A small, fast pseudo-random number generator (SplitMix64). We use our own
+/// so that a given seed always selects the same rows, no matter which
+/// version of a random number crate we were built with.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from `seed`.
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// Return a random `u64`.
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Return a random number from 0.0 (inclusive) to 1.0 (exclusive).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Return a random number less than `n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(n)) >> 64) as u64
+    }
+}
+
+/// Decides which input rows to check when using `--limit` or
+/// `--sample-rate`, and counts the rows we've checked.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    /// The maximum number of rows to output.
+    limit: Option<u64>,
+    /// The fraction of input rows to check.
+    rate: Option<f64>,
+    rng: Rng,
+    /// The number of input rows we've checked.
+    rows_checked: u64,
+    /// The number of rows we've output.
+    rows_output: u64,
+}
+
+impl Sampler {
+    /// Create a sampler which outputs at most `limit` rows, and checks a
+    /// random fraction `rate` of our input rows chosen using `seed`.
+    pub(crate) fn new(limit: Option<u64>, rate: Option<f64>, seed: u64) -> Sampler {
+        Sampler {
+            limit,
+            rate,
+            rng: Rng::new(seed),
+            rows_checked: 0,
+            rows_output: 0,
+        }
+    }
+
+    /// Have we output as many rows as we were asked for?
+    pub(crate) fn at_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.rows_output >= limit)
+    }
+
+    /// Should we check the next input row, or skip it?
+    pub(crate) fn sample_row(&mut self) -> bool {
+        let keep = match self.rate {
+            Some(rate) => self.rng.next_f64() < rate,
+            None => true,
+        };
+        if keep {
+            self.rows_checked += 1;
+        }
+        keep
+    }
+
+    /// Record that we output a row.
+    pub(crate) fn record_output(&mut self) {
+        self.rows_output += 1;
+    }
+
+    /// The number of input rows we've checked.
+    pub(crate) fn rows_checked(&self) -> u64 {
+        self.rows_checked
+    }
+}
+
+/// Keeps a uniform random sample of the records written to it, using
+/// "Algorithm R".
+#[derive(Debug)]
+pub(crate) struct Reservoir {
+    /// The number of records to keep.
+    size: usize,
+    rng: Rng,
+    /// The number of records written to us.
+    seen: u64,
+    /// The records we're keeping, and their positions in our input.
+    records: Vec<(u64, ByteRecord)>,
+}
+
+impl Reservoir {
+    /// Create a reservoir which keeps `size` records chosen using `seed`.
+    pub(crate) fn new(size: usize, seed: u64) -> Reservoir {
+        Reservoir {
+            size,
+            // Don't pick the same random numbers as a `Sampler` with the same
+            // seed.
+            rng: Rng::new(!seed),
+            seen: 0,
+            records: Vec::with_capacity(size.min(64 * 1024)),
+        }
+    }
+
+    /// The records we kept, in their original order.
+    pub(crate) fn into_records(mut self) -> Vec<ByteRecord> {
+        self.records.sort_by_key(|(pos, _)| *pos);
+        self.records.into_iter().map(|(_, record)| record).collect()
+    }
+}
+
+impl RecordSink for Reservoir {
+    fn write_record<I, T>(&mut self, record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let pos = self.seen;
+        self.seen += 1;
+        let slot = if self.records.len() < self.size {
+            self.records.len()
+        } else {
+            let slot = self.rng.below(self.seen);
+            if slot >= self.size as u64 {
+                return Ok(());
+            }
+            slot as usize
+        };
+        let mut kept = ByteRecord::new();
+        kept.write_record(record)?;
+        if slot == self.records.len() {
+            self.records.push((pos, kept));
+        } else {
+            self.records[slot] = (pos, kept);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn samples_are_deterministic() {
+    let sample = |seed| {
+        let mut sampler = Sampler::new(None, Some(0.25), seed);
+        (0..1000)
+            .filter(|_| sampler.sample_row())
+            .collect::<Vec<_>>()
+    };
+    let rows = sample(1);
+    assert_eq!(rows, sample(1));
+    assert_ne!(rows, sample(2));
+    assert!(rows.len() > 200 && rows.len() < 300, "{}", rows.len());
+
+    let mut sampler = Sampler::new(Some(2), None, 0);
+    assert!(sampler.sample_row());
+    sampler.record_output();
+    assert!(!sampler.at_limit());
+    sampler.record_output();
+    assert!(sampler.at_limit());
+    assert_eq!(sampler.rows_checked(), 1);
+
+    let mut reservoir = Reservoir::new(10, 1);
+    for i in 0..1000 {
+        reservoir.write_record([i.to_string()]).unwrap();
+    }
+    let kept = reservoir
+        .into_records()
+        .iter()
+        .map(|record| std::str::from_utf8(&record[0]).unwrap().parse().unwrap())
+        .collect::<Vec<u32>>();
+    assert_eq!(kept.len(), 10);
+    assert!(kept.windows(2).all(|w| w[0] < w[1]), "{:?}", kept);
+}
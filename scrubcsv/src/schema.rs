@@ -0,0 +1,204 @@
+//! Column types, declared using a schema file.
+//!
+//! A schema file is either TOML or JSON (chosen by extension), and maps
+//! output column names to types:
+//!
+//! ```toml
+//! [columns]
+//! id = "integer"
+//! price = "number"
+//! active = "boolean"
+//! signup_date = "date"
+//! updated_at = "timestamp"
+//! notes = "string"
+//! ```
+//!
+//! Values must already be in a canonical format: integers like `-12`,
+//! numbers like `-1234.5`, booleans like `true` or `false`, dates like
+//! `2021-03-04` and ISO 8601 timestamps like `2021-03-04T17:05:00`, with an
+//! optional UTC offset. The `number`, `boolean` and `date` transform rules
+//! convert other formats into these. Rows with other values are dropped, and
+//! empty values are always allowed.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use crate::errors::*;
+
+/// The type of a column.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    /// Any value.
+    String,
+    /// A 64-bit signed integer.
+    Integer,
+    /// A 64-bit floating point number.
+    Number,
+    /// `true` or `false`.
+    Boolean,
+    /// A date, without a time.
+    Date,
+    /// A date and time, which we treat as UTC unless it has an offset.
+    Timestamp,
+}
+
+impl ColumnType {
+    /// Is `val` a valid value of this type? Empty values are always valid.
+    pub(crate) fn is_valid(self, val: &[u8]) -> bool {
+        if val.is_empty() {
+            return true;
+        }
+        match self {
+            ColumnType::String => true,
+            ColumnType::Integer => parse_integer(val).is_some(),
+            ColumnType::Number => parse_number(val).is_some(),
+            ColumnType::Boolean => parse_boolean(val).is_some(),
+            ColumnType::Date => parse_date(val).is_some(),
+            ColumnType::Timestamp => parse_timestamp_micros(val).is_some(),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColumnType::String => "string",
+            ColumnType::Integer => "integer",
+            ColumnType::Number => "number",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Date => "date",
+            ColumnType::Timestamp => "timestamp",
+        };
+        name.fmt(f)
+    }
+}
+
+/// The contents of a schema file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    /// The type of each output column. Other columns are inferred from our
+    /// transform rules, or treated as strings.
+    #[serde(default)]
+    columns: BTreeMap<String, ColumnType>,
+}
+
+impl Schema {
+    /// Load a schema from a TOML or JSON file.
+    pub fn from_path(path: &Path) -> Result<Schema> {
+        let data = fs::read_to_string(path)
+            .with_context(|_| format!("cannot read {}", path.display()))?;
+        let is_toml = path.extension().map(|ext| ext == "toml").unwrap_or(false);
+        if is_toml {
+            toml::from_str(&data)
+                .with_context(|_| format!("cannot parse {}", path.display()))
+        } else {
+            serde_json::from_str(&data)
+                .with_context(|_| format!("cannot parse {}", path.display()))
+        }
+    }
+
+    /// Look up the declared type of each of our output columns `names`.
+    pub(crate) fn resolve(&self, names: &[String]) -> Result<Vec<Option<ColumnType>>> {
+        for name in self.columns.keys() {
+            if !names.contains(name) {
+                return Err(format_err!(
+                    "schema column {:?} is not in the output",
+                    name
+                ));
+            }
+        }
+        Ok(names
+            .iter()
+            .map(|name| self.columns.get(name).copied())
+            .collect())
+    }
+}
+
+/// Parse an integer.
+pub(crate) fn parse_integer(val: &[u8]) -> Option<i64> {
+    std::str::from_utf8(val).ok()?.parse().ok()
+}
+
+/// Parse a finite number.
+pub(crate) fn parse_number(val: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(val).ok()?;
+    // Rust also accepts "inf" and "NaN", which aren't numbers in JSON.
+    if !s
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+    {
+        return None;
+    }
+    s.parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// Parse `true` or `false`, ignoring case.
+pub(crate) fn parse_boolean(val: &[u8]) -> Option<bool> {
+    if val.eq_ignore_ascii_case(b"true") {
+        Some(true)
+    } else if val.eq_ignore_ascii_case(b"false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Parse an ISO 8601 date.
+pub(crate) fn parse_date(val: &[u8]) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(std::str::from_utf8(val).ok()?, "%Y-%m-%d").ok()
+}
+
+/// Parse an ISO 8601 timestamp or date, and return the number of
+/// microseconds since the Unix epoch.
+pub(crate) fn parse_timestamp_micros(val: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(val).ok()?;
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Some(timestamp.timestamp_micros());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(timestamp.and_utc().timestamp_micros());
+        }
+    }
+    let date = parse_date(val)?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_micros())
+}
+
+#[test]
+fn parses_typed_values() {
+    assert_eq!(parse_integer(b"-12"), Some(-12));
+    assert_eq!(parse_integer(b"1.5"), None);
+    assert_eq!(parse_number(b"-1234.5"), Some(-1234.5));
+    assert_eq!(parse_number(b"1e3"), Some(1000.0));
+    assert_eq!(parse_number(b"inf"), None);
+    assert_eq!(parse_number(b"1e999"), None);
+    assert_eq!(parse_boolean(b"TRUE"), Some(true));
+    assert_eq!(parse_boolean(b"yes"), None);
+    assert_eq!(
+        parse_date(b"2021-03-04"),
+        NaiveDate::from_ymd_opt(2021, 3, 4)
+    );
+    assert_eq!(parse_date(b"03/04/2021"), None);
+    assert_eq!(
+        parse_timestamp_micros(b"1970-01-01T00:00:01.5"),
+        Some(1_500_000)
+    );
+    assert_eq!(
+        parse_timestamp_micros(b"1970-01-01T01:00:00+01:00"),
+        Some(0)
+    );
+    assert_eq!(parse_timestamp_micros(b"1970-01-02"), Some(86_400_000_000));
+    assert!(ColumnType::Integer.is_valid(b""));
+    assert!(!ColumnType::Integer.is_valid(b"x"));
+
+    let schema: Schema = toml::from_str("[columns]\nid = \"integer\"\n").unwrap();
+    let names = vec!["id".to_owned(), "name".to_owned()];
+    assert_eq!(
+        schema.resolve(&names).unwrap(),
+        vec![Some(ColumnType::Integer), None]
+    );
+    assert!(schema.resolve(&names[1..]).is_err());
+}
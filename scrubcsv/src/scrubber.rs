@@ -0,0 +1,1080 @@
+//! Reading, cleaning and writing an entire CSV file.
+
+use csv::ByteRecord;
+use log::debug;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, prelude::*},
+    mem,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::clean_column_names::ColumnNameMap;
+use crate::clean_values::ValueCleaner;
+use crate::comments::{CommentCounts, CommentFilter};
+use crate::compression::{decompress, ByteCount};
+use crate::dedupe::{DedupeKeep, Deduper};
+use crate::dialect::{InputDialect, OutputDialect};
+use crate::errors::*;
+use crate::escapes::{decoded_dialect, EscapeDecoder};
+use crate::fast_path::{process_fast, FastReader, FastWriter};
+use crate::limits::Limits;
+use crate::options::ScrubOptions;
+use crate::output_format::{JsonLinesWriter, OutputFormat, ParquetWriter};
+use crate::parallel::{process_parallel, RecordSplitter, CHUNK_SIZE};
+use crate::position::{locate_csv_error, TrackingReader};
+use crate::progress::{fraction_read, Progress, ProgressReporter};
+use crate::record_processor::{RecordProcessor, RecordSink};
+use crate::sample::{Reservoir, Sampler};
+use crate::schema::ColumnType;
+use crate::select::{rename_columns, selected_columns};
+use crate::skip::{skip_preamble, FooterFilter};
+use crate::sniff::{sniff, SniffedDialect};
+use crate::stats::{SampleStats, Stats, StatsCollector};
+use crate::BUFFER_SIZE;
+
+/// Where we get our records from.
+enum Source<R: Read> {
+    /// Parse records on the main thread.
+    Sequential(csv::Reader<R>),
+    /// Parse records on the main thread, copying them to the output without
+    /// building `ByteRecord`s.
+    Fast(Box<FastReader<R>>),
+    /// Split our input into chunks to be parsed by worker threads. If we're
+    /// asked for individual records, we parse each `chunk` ourselves.
+    Parallel {
+        splitter: Box<RecordSplitter<io::BufReader<R>>>,
+        chunk: Option<csv::Reader<io::Cursor<Vec<u8>>>>,
+    },
+}
+
+impl<R: Read> Source<R> {
+    /// Read the next record into `record`. Returns `false` at the end of our
+    /// input.
+    fn read_byte_record(
+        &mut self,
+        dialect: &InputDialect,
+        record: &mut ByteRecord,
+    ) -> Result<bool> {
+        match self {
+            Source::Sequential(rdr) => rdr
+                .read_byte_record(record)
+                .map_err(|err| locate_csv_error(err, rdr.position()))
+                .context("cannot read record"),
+            Source::Fast(rdr) => {
+                if rdr.read_record()? {
+                    *record = rdr.to_byte_record();
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            Source::Parallel { splitter, chunk } => loop {
+                if let Some(rdr) = chunk {
+                    if rdr.read_byte_record(record).context("cannot read record")? {
+                        return Ok(true);
+                    }
+                }
+                match splitter.next_chunk(CHUNK_SIZE, u64::MAX)? {
+                    Some(next) => {
+                        *chunk =
+                            Some(dialect.csv_reader(io::Cursor::new(next.data), false))
+                    }
+                    None => return Ok(false),
+                }
+            },
+        }
+    }
+
+    /// The number of bytes read so far.
+    fn bytes_read(&self) -> u64 {
+        match self {
+            Source::Sequential(rdr) => rdr.position().byte(),
+            Source::Fast(rdr) => rdr.bytes_read(),
+            Source::Parallel { splitter, .. } => splitter.bytes_read(),
+        }
+    }
+}
+
+/// Reads records from a CSV file, validates and cleans them, and either
+/// writes them to an output or returns them one at a time.
+/// A `Scrubber` is `Send`, so it can be created on one thread and used on
+/// another.
+///
+/// ```
+/// use scrubcsv::{ScrubOptions, Scrubber};
+///
+/// let mut options = ScrubOptions::new();
+/// options.trim_whitespace(true);
+/// let input = "a,b\n 1 ,2\nbad\n";
+/// let mut output = vec![];
+/// let stats = Scrubber::new(&options, input.as_bytes())?.scrub(&mut output)?;
+/// assert_eq!(output, b"a,b\n1,2\n");
+/// assert_eq!(stats.bad_rows, 1);
+/// # Ok::<(), scrubcsv::Error>(())
+/// ```
+pub struct Scrubber<'a> {
+    /// Our input records.
+    source: Source<Box<dyn Read + Send + 'a>>,
+    /// The dialect of our input.
+    input_dialect: InputDialect,
+    /// The dialect of our output.
+    output_dialect: OutputDialect,
+    /// The format of our output.
+    output_format: OutputFormat,
+    /// The type of each output column.
+    output_types: Vec<ColumnType>,
+    /// The number of worker threads to use.
+    threads: usize,
+    /// Everything we need to process an individual record.
+    processor: RecordProcessor,
+    /// Tracks duplicate rows, if we're removing them.
+    deduper: Option<Deduper>,
+    /// Holds back records until we know they're not part of a footer.
+    footer_filter: Option<FooterFilter>,
+    /// Records released by `footer_filter` which we haven't processed yet.
+    released: VecDeque<ByteRecord>,
+    /// Have we read all of `source`?
+    at_eof: bool,
+    /// Scratch space for reading records.
+    raw: ByteRecord,
+    /// Our output header.
+    headers: ByteRecord,
+    /// The cleaned names of our input columns.
+    input_names: Vec<String>,
+    /// Our column name map, including any new mappings.
+    column_name_map: Option<ColumnNameMap>,
+    /// The number of the last row we read. We count the input's header as a
+    /// row for backwards compatibility.
+    rows: u64,
+    /// The number of header rows included in `rows`.
+    header_rows: u64,
+    /// Chooses which rows to check, if we're sampling our input.
+    sampler: Option<Sampler>,
+    /// The number of rows to output using a `Reservoir`, if any.
+    reservoir: Option<usize>,
+    /// Our sampling options, used to build our statistics.
+    sample_stats: Option<SampleStats>,
+    /// Statistics about our rows.
+    stats: StatsCollector,
+    /// The comments we've removed from our input.
+    comment_counts: Arc<CommentCounts>,
+    /// The number of bytes skipped before our header.
+    preamble_bytes: u64,
+    /// The dialect we detected, if we were asked to sniff it.
+    sniffed: Option<SniffedDialect>,
+    /// The number of compressed bytes read, if our input is compressed.
+    compressed_bytes: Option<ByteCount>,
+    /// The number of uncompressed bytes read, including any preamble and
+    /// comments.
+    input_bytes: ByteCount,
+    /// The size of our input file, if we know it.
+    input_size: Option<u64>,
+    /// Reports our progress, if anybody asked.
+    progress: Option<ProgressReporter<'a>>,
+    /// When we started.
+    start_time: Instant,
+}
+
+/// Check that `T` is `Send` at compile time.
+fn assert_send<T: Send>() {}
+
+// Callers need to be able to move a `Scrubber` into a worker thread.
+const _: fn() = assert_send::<Scrubber<'static>>;
+
+impl<'a> Scrubber<'a> {
+    /// Create a `Scrubber` which reads from `input`, and read the header.
+    pub fn new<R: Read + Send + 'a>(
+        options: &ScrubOptions,
+        input: R,
+    ) -> Result<Scrubber<'a>> {
+        if options.dedupe_enabled() && options.dedupe_keep == DedupeKeep::Last {
+            return Err(format_err!("--dedupe-keep=last requires an input file"));
+        }
+        Scrubber::from_reader(options, Box::new(input), None)
+    }
+
+    /// Create a `Scrubber` which reads from the file at `path`, and read the
+    /// header.
+    pub fn from_path<P: AsRef<Path>>(
+        options: &ScrubOptions,
+        path: P,
+    ) -> Result<Scrubber<'static>> {
+        let path = path.as_ref();
+        let open = || {
+            fs::File::open(path)
+                .with_context(|_| format!("cannot open {}", path.display()))
+        };
+        let mut scrubber =
+            Scrubber::from_reader(options, Box::new(open()?), Some(path))?;
+
+        // If we're keeping the last copy of duplicate rows, we need to find
+        // all the last copies before we can return anything.
+        let processor = &scrubber.processor;
+        if let Some(deduper) = scrubber.deduper.as_mut().filter(|d| d.needs_scan()) {
+            let (scan_input, _) = decompress(Box::new(open()?), Some(path))?;
+            let scan_input = prepare_input(
+                options,
+                scan_input,
+                Arc::new(CommentCounts::default()),
+            )?;
+            let mut scan_rdr = scan_input
+                .dialect
+                .csv_reader(scan_input.input, scan_input.has_headers);
+            let mut scan_stats = StatsCollector::new(None);
+            let mut scratch = ByteRecord::new();
+            let mut row = scrubber.rows;
+            let footer_filter = options.footer_filter(&scan_input.dialect);
+            for_each_record(&mut scan_rdr, footer_filter, |record| {
+                row += 1;
+                processor.process(
+                    row,
+                    record,
+                    Some(deduper),
+                    &mut scan_stats,
+                    &mut scratch,
+                )?;
+                Ok(())
+            })?;
+            deduper.finish_scan();
+        }
+        Ok(scrubber)
+    }
+
+    /// Create a `Scrubber` which reads from `input`.
+    fn from_reader(
+        options: &ScrubOptions,
+        input: Box<dyn Read + Send + 'a>,
+        path: Option<&Path>,
+    ) -> Result<Scrubber<'a>> {
+        // Deduplication and footer detection need to see rows in order.
+        let threads = options.thread_count();
+        if options.has_footer() && threads > 1 {
+            return Err(format_err!(
+                "cannot use --threads with --footer-regex or --skip-trailing"
+            ));
+        }
+        if options.dedupe_enabled() && threads > 1 {
+            return Err(format_err!(
+                "cannot use --threads with --dedupe or --dedupe-on"
+            ));
+        }
+        if options.output_format != OutputFormat::Csv && threads > 1 {
+            return Err(format_err!("cannot use --threads with --output-format"));
+        }
+        if options.is_sampled() && threads > 1 {
+            return Err(format_err!(
+                "cannot use --threads with --limit, --sample-rate or --reservoir"
+            ));
+        }
+        // Our scan for the last copy of each row would see rows which we'll
+        // never sample.
+        if options.dedupe_enabled()
+            && options.dedupe_keep == DedupeKeep::Last
+            && (options.limit.is_some() || options.sample_rate.is_some())
+        {
+            return Err(format_err!(
+                "cannot use --dedupe-keep=last with --limit or --sample-rate"
+            ));
+        }
+        if options.limit.is_some() && options.reservoir.is_some() {
+            return Err(format_err!("cannot use --limit with --reservoir"));
+        }
+        if let Some(rate) = options.sample_rate {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(format_err!(
+                    "--sample-rate must be greater than 0 and at most 1, found {}",
+                    rate
+                ));
+            }
+        }
+        if !options.has_headers && options.header.is_none() {
+            return Err(format_err!(
+                "column names must be supplied if the input has no headers"
+            ));
+        }
+
+        // Remember the time we started.
+        let start_time = Instant::now();
+
+        // Decompress our input if necessary, skip any junk before our header,
+        // and remove comments.
+        let (input, compressed_bytes) = decompress(input, path)?;
+        let input = TrackingReader::new(input);
+        let input_bytes = input.byte_count();
+        let input = Box::new(input);
+        let input_size = path
+            .and_then(|path| fs::metadata(path).ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        let comment_counts = Arc::new(CommentCounts::default());
+        let PreparedInput {
+            input,
+            preamble_bytes,
+            dialect: input_dialect,
+            has_headers,
+            sniffed,
+        } = prepare_input(options, input, comment_counts.clone())?;
+
+        // Build our value cleaner, including our `--null` regex.
+        let mut value_cleaner = ValueCleaner::new(
+            options.null.as_deref(),
+            options.trim_whitespace,
+            options.replace_newlines,
+        )?;
+        value_cleaner.set_unicode_cleanups(
+            options.strip_control_chars,
+            options.unicode_normalize,
+            options.unicode_whitespace,
+        );
+
+        // Create our CSV reader, or split our input into chunks for our worker
+        // threads.
+        //
+        // If we don't need to clean any values, we use the lowest-level,
+        // zero-copy API for `csv`, which buffers each record's field
+        // boundaries so that we can count them before copying the raw record
+        // to our output.
+        let use_fast_path = threads <= 1
+            && options.fast_path
+            && value_cleaner.is_noop()
+            && options.transform_rules.is_none()
+            && options.drop_row_if_null.is_empty()
+            && !options.dedupe_enabled()
+            && !options.has_limits()
+            && !options.has_footer()
+            && !options.profile_columns
+            && !options.is_sampled()
+            && options.schema.is_none()
+            && options.output_format == OutputFormat::Csv;
+        let (input_hdr, source) = if threads > 1 {
+            let mut splitter = RecordSplitter::new(
+                io::BufReader::with_capacity(BUFFER_SIZE, input),
+                &input_dialect,
+            );
+            let mut hdr = ByteRecord::new();
+            let chunk = if has_headers {
+                splitter.next_chunk(usize::MAX, 1)?
+            } else {
+                None
+            };
+            if let Some(chunk) = chunk {
+                input_dialect
+                    .csv_reader(&chunk.data[..], false)
+                    .read_byte_record(&mut hdr)
+                    .context("cannot read headers")?;
+            }
+            let splitter = Box::new(splitter);
+            (
+                hdr,
+                Source::Parallel {
+                    splitter,
+                    chunk: None,
+                },
+            )
+        } else if use_fast_path {
+            let mut rdr = FastReader::new(input, &input_dialect);
+            let hdr = if has_headers && rdr.read_record()? {
+                rdr.to_byte_record()
+            } else {
+                ByteRecord::new()
+            };
+            (hdr, Source::Fast(Box::new(rdr)))
+        } else {
+            let mut rdr = input_dialect.csv_reader(input, has_headers);
+            let hdr = if has_headers {
+                rdr.byte_headers()
+                    .map_err(|err| locate_csv_error(err, &csv::Position::new()))
+                    .context("cannot read headers")?
+                    .to_owned()
+            } else {
+                ByteRecord::new()
+            };
+            (hdr, Source::Sequential(rdr))
+        };
+
+        // If we were given a header, use it instead of the one in our input.
+        let mut hdr = match &options.header {
+            Some(hdr) => hdr.clone(),
+            None => input_hdr,
+        };
+
+        // If we were asked, make sure all the column names are unique.
+        let mut column_name_map = options.column_name_map.clone();
+        if options.clean_column_names.is_some() || column_name_map.is_some() {
+            hdr = clean_column_names(options, &hdr, column_name_map.as_mut())?;
+        }
+
+        // Figure out which columns we want to output, and what to call them.
+        let input_names = hdr
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<String>>();
+        let mut renamed_names = input_names.clone();
+        rename_columns(&mut renamed_names, &options.rename)?;
+        if let Some(rules) = &options.transform_rules {
+            value_cleaner.set_column_transforms(rules.compile(&input_names)?);
+        }
+        let reordered_cols = match &options.expect_columns {
+            Some(expected) => expected.check(&input_names)?,
+            None => None,
+        };
+        let selected_cols = match reordered_cols {
+            Some(_) if !options.select.is_empty() || !options.exclude.is_empty() => {
+                return Err(format_err!(
+                    "cannot use --select or --exclude when reordering expected columns"
+                ));
+            }
+            Some(cols) => Some(cols),
+            None => selected_columns(&input_names, &options.select, &options.exclude)?,
+        };
+        let out_cols = match &selected_cols {
+            Some(cols) => cols.clone(),
+            None => (0..hdr.len()).collect(),
+        };
+        // Columns past the end of our input are expected columns which are
+        // missing, so we output them as empty values.
+        let out_names = out_cols
+            .iter()
+            .enumerate()
+            .map(|(i, &idx)| match renamed_names.get(idx) {
+                Some(name) => name.clone(),
+                None => options
+                    .expect_columns
+                    .as_ref()
+                    .expect("missing column without expected columns")
+                    .names()[i]
+                    .clone(),
+            })
+            .collect::<Vec<String>>();
+        let check_duplicates = options.clean_column_names.is_some()
+            || column_name_map.is_some()
+            || !options.rename.is_empty()
+            || options.output_format != OutputFormat::Csv;
+        let mut out_hdr = ByteRecord::default();
+        for (i, (&idx, name)) in out_cols.iter().zip(out_names.iter()).enumerate() {
+            if check_duplicates && out_names[..i].contains(name) {
+                return Err(format_err!("duplicate output column name {:?}", name));
+            }
+            if let Some(reserved_re) = &options.reserve_column_names {
+                if reserved_re.is_match(name) {
+                    return Err(format_err!(
+                        "file used reserved column name {:?}",
+                        name
+                    ));
+                }
+            }
+            // Preserve the original bytes of any column we didn't rename.
+            if input_names.get(idx) == Some(name) {
+                out_hdr.push_field(&hdr[idx]);
+            } else {
+                out_hdr.push_field(name.as_bytes());
+            }
+        }
+
+        // Figure out which columns to use when looking for duplicates.
+        let deduper = if options.dedupe {
+            Some(Deduper::new(
+                out_cols.clone(),
+                options.dedupe_keep,
+                options.dedupe_max_keys,
+            ))
+        } else if !options.dedupe_on.is_empty() {
+            let mut key_cols = vec![];
+            for spec in &options.dedupe_on {
+                key_cols.extend(spec.matching_indices(&input_names)?);
+            }
+            Some(Deduper::new(
+                key_cols,
+                options.dedupe_keep,
+                options.dedupe_max_keys,
+            ))
+        } else {
+            None
+        };
+
+        // Just in case --drop-row-if-null was passed, precompute which columns are
+        // required to contain a value. We check these before selecting columns,
+        // so rows can be dropped based on columns which aren't output, and we
+        // accept either the cleaned or renamed form of each column name.
+        let required_cols = input_names
+            .iter()
+            .zip(renamed_names.iter())
+            .map(|(name, renamed)| -> bool {
+                options.drop_row_if_null.iter().any(|required_name| {
+                    required_name == name || required_name == renamed
+                })
+            })
+            .collect::<Vec<bool>>();
+
+        // Look up the types of our output columns, using our schema or our
+        // transform rules. We check the values of any columns in our schema.
+        let declared_types = match &options.schema {
+            Some(schema) => schema.resolve(&out_names)?,
+            None => vec![None; out_names.len()],
+        };
+        let mut schema_types = vec![None; input_names.len()];
+        for (&idx, declared) in out_cols.iter().zip(declared_types.iter()) {
+            if let (Some(slot), Some(_)) = (schema_types.get_mut(idx), declared) {
+                *slot = *declared;
+            }
+        }
+        let output_types = out_cols
+            .iter()
+            .zip(declared_types)
+            .map(|(&idx, declared)| {
+                declared
+                    .or_else(|| value_cleaner.column_type(idx))
+                    .unwrap_or(ColumnType::String)
+            })
+            .collect::<Vec<_>>();
+
+        // Everything we need to process an individual record.
+        let limits = Limits::new(
+            &input_names,
+            &out_cols,
+            &options.max_field_bytes,
+            options.long_field_action,
+            options.max_row_bytes,
+        )?;
+        let processor = RecordProcessor {
+            expected_cols: hdr.len(),
+            selected_cols,
+            required_cols,
+            value_cleaner,
+            limits,
+            schema_types,
+        };
+
+        // If we were asked, collect statistics about the values in each column.
+        let stats = StatsCollector::new(
+            Some(input_names.len()).filter(|_| options.profile_columns),
+        );
+
+        let footer_filter = options.footer_filter(&input_dialect);
+        Ok(Scrubber {
+            source,
+            input_dialect,
+            output_dialect: options.output_dialect.clone(),
+            output_format: options.output_format,
+            output_types,
+            threads,
+            processor,
+            deduper,
+            footer_filter,
+            released: VecDeque::new(),
+            at_eof: false,
+            raw: ByteRecord::new(),
+            headers: out_hdr,
+            input_names,
+            column_name_map,
+            rows: if has_headers { 1 } else { 0 },
+            header_rows: if has_headers { 1 } else { 0 },
+            sampler: if options.is_sampled() {
+                Some(Sampler::new(
+                    options.limit,
+                    options.sample_rate,
+                    options.seed,
+                ))
+            } else {
+                None
+            },
+            reservoir: options.reservoir,
+            sample_stats: if options.is_sampled() {
+                Some(SampleStats {
+                    limit: options.limit,
+                    sample_rate: options.sample_rate,
+                    reservoir: options.reservoir,
+                    seed: options.seed,
+                    read_all_input: false,
+                    rows_checked: 0,
+                    bad_row_ratio: 0.0,
+                    estimated_total_rows: None,
+                    estimated_bad_rows: None,
+                })
+            } else {
+                None
+            },
+            stats,
+            comment_counts,
+            preamble_bytes,
+            sniffed,
+            compressed_bytes,
+            input_bytes,
+            input_size,
+            progress: None,
+            start_time,
+        })
+    }
+
+    /// The header of our output.
+    pub fn headers(&self) -> &ByteRecord {
+        &self.headers
+    }
+
+    /// Our column name map, including any mappings added for new columns.
+    pub fn column_name_map(&self) -> Option<&ColumnNameMap> {
+        self.column_name_map.as_ref()
+    }
+
+    /// Call `callback` with our progress roughly once every `interval` while
+    /// we read our input.
+    pub fn on_progress<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: FnMut(&Progress) + Send + 'a,
+    {
+        self.progress = Some(ProgressReporter::new(
+            Box::new(callback),
+            interval,
+            self.start_time,
+            self.input_bytes.clone(),
+            self.compressed_bytes.clone(),
+            self.input_size,
+        ));
+    }
+
+    /// Read the next good record into `record`, after cleaning it. Returns
+    /// `false` at the end of our input.
+    pub fn read_record(&mut self, record: &mut ByteRecord) -> Result<bool> {
+        let mut raw = mem::take(&mut self.raw);
+        let result = self.scrub_next(&mut raw, record);
+        self.raw = raw;
+        result
+    }
+
+    /// An iterator over our remaining good records, after cleaning them.
+    pub fn byte_records(&mut self) -> ByteRecordsIter<'_, 'a> {
+        ByteRecordsIter { scrubber: self }
+    }
+
+    /// Write our header and all our remaining good records to `output`, and
+    /// return our statistics.
+    pub fn scrub<W: Write>(mut self, output: W) -> Result<Stats> {
+        match self.output_format {
+            OutputFormat::Csv => self.scrub_csv(output)?,
+            OutputFormat::Jsonl => {
+                let mut wtr =
+                    JsonLinesWriter::new(output, &self.headers, &self.output_types)?;
+                self.scrub_all(&mut wtr)?;
+                wtr.flush()?;
+            }
+            OutputFormat::Parquet => {
+                let mut wtr =
+                    ParquetWriter::new(output, &self.headers, &self.output_types)?;
+                self.scrub_all(&mut wtr)?;
+                wtr.finish()?.flush().context("error writing records")?;
+            }
+        }
+        self.finish()
+    }
+
+    /// Write our header and all our remaining good records to `output` as
+    /// CSV.
+    fn scrub_csv<W: Write>(&mut self, output: W) -> Result<()> {
+        // Create our CSV writer.  Note that we _don't_ allow variable numbers
+        // of columns or other nonsense: We want our output to be highly
+        // normalized. But some loaders insist on a particular dialect, so we
+        // let the user choose that explicitly.
+        let mut wtr = self.output_dialect.csv_writer(output);
+        wtr.write_byte_record(&self.headers)
+            .context("cannot write headers")?;
+
+        match &mut self.source {
+            Source::Fast(rdr) => {
+                let output = wtr.into_inner().map_err(|err| {
+                    format_err!("error writing records: {}", err.error())
+                })?;
+                let mut fast_wtr = FastWriter::new(output, &self.output_dialect);
+                self.rows += process_fast(
+                    rdr,
+                    &mut fast_wtr,
+                    self.rows + 1,
+                    self.processor.expected_cols,
+                    self.processor.selected_cols.as_deref(),
+                    &self.input_dialect,
+                    &self.output_dialect,
+                    &mut self.stats,
+                    self.progress.as_mut(),
+                )?;
+                fast_wtr.flush().context("error writing records")?;
+            }
+            Source::Parallel {
+                splitter,
+                chunk: None,
+            } => {
+                // Our workers send us pre-formatted data, which we write
+                // directly to our output.
+                let mut output = wtr.into_inner().map_err(|err| {
+                    format_err!("error writing records: {}", err.error())
+                })?;
+                self.rows += process_parallel(
+                    splitter,
+                    self.rows + 1,
+                    self.threads,
+                    &self.processor,
+                    &mut self.stats,
+                    &self.input_dialect,
+                    &self.output_dialect,
+                    &mut output,
+                    self.progress.as_mut(),
+                )?;
+                output.flush().context("error writing records")?;
+            }
+            _ => {
+                self.scrub_all(&mut wtr)?;
+                wtr.flush().context("error writing records")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all our remaining good records to `out`, or a random sample of
+    /// them if we were asked for a reservoir.
+    fn scrub_all<S: RecordSink>(&mut self, out: &mut S) -> Result<()> {
+        let mut raw = mem::take(&mut self.raw);
+        if let Some(size) = self.reservoir {
+            let seed = self.sample_stats.as_ref().map_or(0, |s| s.seed);
+            let mut reservoir = Reservoir::new(size, seed);
+            while self.scrub_next(&mut raw, &mut reservoir)? {}
+            for record in reservoir.into_records() {
+                out.write_record(&record)?;
+            }
+        } else {
+            while self.scrub_next(&mut raw, out)? {}
+        }
+        Ok(())
+    }
+
+    /// Return statistics about the records we've read.
+    pub fn finish(self) -> Result<Stats> {
+        let bytes_processed = self.preamble_bytes
+            + self.comment_counts.bytes.load(Ordering::Relaxed)
+            + self.source.bytes_read();
+        let elapsed_seconds = self.start_time.elapsed().as_secs_f64();
+        let mut stats = self.stats;
+
+        // If we sampled our input, estimate the number of bad rows in all of
+        // it. We may have read ahead of the last row we parsed, so we scale
+        // down how much we read by how much we parsed.
+        let mut sample_stats = self.sample_stats;
+        if let (Some(sample), Some(sampler)) = (&mut sample_stats, &self.sampler) {
+            sample.read_all_input = self.at_eof;
+            sample.rows_checked = sampler.rows_checked();
+            if sample.rows_checked > 0 {
+                sample.bad_row_ratio =
+                    stats.bad_rows() as f64 / sample.rows_checked as f64;
+            }
+            let data_rows = self.rows - self.header_rows;
+            let fraction = if self.at_eof {
+                Some(1.0)
+            } else {
+                let input_bytes = self.input_bytes.get();
+                fraction_read(
+                    input_bytes,
+                    self.compressed_bytes.as_ref().map(|count| count.get()),
+                    self.input_size,
+                )
+                .filter(|_| input_bytes > 0)
+                .map(|f| f * bytes_processed as f64 / input_bytes as f64)
+            };
+            if let Some(fraction) = fraction.filter(|&f| f > 0.0) {
+                let total_rows = (data_rows as f64 / fraction).round();
+                sample.estimated_total_rows = Some(total_rows as u64);
+                sample.estimated_bad_rows =
+                    Some((total_rows * sample.bad_row_ratio).round() as u64);
+            }
+        }
+
+        Ok(Stats {
+            rows: self.rows,
+            bad_rows: stats.bad_rows(),
+            duplicate_rows: stats.rejections.duplicate,
+            comment_lines: self.comment_counts.lines.load(Ordering::Relaxed),
+            elapsed_seconds,
+            bytes_processed,
+            bytes_per_second: (bytes_processed as f64 / elapsed_seconds) as i64,
+            compressed_bytes_processed: self.compressed_bytes.map(|count| count.get()),
+            rejections: stats.rejections,
+            columns: stats.column_profiles(&self.input_names),
+            bad_row_column_counts: stats.bad_row_column_counts,
+            dialect: self.sniffed,
+            sample: sample_stats,
+        })
+    }
+
+    /// Process records until we write one to `out`. Returns `false` at the
+    /// end of our input.
+    fn scrub_next<S: RecordSink>(
+        &mut self,
+        raw: &mut ByteRecord,
+        out: &mut S,
+    ) -> Result<bool> {
+        loop {
+            if self.sampler.as_ref().is_some_and(|s| s.at_limit()) {
+                return Ok(false);
+            }
+            if !self.read_raw_record(raw)? {
+                return Ok(false);
+            }
+            self.rows += 1;
+            if let Some(progress) = &mut self.progress {
+                progress.tick(self.rows, &self.stats);
+            }
+            if let Some(sampler) = &mut self.sampler {
+                if !sampler.sample_row() {
+                    continue;
+                }
+            }
+            if self.processor.process(
+                self.rows,
+                raw,
+                self.deduper.as_mut(),
+                &mut self.stats,
+                out,
+            )? {
+                if let Some(sampler) = &mut self.sampler {
+                    sampler.record_output();
+                }
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Read the next record which isn't part of a footer. Returns `false` at
+    /// the end of our input.
+    fn read_raw_record(&mut self, raw: &mut ByteRecord) -> Result<bool> {
+        loop {
+            if let Some(record) = self.released.pop_front() {
+                *raw = record;
+                return Ok(true);
+            }
+            if self.at_eof {
+                return Ok(false);
+            }
+            if !self.source.read_byte_record(&self.input_dialect, raw)? {
+                debug!("reached end of input");
+                self.at_eof = true;
+                if let Some(footer_filter) = self.footer_filter.take() {
+                    footer_filter.finish()?;
+                }
+                return Ok(false);
+            }
+            match &mut self.footer_filter {
+                Some(footer_filter) => {
+                    let released = &mut self.released;
+                    footer_filter.push(raw.clone(), |record| {
+                        released.push_back(record.clone());
+                        Ok(())
+                    })?;
+                }
+                None => return Ok(true),
+            }
+        }
+    }
+}
+
+/// An iterator over the cleaned records from a `Scrubber`.
+pub struct ByteRecordsIter<'s, 'a> {
+    scrubber: &'s mut Scrubber<'a>,
+}
+
+impl<'s, 'a> Iterator for ByteRecordsIter<'s, 'a> {
+    type Item = Result<ByteRecord>;
+
+    fn next(&mut self) -> Option<Result<ByteRecord>> {
+        let mut record = ByteRecord::new();
+        match self.scrubber.read_record(&mut record) {
+            Ok(true) => Some(Ok(record)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Clean the column names in `hdr`. If we have a `column_name_map`, we use
+/// it to look up names, and only clean names which aren't in the map. Those
+/// are added to the map if `options.update_column_name_map` is set, or
+/// reported as an error otherwise.
+fn clean_column_names(
+    options: &ScrubOptions,
+    hdr: &ByteRecord,
+    mut column_name_map: Option<&mut ColumnNameMap>,
+) -> Result<ByteRecord> {
+    let mut cleaner = options.clean_column_names.map(|cleaner_type| {
+        cleaner_type.build_cleaner(options.transliterate_column_names)
+    });
+    // Don't generate any names that we're going to use for mapped columns.
+    if let (Some(cleaner), Some(map)) = (&mut cleaner, &column_name_map) {
+        for col in hdr {
+            if let Some(mapped) = map.get(&String::from_utf8_lossy(col)) {
+                cleaner.reserve_id(mapped);
+            }
+        }
+    }
+    let mut new_hdr = ByteRecord::default();
+    let mut missing = vec![];
+    for col in hdr.into_iter() {
+        // Convert from bytes to UTF-8, make unique (and clean), and convert back to bytes.
+        let col = String::from_utf8_lossy(col);
+        if let Some(mapped) = column_name_map.as_ref().and_then(|m| m.get(&col)) {
+            new_hdr.push_field(mapped.as_bytes());
+            continue;
+        }
+        let clean = match &mut cleaner {
+            Some(cleaner) => cleaner.unique_id_for(&col)?,
+            None => col.clone().into_owned(),
+        };
+        if let Some(map) = column_name_map.as_mut() {
+            if options.update_column_name_map {
+                map.insert(col.into_owned(), clean.clone());
+            } else {
+                missing.push(col.into_owned());
+            }
+        }
+        new_hdr.push_field(clean.as_bytes());
+    }
+    if !missing.is_empty() {
+        return Err(format_err!(
+            "columns missing from column name map (use --update-column-name-map to add them): {:?}",
+            missing
+        ));
+    }
+    Ok(new_hdr)
+}
+
+/// Our input, ready to parse.
+struct PreparedInput<'a> {
+    /// Our input, without any preamble or comments.
+    input: Box<dyn Read + Send + 'a>,
+    /// The number of bytes skipped before our header.
+    preamble_bytes: u64,
+    /// The dialect to use when parsing `input`.
+    dialect: InputDialect,
+    /// Does `input` start with a header row?
+    has_headers: bool,
+    /// The dialect we detected, if we were asked to sniff it.
+    sniffed: Option<SniffedDialect>,
+}
+
+/// Skip any lines before our header, sniff our dialect if we were asked to,
+/// and wrap `input` to remove comments.
+fn prepare_input<'a>(
+    options: &ScrubOptions,
+    input: Box<dyn Read + Send + 'a>,
+    comment_counts: Arc<CommentCounts>,
+) -> Result<PreparedInput<'a>> {
+    let (mut input, preamble_bytes) =
+        if options.skip_lines > 0 || options.header_regex.is_some() {
+            skip_preamble(
+                io::BufReader::with_capacity(BUFFER_SIZE, input),
+                options.skip_lines,
+                options.header_regex.as_ref(),
+            )?
+        } else {
+            (input, 0)
+        };
+
+    // Sniff the start of our input, and then put it back.
+    let mut dialect = options.input_dialect.clone();
+    let mut has_headers = options.has_headers;
+    let mut sniffed = None;
+    if options.sniff {
+        let mut sample = vec![];
+        input
+            .by_ref()
+            .take(options.sniff_bytes as u64)
+            .read_to_end(&mut sample)
+            .context("cannot read input")?;
+        let detected = sniff(
+            &sample,
+            sample.len() < options.sniff_bytes,
+            options.comment,
+            &dialect,
+            has_headers,
+        );
+        debug!("sniffed dialect: {:?}", detected);
+        if !detected.has_headers && options.header.is_none() {
+            return Err(format_err!(
+                "input does not seem to have a header row (use --header to supply column names)"
+            ));
+        }
+        dialect = detected.input_dialect();
+        has_headers = detected.has_headers;
+        sniffed = Some(detected);
+        input = Box::new(io::Cursor::new(sample).chain(input));
+    }
+
+    if let Some(comment) = options.comment {
+        input = Box::new(CommentFilter::new(input, comment, &dialect, comment_counts));
+    }
+
+    // Decode any escape sequences, so that `csv` sees ordinary CSV.
+    if let Some(escape) = dialect.escape {
+        input = Box::new(EscapeDecoder::new(input, escape, &dialect));
+        dialect = decoded_dialect(&dialect);
+    }
+    Ok(PreparedInput {
+        input,
+        preamble_bytes,
+        dialect,
+        has_headers,
+        sniffed,
+    })
+}
+
+/// Call `f` for each record in `rdr`, skipping any records removed by
+/// `footer_filter`.
+fn for_each_record<R, F>(
+    rdr: &mut csv::Reader<R>,
+    footer_filter: Option<FooterFilter>,
+    mut f: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(&ByteRecord) -> Result<()>,
+{
+    let mut record = ByteRecord::new();
+    match footer_filter {
+        Some(mut footer_filter) => {
+            while rdr
+                .read_byte_record(&mut record)
+                .map_err(|err| locate_csv_error(err, rdr.position()))
+                .context("cannot read record")?
+            {
+                footer_filter.push(record.clone(), &mut f)?;
+            }
+            footer_filter.finish()
+        }
+        None => {
+            while rdr
+                .read_byte_record(&mut record)
+                .map_err(|err| locate_csv_error(err, rdr.position()))
+                .context("cannot read record")?
+            {
+                f(&record)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn reads_cleaned_records() {
+    let mut options = ScrubOptions::new();
+    options
+        .skip_trailing(1)
+        .null(Some("NULL"))
+        .drop_row_if_null(vec!["b".to_owned()]);
+    let input = "a,b\n1,2\nbad\n3,NULL\n 4 ,5\nTOTAL,4\n";
+    let mut scrubber = Scrubber::new(&options, input.as_bytes()).unwrap();
+    assert_eq!(scrubber.headers(), vec!["a", "b"]);
+    let records = scrubber.byte_records().collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(records, vec![vec!["1", "2"], vec![" 4 ", "5"]]);
+    let stats = scrubber.finish().unwrap();
+    assert_eq!(stats.rows, 5);
+    assert_eq!(stats.bad_rows, 2);
+    assert_eq!(stats.rejections.missing_required_value, 1);
+}
@@ -0,0 +1,167 @@
+//! Column selection, reordering and renaming.
+
+use regex::Regex;
+use std::str::FromStr;
+
+use crate::errors::*;
+
+/// Specifies one or more columns using a name, a 1-based index, or a
+/// `/regex/` matching column names.
+#[derive(Debug, Clone)]
+pub enum ColumnSpec {
+    /// A column with exactly this name.
+    Name(String),
+    /// A 1-based column index, like `cut` and `xsv`.
+    Index(usize),
+    /// All columns whose names match this regex, in input order.
+    Regex(Regex),
+}
+
+impl ColumnSpec {
+    /// Return the indices of all columns in `names` matching this spec.
+    pub fn matching_indices(&self, names: &[String]) -> Result<Vec<usize>> {
+        match self {
+            ColumnSpec::Name(name) => names
+                .iter()
+                .position(|n| n == name)
+                .map(|idx| vec![idx])
+                .ok_or_else(|| format_err!("cannot find column {:?}", name)),
+            ColumnSpec::Index(idx) => {
+                if *idx >= 1 && *idx <= names.len() {
+                    Ok(vec![idx - 1])
+                } else {
+                    Err(format_err!(
+                        "column index {} out of range (file has {} columns)",
+                        idx,
+                        names.len()
+                    ))
+                }
+            }
+            ColumnSpec::Regex(re) => Ok(names
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| re.is_match(n))
+                .map(|(idx, _)| idx)
+                .collect()),
+        }
+    }
+}
+
+impl FromStr for ColumnSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ColumnSpec> {
+        if s.len() >= 2 && s.starts_with('/') && s.ends_with('/') {
+            let re = Regex::new(&s[1..s.len() - 1])
+                .with_context(|_| format!("cannot compile column regex {:?}", s))?;
+            Ok(ColumnSpec::Regex(re))
+        } else if let Ok(idx) = s.parse::<usize>() {
+            Ok(ColumnSpec::Index(idx))
+        } else {
+            Ok(ColumnSpec::Name(s.to_owned()))
+        }
+    }
+}
+
+/// A request to rename a column, specified as `old=new`.
+#[derive(Debug, Clone)]
+pub struct Rename {
+    /// The (cleaned) name of an input column.
+    pub old: String,
+    /// The name to use in our output.
+    pub new: String,
+}
+
+impl FromStr for Rename {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rename> {
+        match s.split_once('=') {
+            Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok(Rename {
+                old: old.to_owned(),
+                new: new.to_owned(),
+            }),
+            _ => Err(format_err!("expected --rename OLD=NEW, found {:?}", s)),
+        }
+    }
+}
+
+/// Decide which input columns to output, and in which order.
+///
+/// Returns `None` if all columns should be output unchanged, which allows
+/// callers to skip the projection step entirely.
+pub fn selected_columns(
+    names: &[String],
+    select: &[ColumnSpec],
+    exclude: &[ColumnSpec],
+) -> Result<Option<Vec<usize>>> {
+    if select.is_empty() && exclude.is_empty() {
+        return Ok(None);
+    }
+
+    // Start with either the explicitly selected columns, or all columns.
+    let mut cols = vec![];
+    if select.is_empty() {
+        cols.extend(0..names.len());
+    } else {
+        for spec in select {
+            for idx in spec.matching_indices(names)? {
+                if !cols.contains(&idx) {
+                    cols.push(idx);
+                }
+            }
+        }
+    }
+
+    // Remove any excluded columns.
+    let mut excluded = vec![];
+    for spec in exclude {
+        excluded.extend(spec.matching_indices(names)?);
+    }
+    cols.retain(|idx| !excluded.contains(idx));
+
+    if cols.is_empty() {
+        return Err(format_err!("no columns selected for output"));
+    }
+    Ok(Some(cols))
+}
+
+/// Apply `renames` to `names`, failing if any of them don't exist.
+pub fn rename_columns(names: &mut [String], renames: &[Rename]) -> Result<()> {
+    for rename in renames {
+        let col = names
+            .iter_mut()
+            .find(|n| **n == rename.old)
+            .ok_or_else(|| {
+                format_err!("cannot rename missing column {:?}", rename.old)
+            })?;
+        *col = rename.new.clone();
+    }
+    Ok(())
+}
+
+#[test]
+fn selects_columns_by_name_index_and_regex() {
+    let names = ["a", "b", "c_1", "c_2"]
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+    let spec = |s: &str| ColumnSpec::from_str(s).unwrap();
+
+    assert!(selected_columns(&names, &[], &[]).unwrap().is_none());
+    assert_eq!(
+        selected_columns(&names, &[spec("/^c_/"), spec("1")], &[])
+            .unwrap()
+            .unwrap(),
+        vec![2, 3, 0],
+    );
+    assert_eq!(
+        selected_columns(&names, &[], &[spec("b"), spec("4")])
+            .unwrap()
+            .unwrap(),
+        vec![0, 2],
+    );
+    assert!(selected_columns(&names, &[spec("z")], &[]).is_err());
+    assert!(selected_columns(&names, &[spec("5")], &[]).is_err());
+    assert!(selected_columns(&names, &[], &[spec("/./")]).is_err());
+}
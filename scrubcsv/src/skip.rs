@@ -0,0 +1,215 @@
+//! Skipping junk lines before the header and footer records after the data.
+
+use csv::ByteRecord;
+use regex::bytes::Regex as BytesRegex;
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Read},
+};
+
+use crate::errors::*;
+
+/// Skip `lines` lines at the start of `input`, and then any lines before the
+/// first one matching `header_re`. Returns a reader positioned at the start
+/// of the next line, and the number of bytes skipped.
+///
+/// This works on raw lines, because preambles often aren't valid CSV.
+pub fn skip_preamble<'a, R: BufRead + Send + 'a>(
+    mut input: R,
+    lines: usize,
+    header_re: Option<&BytesRegex>,
+) -> Result<(Box<dyn Read + Send + 'a>, u64)> {
+    let mut skipped = 0;
+    let mut line = vec![];
+    for _ in 0..lines {
+        line.clear();
+        let count = input
+            .read_until(b'\n', &mut line)
+            .context("cannot read input")?;
+        if count == 0 {
+            break;
+        }
+        skipped += count as u64;
+    }
+    if let Some(header_re) = header_re {
+        loop {
+            line.clear();
+            let count = input
+                .read_until(b'\n', &mut line)
+                .context("cannot read input")?;
+            if count == 0 {
+                return Err(format_err!("no line matched --header-regex"));
+            }
+            let trimmed = line
+                .strip_suffix(b"\n")
+                .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+                .unwrap_or(&line);
+            if header_re.is_match(trimmed) {
+                // Put the header back so our CSV parser can read it.
+                return Ok((Box::new(io::Cursor::new(line).chain(input)), skipped));
+            }
+            skipped += count as u64;
+        }
+    }
+    Ok((Box::new(input), skipped))
+}
+
+/// Holds back records until we know they're not part of a footer.
+///
+/// The footer consists of the last `trailing` records, plus any records
+/// matching `footer_re` immediately before them.
+#[derive(Debug)]
+pub struct FooterFilter {
+    /// Records matching this regex at the end of the input are footers.
+    footer_re: Option<BytesRegex>,
+    /// The number of records to drop at the end of the input.
+    trailing: usize,
+    /// The most recent `trailing` records.
+    delayed: VecDeque<ByteRecord>,
+    /// Records matching `footer_re` which we haven't released yet.
+    matched: Vec<ByteRecord>,
+    /// The input delimiter, used to reconstruct lines for matching.
+    delimiter: u8,
+    /// Scratch space for reconstructing lines.
+    line: Vec<u8>,
+    /// The number of records we've released.
+    released: u64,
+}
+
+impl FooterFilter {
+    /// Create a new `FooterFilter`.
+    pub fn new(
+        footer_re: Option<BytesRegex>,
+        trailing: usize,
+        delimiter: u8,
+    ) -> FooterFilter {
+        FooterFilter {
+            footer_re,
+            trailing,
+            delayed: VecDeque::with_capacity(trailing + 1),
+            matched: vec![],
+            delimiter,
+            line: vec![],
+            released: 0,
+        }
+    }
+
+    /// Add `record` to our filter, passing any records which we know aren't
+    /// part of the footer to `f`.
+    pub fn push<F>(&mut self, record: ByteRecord, mut f: F) -> Result<()>
+    where
+        F: FnMut(&ByteRecord) -> Result<()>,
+    {
+        self.delayed.push_back(record);
+        if self.delayed.len() <= self.trailing {
+            return Ok(());
+        }
+        let record = self.delayed.pop_front().expect("delayed records");
+        if self.is_footer_match(&record) {
+            self.matched.push(record);
+            return Ok(());
+        }
+        // Anything that matched earlier wasn't part of the footer after all.
+        for matched in self.matched.drain(..) {
+            f(&matched)?;
+            self.released += 1;
+        }
+        f(&record)?;
+        self.released += 1;
+        Ok(())
+    }
+
+    /// Call this at the end of the input. If `footer_re` contains a capture
+    /// group named `rows`, make sure it matches the number of records
+    /// before the footer.
+    pub fn finish(mut self) -> Result<()> {
+        let footer_re = match self.footer_re.take() {
+            Some(footer_re) => footer_re,
+            None => return Ok(()),
+        };
+        for record in &self.matched {
+            join_fields(record, self.delimiter, &mut self.line);
+            let rows = match footer_re
+                .captures(&self.line)
+                .and_then(|caps| caps.name("rows"))
+            {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let expected = String::from_utf8_lossy(rows.as_bytes())
+                .trim()
+                .parse::<u64>()
+                .with_context(|_| {
+                    format!("cannot parse footer row count {:?}", rows.as_bytes())
+                })?;
+            if expected != self.released {
+                return Err(format_err!(
+                    "footer says there are {} rows, but we found {}",
+                    expected,
+                    self.released
+                ));
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    /// Does `record` match `footer_re`?
+    fn is_footer_match(&mut self, record: &ByteRecord) -> bool {
+        match &self.footer_re {
+            Some(footer_re) => {
+                join_fields(record, self.delimiter, &mut self.line);
+                footer_re.is_match(&self.line)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Join the fields of `record` using `delimiter`, storing the result in `out`.
+fn join_fields(record: &ByteRecord, delimiter: u8, out: &mut Vec<u8>) {
+    out.clear();
+    for (i, field) in record.iter().enumerate() {
+        if i > 0 {
+            out.push(delimiter);
+        }
+        out.extend_from_slice(field);
+    }
+}
+
+#[test]
+fn skips_preamble_lines() {
+    let input = b"Report\r\nGenerated today\n\nid,name\n1,a\n";
+    let header_re = BytesRegex::new("^id,").unwrap();
+    let (mut rdr, skipped) = skip_preamble(&input[..], 1, Some(&header_re)).unwrap();
+    let mut rest = String::new();
+    rdr.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "id,name\n1,a\n");
+    assert_eq!(skipped, 25);
+}
+
+#[test]
+fn filters_footer_records() {
+    let records = ["1,a", "TOTAL: 9", "2,b", "TOTAL ROWS: 3", "", "end"];
+    let run = |footer_re: &str, trailing| -> Result<Vec<String>> {
+        let footer_re = BytesRegex::new(footer_re).unwrap();
+        let mut filter = FooterFilter::new(Some(footer_re), trailing, b',');
+        let mut released = vec![];
+        for record in &records {
+            let record = ByteRecord::from(record.split(',').collect::<Vec<_>>());
+            filter.push(record, |r| {
+                released.push(String::from_utf8(r.as_slice().to_owned()).unwrap());
+                Ok(())
+            })?;
+        }
+        filter.finish()?;
+        Ok(released)
+    };
+    let expected = vec!["1a", "TOTAL: 9", "2b"];
+    assert_eq!(run(r"^(TOTAL.*|)$", 1).unwrap(), expected);
+    assert_eq!(
+        run(r"^TOTAL ROWS: (?P<rows>\d+)$|^$|^end$", 0).unwrap(),
+        expected,
+    );
+    assert!(run(r"^TOTAL\D*(?P<rows>\d+)$", 4).is_err());
+}
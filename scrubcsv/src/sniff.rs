@@ -0,0 +1,256 @@
+//! Detecting the dialect of our input from a sample.
+//!
+//! We try every combination of our candidate delimiters, quotes and escape
+//! characters, and pick the one which splits the sample into the most
+//! consistent number of columns.
+
+use csv::ByteRecord;
+use log::debug;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+use crate::dialect::InputDialect;
+
+/// Delimiters to try, in order of preference.
+const DELIMITERS: &[u8] = b",\t;|:";
+
+/// Quote characters to try, in order of preference.
+const QUOTES: &[Option<u8>] = &[Some(b'"'), Some(b'\''), None];
+
+/// The input dialect we detected.
+#[derive(Clone, Debug, Serialize)]
+pub struct SniffedDialect {
+    /// Character used to separate fields.
+    #[serde(serialize_with = "serialize_char")]
+    pub delimiter: u8,
+    /// Character used to quote fields, or `None` if fields are not quoted.
+    #[serde(serialize_with = "serialize_opt_char")]
+    pub quote: Option<u8>,
+    /// Character used to escape quotes, or `None` if quotes are doubled.
+    #[serde(serialize_with = "serialize_opt_char")]
+    pub escape: Option<u8>,
+    /// Does the input start with a header row?
+    pub has_headers: bool,
+}
+
+impl SniffedDialect {
+    /// The `InputDialect` to use when parsing our input.
+    pub(crate) fn input_dialect(&self) -> InputDialect {
+        InputDialect {
+            delimiter: self.delimiter,
+            quote: self.quote,
+            escape: self.escape,
+        }
+    }
+}
+
+/// Serialize a character as a one-character string.
+fn serialize_char<S: Serializer>(c: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&char::from(*c))
+}
+
+/// Serialize an optional character as a one-character string or `null`.
+fn serialize_opt_char<S: Serializer>(
+    c: &Option<u8>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match c {
+        Some(c) => serialize_char(c, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// How well a candidate dialect splits our sample. Higher is better.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Score {
+    /// Does the dialect split records into more than one column?
+    multiple_columns: bool,
+    /// How many records per thousand have the most common column count?
+    consistency: usize,
+    /// The most common column count.
+    columns: usize,
+}
+
+/// Detect the dialect of `sample`, which is the start of our input. If
+/// `complete` is false, the last record in `sample` may be truncated. Lines
+/// starting with `comment` are ignored. If `has_headers` is false, we don't
+/// look for a header row.
+///
+/// Returns `fallback` if `sample` contains no records.
+pub(crate) fn sniff(
+    sample: &[u8],
+    complete: bool,
+    comment: Option<u8>,
+    fallback: &InputDialect,
+    has_headers: bool,
+) -> SniffedDialect {
+    // Remove any comment lines, which won't have the right number of columns.
+    let sample = match comment {
+        Some(comment) => sample
+            .split_inclusive(|&b| b == b'\n')
+            .filter(|line| line.first() != Some(&comment))
+            .flatten()
+            .copied()
+            .collect::<Vec<u8>>(),
+        None => sample.to_owned(),
+    };
+
+    // If the sample seems to use backslash escapes, try them first, so they
+    // win any ties.
+    let escapes: &[Option<u8>] = if sample.windows(2).any(|w| w == b"\\\"") {
+        &[Some(b'\\'), None]
+    } else {
+        &[None, Some(b'\\')]
+    };
+
+    // Try each candidate dialect, keeping the first one with the best score.
+    let mut best: Option<(Score, InputDialect, Vec<ByteRecord>)> = None;
+    for &delimiter in DELIMITERS {
+        for &quote in QUOTES {
+            for &escape in escapes {
+                // Escapes only matter inside quoted fields.
+                if quote.is_none() && escape.is_some() {
+                    continue;
+                }
+                let dialect = InputDialect {
+                    delimiter,
+                    quote,
+                    escape,
+                };
+                let records = match parse_sample(&sample, complete, &dialect) {
+                    Some(records) => records,
+                    None => continue,
+                };
+                let score = score(&records);
+                debug!("sniffing {:?}: {:?}", dialect, score);
+                if best.as_ref().is_none_or(|(best, _, _)| score > *best) {
+                    best = Some((score, dialect, records));
+                }
+            }
+        }
+    }
+
+    let (dialect, records) = match best {
+        Some((_, dialect, records)) => (dialect, records),
+        None => (fallback.clone(), vec![]),
+    };
+    SniffedDialect {
+        delimiter: dialect.delimiter,
+        quote: dialect.quote,
+        escape: dialect.escape,
+        has_headers: has_headers && looks_like_header(&records),
+    }
+}
+
+/// Parse `sample` using `dialect`, dropping the last record if `sample` is
+/// not `complete`. Returns `None` if there are no records.
+fn parse_sample(
+    sample: &[u8],
+    complete: bool,
+    dialect: &InputDialect,
+) -> Option<Vec<ByteRecord>> {
+    let mut rdr = dialect.csv_reader(sample, false);
+    let mut records = vec![];
+    let mut record = ByteRecord::new();
+    while rdr.read_byte_record(&mut record).ok()? {
+        records.push(record.clone());
+    }
+    if !complete {
+        records.pop();
+    }
+    if records.is_empty() {
+        None
+    } else {
+        Some(records)
+    }
+}
+
+/// Score how consistently `records` were split into columns.
+fn score(records: &[ByteRecord]) -> Score {
+    let mut counts = BTreeMap::new();
+    for record in records {
+        *counts.entry(record.len()).or_insert(0) += 1;
+    }
+    // Prefer more columns if two counts are equally common.
+    let (columns, count) = counts
+        .into_iter()
+        .max_by_key(|&(columns, count)| (count, columns))
+        .unwrap_or((0, 0));
+    Score {
+        multiple_columns: columns > 1,
+        consistency: count * 1000 / records.len().max(1),
+        columns,
+    }
+}
+
+/// Guess whether the first record is a header. We compare each column's
+/// first value to the rest of the column: a text value above numbers, or a
+/// value whose length differs from a column of fixed-length values, suggests
+/// a header. Without any evidence either way, we assume there's a header.
+fn looks_like_header(records: &[ByteRecord]) -> bool {
+    let (first, rest) = match records.split_first() {
+        Some((first, rest)) if !rest.is_empty() => (first, rest),
+        _ => return true,
+    };
+    let mut votes = 0i32;
+    for (col, name) in first.iter().enumerate() {
+        let values = rest
+            .iter()
+            .filter_map(|record| record.get(col))
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<&[u8]>>();
+        if values.is_empty() {
+            continue;
+        }
+        if values.iter().all(|value| is_number(value)) {
+            votes += if is_number(name) { -1 } else { 1 };
+        } else if values.iter().all(|value| value.len() == values[0].len()) {
+            votes += if name.len() == values[0].len() { -1 } else { 1 };
+        }
+    }
+    votes >= 0
+}
+
+/// Does `value` look like a number?
+fn is_number(value: &[u8]) -> bool {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .is_some()
+}
+
+#[test]
+fn sniffs_dialects() {
+    let fallback = InputDialect {
+        delimiter: b',',
+        quote: Some(b'"'),
+        escape: None,
+    };
+    let sniffed = sniff(b"a;b;c\n1;2;3\n4;5", false, None, &fallback, true);
+    assert_eq!(sniffed.delimiter, b';');
+    assert_eq!(sniffed.quote, Some(b'"'));
+    assert_eq!(sniffed.escape, None);
+    assert!(sniffed.has_headers);
+
+    let sniffed = sniff(
+        b"# comment\n1\t'x\ty'\n2\t'zz'\n3\t'z'\n",
+        true,
+        Some(b'#'),
+        &fallback,
+        true,
+    );
+    assert_eq!(sniffed.delimiter, b'\t');
+    assert_eq!(sniffed.quote, Some(b'\''));
+    assert!(!sniffed.has_headers);
+
+    let sniffed = sniff(
+        b"a,b\n\"x\\\",y\",1\n\"z\",2\n",
+        true,
+        None,
+        &fallback,
+        false,
+    );
+    assert_eq!(sniffed.delimiter, b',');
+    assert_eq!(sniffed.escape, Some(b'\\'));
+    assert!(!sniffed.has_headers);
+}
@@ -0,0 +1,360 @@
+//! Statistics about rejected rows and the values in each column.
+
+use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::BuildHasherDefault,
+};
+
+use crate::clean_values::Cleanups;
+use crate::sniff::SniffedDialect;
+
+/// The precision of our HyperLogLog sketches. This uses up to 16 KiB per
+/// column, with a typical error of about 0.8%.
+const HLL_PRECISION: u8 = 14;
+
+/// A HyperLogLog sketch for estimating the number of distinct values. We use
+/// a hasher with fixed keys, so that sketches from different threads can be
+/// merged. We insert `[u8]` values, which hash the same way as `Vec<u8>`.
+type DistinctSketch = HyperLogLogPlus<Vec<u8>, BuildHasherDefault<DefaultHasher>>;
+
+/// Statistics about a run, returned by `Scrubber`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+    /// The number of rows read, including the input's header row, if any.
+    pub rows: u64,
+    /// The number of rows rejected as bad. This does not include duplicates.
+    pub bad_rows: u64,
+    /// The number of rows dropped as duplicates.
+    pub duplicate_rows: u64,
+    /// The number of comment lines removed.
+    pub comment_lines: u64,
+    /// The time since the `Scrubber` was created.
+    pub elapsed_seconds: f64,
+    /// The number of input bytes processed, including skipped lines. For
+    /// compressed input, this is the uncompressed size.
+    pub bytes_processed: u64,
+    /// `bytes_processed` divided by `elapsed_seconds`.
+    pub bytes_per_second: i64,
+    /// The number of compressed bytes read, if our input was compressed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compressed_bytes_processed: Option<u64>,
+    /// The number of rows rejected for each reason.
+    pub rejections: Rejections,
+    /// For rows with the wrong number of columns, how many columns did they
+    /// have?
+    pub bad_row_column_counts: BTreeMap<usize, u64>,
+    /// Statistics about the output values in each input column, if
+    /// requested using `ScrubOptions::profile_columns`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<ColumnProfile>>,
+    /// The input dialect we detected, if requested using
+    /// `ScrubOptions::sniff`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dialect: Option<SniffedDialect>,
+    /// How we sampled our input, if we only checked or output some of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleStats>,
+}
+
+/// Statistics about a sampled run.
+#[derive(Clone, Debug, Serialize)]
+pub struct SampleStats {
+    /// The maximum number of rows to output, from `ScrubOptions::limit`.
+    pub limit: Option<u64>,
+    /// The fraction of input rows to check, from
+    /// `ScrubOptions::sample_rate`.
+    pub sample_rate: Option<f64>,
+    /// The number of rows to output, from `ScrubOptions::reservoir`.
+    pub reservoir: Option<usize>,
+    /// The random seed used to choose rows.
+    pub seed: u64,
+    /// Did we read all of our input, or stop early?
+    pub read_all_input: bool,
+    /// The number of input rows we checked, not including the header.
+    pub rows_checked: u64,
+    /// `bad_rows` divided by `rows_checked`, which estimates the fraction of
+    /// bad rows in the entire input.
+    pub bad_row_ratio: f64,
+    /// The estimated number of rows in the entire input, not including the
+    /// header, if we know how much of our input we read.
+    pub estimated_total_rows: Option<u64>,
+    /// The estimated number of bad rows in the entire input.
+    pub estimated_bad_rows: Option<u64>,
+}
+
+/// The number of rows rejected for each reason.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Rejections {
+    /// Rows with the wrong number of columns.
+    pub wrong_column_count: u64,
+    /// Rows with an empty value in a `--drop-row-if-null` column.
+    pub missing_required_value: u64,
+    /// Rows with a value which a transform rule could not normalize, or which
+    /// didn't match the type declared in our schema.
+    pub invalid_value: u64,
+    /// Rows with a value longer than `--max-field-bytes`.
+    pub field_too_long: u64,
+    /// Rows longer than `--max-row-bytes`.
+    pub row_too_long: u64,
+    /// Rows which duplicated another row's key.
+    pub duplicate: u64,
+}
+
+/// Statistics about the output values in a single column.
+#[derive(Clone, Debug, Serialize)]
+pub struct ColumnProfile {
+    /// The cleaned name of the column.
+    pub name: String,
+    /// The number of empty values, including values matching `--null`.
+    pub empty: u64,
+    /// The number of values matching `--null`.
+    pub null_matches: u64,
+    /// The number of values changed by `--trim-whitespace`.
+    pub trimmed: u64,
+    /// The number of values changed by `--replace-newlines`.
+    pub newlines_replaced: u64,
+    /// The number of values changed by `--strip-control-chars`.
+    pub control_chars_stripped: u64,
+    /// The number of values changed by `--unicode-normalize`.
+    pub unicode_normalized: u64,
+    /// The number of values truncated by `--max-field-bytes`.
+    pub truncated: u64,
+    /// The length of the shortest value, in bytes.
+    pub min_length: Option<usize>,
+    /// The length of the longest value, in bytes.
+    pub max_length: Option<usize>,
+    /// An estimate of the number of distinct values.
+    pub approx_distinct: u64,
+}
+
+/// Collects statistics about our rows. Each worker thread keeps its own copy,
+/// and we merge them at the end.
+#[derive(Clone, Debug)]
+pub struct StatsCollector {
+    /// The number of rows rejected for each reason.
+    pub rejections: Rejections,
+    /// For rows with the wrong number of columns, how many columns did they
+    /// have?
+    pub bad_row_column_counts: BTreeMap<usize, u64>,
+    /// Statistics about each input column, if we're collecting them.
+    pub columns: Option<Vec<ColumnStats>>,
+}
+
+impl StatsCollector {
+    /// Create a new `StatsCollector`. If `profile_cols` is specified, collect
+    /// statistics about that many columns.
+    pub fn new(profile_cols: Option<usize>) -> StatsCollector {
+        StatsCollector {
+            rejections: Rejections::default(),
+            bad_row_column_counts: BTreeMap::new(),
+            columns: profile_cols.map(|count| vec![ColumnStats::new(); count]),
+        }
+    }
+
+    /// The number of rows which we rejected as bad. This does not include
+    /// duplicates.
+    pub fn bad_rows(&self) -> u64 {
+        self.rejections.wrong_column_count
+            + self.rejections.missing_required_value
+            + self.rejections.invalid_value
+            + self.rejections.field_too_long
+            + self.rejections.row_too_long
+    }
+
+    /// Record a row with `count` columns, which was the wrong number.
+    pub fn record_wrong_column_count(&mut self, count: usize) {
+        self.rejections.wrong_column_count += 1;
+        *self.bad_row_column_counts.entry(count).or_insert(0) += 1;
+    }
+
+    /// Are we collecting statistics about each column?
+    pub fn profiles_columns(&self) -> bool {
+        self.columns.is_some()
+    }
+
+    /// Record the cleaned `values` of an output row, and the `cleanups` which
+    /// produced them.
+    pub fn record_row(&mut self, values: &[Cow<'_, [u8]>], cleanups: &[Cleanups]) {
+        if let Some(columns) = &mut self.columns {
+            for ((col, value), cleanups) in
+                columns.iter_mut().zip(values).zip(cleanups)
+            {
+                col.record_value(value, *cleanups);
+            }
+        }
+    }
+
+    /// Merge `other` into this `StatsCollector`.
+    pub fn merge(&mut self, other: &StatsCollector) {
+        let rejections = &mut self.rejections;
+        rejections.wrong_column_count += other.rejections.wrong_column_count;
+        rejections.missing_required_value += other.rejections.missing_required_value;
+        rejections.invalid_value += other.rejections.invalid_value;
+        rejections.field_too_long += other.rejections.field_too_long;
+        rejections.row_too_long += other.rejections.row_too_long;
+        rejections.duplicate += other.rejections.duplicate;
+        for (&count, &rows) in &other.bad_row_column_counts {
+            *self.bad_row_column_counts.entry(count).or_insert(0) += rows;
+        }
+        if let (Some(columns), Some(other_columns)) =
+            (&mut self.columns, &other.columns)
+        {
+            for (col, other_col) in columns.iter_mut().zip(other_columns) {
+                col.merge(other_col);
+            }
+        }
+    }
+
+    /// Summarize our column statistics, using `names` for our columns.
+    pub fn column_profiles(&mut self, names: &[String]) -> Option<Vec<ColumnProfile>> {
+        self.columns.as_mut().map(|columns| {
+            columns
+                .iter_mut()
+                .zip(names)
+                .map(|(col, name)| col.profile(name))
+                .collect()
+        })
+    }
+}
+
+/// Collects statistics about the output values in a single column.
+#[derive(Clone, Debug)]
+pub struct ColumnStats {
+    /// The number of empty values, including values matching `--null`.
+    empty: u64,
+    /// The number of values matching `--null`.
+    null_matches: u64,
+    /// The number of values changed by `--trim-whitespace`.
+    trimmed: u64,
+    /// The number of values changed by `--replace-newlines`.
+    newlines_replaced: u64,
+    /// The number of values changed by `--strip-control-chars`.
+    control_chars_stripped: u64,
+    /// The number of values changed by `--unicode-normalize`.
+    unicode_normalized: u64,
+    /// The number of values truncated by `--max-field-bytes`.
+    truncated: u64,
+    /// The length of the shortest and longest values, in bytes.
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    /// An estimate of the number of distinct values.
+    distinct: DistinctSketch,
+}
+
+impl ColumnStats {
+    /// Create a new `ColumnStats`.
+    fn new() -> ColumnStats {
+        ColumnStats {
+            empty: 0,
+            null_matches: 0,
+            trimmed: 0,
+            newlines_replaced: 0,
+            control_chars_stripped: 0,
+            unicode_normalized: 0,
+            truncated: 0,
+            min_length: None,
+            max_length: None,
+            distinct: DistinctSketch::new(
+                HLL_PRECISION,
+                BuildHasherDefault::default(),
+            )
+            .expect("invalid HyperLogLog precision"),
+        }
+    }
+
+    /// Record a single value.
+    fn record_value(&mut self, value: &[u8], cleanups: Cleanups) {
+        if value.is_empty() {
+            self.empty += 1;
+        }
+        self.null_matches += u64::from(cleanups.null);
+        self.trimmed += u64::from(cleanups.trimmed);
+        self.newlines_replaced += u64::from(cleanups.newlines);
+        self.control_chars_stripped += u64::from(cleanups.control_chars);
+        self.unicode_normalized += u64::from(cleanups.unicode_normalized);
+        self.truncated += u64::from(cleanups.truncated);
+        self.min_length =
+            Some(self.min_length.map_or(value.len(), |l| l.min(value.len())));
+        self.max_length =
+            Some(self.max_length.map_or(value.len(), |l| l.max(value.len())));
+        self.distinct.insert(value);
+    }
+
+    /// Merge `other` into this `ColumnStats`.
+    fn merge(&mut self, other: &ColumnStats) {
+        self.empty += other.empty;
+        self.null_matches += other.null_matches;
+        self.trimmed += other.trimmed;
+        self.newlines_replaced += other.newlines_replaced;
+        self.control_chars_stripped += other.control_chars_stripped;
+        self.unicode_normalized += other.unicode_normalized;
+        self.truncated += other.truncated;
+        self.min_length = match (self.min_length, other.min_length) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max_length = self.max_length.max(other.max_length);
+        self.distinct
+            .merge(&other.distinct)
+            .expect("HyperLogLog precisions should match");
+    }
+
+    /// Summarize these statistics.
+    fn profile(&mut self, name: &str) -> ColumnProfile {
+        ColumnProfile {
+            name: name.to_owned(),
+            empty: self.empty,
+            null_matches: self.null_matches,
+            trimmed: self.trimmed,
+            newlines_replaced: self.newlines_replaced,
+            control_chars_stripped: self.control_chars_stripped,
+            unicode_normalized: self.unicode_normalized,
+            truncated: self.truncated,
+            min_length: self.min_length,
+            max_length: self.max_length,
+            approx_distinct: self.distinct.count().round() as u64,
+        }
+    }
+}
+
+#[test]
+fn merges_stats() {
+    let mut stats = StatsCollector::new(Some(2));
+    let mut other = stats.clone();
+    let cleanups = [Cleanups::default(); 2];
+    for i in 0..1000 {
+        let values = [
+            Cow::Owned(i.to_string().into_bytes()),
+            Cow::Borrowed(&b""[..]),
+        ];
+        if i % 2 == 0 {
+            stats.record_row(&values, &cleanups);
+        } else {
+            other.record_row(&values, &cleanups);
+        }
+    }
+    stats.record_wrong_column_count(3);
+    other.record_wrong_column_count(3);
+    other.record_wrong_column_count(1);
+    stats.merge(&other);
+
+    assert_eq!(stats.rejections.wrong_column_count, 3);
+    let counts = stats
+        .bad_row_column_counts
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![(1, 1), (3, 2)]);
+    let columns = stats
+        .column_profiles(&["a".to_owned(), "b".to_owned()])
+        .unwrap();
+    assert_eq!(columns[0].min_length, Some(1));
+    assert_eq!(columns[0].max_length, Some(3));
+    assert!((990..=1010).contains(&columns[0].approx_distinct));
+    assert_eq!(columns[1].name, "b");
+    assert_eq!(columns[1].empty, 1000);
+    assert_eq!(columns[1].approx_distinct, 1);
+}
@@ -0,0 +1,360 @@
+//! Per-column value transforms, loaded from a rules file.
+//!
+//! A rules file is either TOML or JSON (chosen by extension), and contains a
+//! list of rules:
+//!
+//! ```toml
+//! [[rules]]
+//! columns = ["phone"]
+//! replace = { regex = "[^0-9]", with = "" }
+//!
+//! [[rules]]
+//! columns = ["/_amount$/"]
+//! null = "N/A|n\\.a\\."
+//!
+//! [[rules]]
+//! trim = true
+//! truncate = 255
+//!
+//! [[rules]]
+//! columns = ["price"]
+//! number = { decimal = "," }
+//! on_invalid = "null"
+//!
+//! [[rules]]
+//! columns = ["active"]
+//! boolean = { truthy = ["yes", "y"], falsy = ["no", "n"] }
+//!
+//! [[rules]]
+//! columns = ["signup_date"]
+//! date = { formats = ["%m/%d/%Y", "%Y-%m-%d"] }
+//! ```
+//!
+//! Rules without `columns` apply to every column. Within a rule, operations
+//! are applied in the order `null`, `trim`, `replace`, `case`, `number` (or
+//! `boolean` or `date`), `truncate`. Rules are applied in the order they
+//! appear in the file.
+//!
+//! `number` removes currency symbols and thousands separators (which must
+//! separate groups of 3 digits), and rejects whitespace inside the number
+//! unless it's next to a currency symbol. Negative numbers use a sign or
+//! parentheses, but not both. `boolean` outputs `true` or `false`, and
+//! `date` converts each of the specified `strftime` formats to an ISO 8601
+//! date, or to a timestamp if any of the formats includes a time. Empty
+//! values are left alone. `on_invalid` controls what happens to other values
+//! which can't be parsed: they can become `"null"`, `"drop"` the row (the
+//! default), or `"fail"` with an error.
+
+use regex::bytes::Regex as BytesRegex;
+use serde::Deserialize;
+use std::{borrow::Cow, fs, path::Path, str::FromStr};
+
+use crate::clean_values::trim_ascii_whitespace;
+use crate::errors::*;
+use crate::normalize::{
+    BooleanConfig, DateConfig, Normalizer, NumberConfig, OnInvalid,
+};
+use crate::schema::ColumnType;
+use crate::select::ColumnSpec;
+use crate::util::truncate_bytes;
+
+/// The contents of a rules file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformRules {
+    /// Our rules, in the order they should be applied.
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+/// A single rule in a rules file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    /// The columns to which this rule applies, using the same syntax as
+    /// `--select`. Defaults to all columns.
+    columns: Option<Vec<String>>,
+    /// Convert values matching this regex to empty strings.
+    null: Option<String>,
+    /// Remove whitespace at beginning and end of each value.
+    #[serde(default)]
+    trim: bool,
+    /// Replace all matches of a regex.
+    replace: Option<ReplaceConfig>,
+    /// Change the case of the value.
+    case: Option<Case>,
+    /// Truncate the value to at most this many bytes.
+    truncate: Option<usize>,
+    /// Normalize numbers.
+    number: Option<NumberConfig>,
+    /// Normalize booleans.
+    boolean: Option<BooleanConfig>,
+    /// Normalize dates and timestamps.
+    date: Option<DateConfig>,
+    /// What to do with values which can't be normalized.
+    #[serde(default)]
+    on_invalid: OnInvalid,
+}
+
+/// A regex replacement.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplaceConfig {
+    /// The regex to search for.
+    regex: String,
+    /// The replacement, which may refer to capture groups using `$1` or
+    /// `${name}`.
+    with: String,
+}
+
+/// Case conversions.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Case {
+    Upper,
+    Lower,
+}
+
+impl TransformRules {
+    /// Load rules from a TOML or JSON file.
+    pub fn from_path(path: &Path) -> Result<TransformRules> {
+        let data = fs::read_to_string(path)
+            .with_context(|_| format!("cannot read {}", path.display()))?;
+        let is_toml = path.extension().map(|ext| ext == "toml").unwrap_or(false);
+        if is_toml {
+            toml::from_str(&data)
+                .with_context(|_| format!("cannot parse {}", path.display()))
+        } else {
+            serde_json::from_str(&data)
+                .with_context(|_| format!("cannot parse {}", path.display()))
+        }
+    }
+
+    /// Compile these rules for a file with the specified column names.
+    pub fn compile(&self, names: &[String]) -> Result<ColumnTransforms> {
+        let mut transforms = vec![];
+        let mut by_column = vec![vec![]; names.len()];
+        for config in &self.rules {
+            let transform = Transform::from_config(config)?;
+            if let Some(columns) = &config.columns {
+                for col in columns {
+                    let spec = ColumnSpec::from_str(col)?;
+                    for idx in spec.matching_indices(names)? {
+                        by_column[idx].push(transforms.len());
+                    }
+                }
+            } else {
+                for col_transforms in &mut by_column {
+                    col_transforms.push(transforms.len());
+                }
+            }
+            transforms.push(transform);
+        }
+        Ok(ColumnTransforms {
+            transforms,
+            by_column,
+        })
+    }
+}
+
+/// A compiled rule.
+#[derive(Debug)]
+struct Transform {
+    null_re: Option<BytesRegex>,
+    trim: bool,
+    replace: Option<(BytesRegex, Vec<u8>)>,
+    case: Option<Case>,
+    normalizer: Option<Normalizer>,
+    on_invalid: OnInvalid,
+    truncate: Option<usize>,
+}
+
+/// A value which one of our normalizers could not parse.
+#[derive(Debug)]
+pub struct InvalidValue {
+    /// Should we fail with an error, instead of dropping the row?
+    pub fail: bool,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl Transform {
+    /// Compile a rule from a rules file.
+    fn from_config(config: &RuleConfig) -> Result<Transform> {
+        let null_re = match &config.null {
+            Some(null) => Some(
+                BytesRegex::new(&format!("^{}$", null))
+                    .context("can't compile regular expression")?,
+            ),
+            None => None,
+        };
+        let replace = match &config.replace {
+            Some(replace) => Some((
+                BytesRegex::new(&replace.regex)
+                    .context("can't compile regular expression")?,
+                replace.with.as_bytes().to_owned(),
+            )),
+            None => None,
+        };
+        Ok(Transform {
+            null_re,
+            trim: config.trim,
+            replace,
+            case: config.case,
+            normalizer: Normalizer::from_config(
+                config.number.as_ref(),
+                config.boolean.as_ref(),
+                config.date.as_ref(),
+            )?,
+            on_invalid: config.on_invalid,
+            truncate: config.truncate,
+        })
+    }
+
+    /// Can this transform reject a value?
+    fn can_reject(&self) -> bool {
+        self.normalizer.is_some() && self.on_invalid != OnInvalid::Null
+    }
+
+    /// Apply this transform to `val`.
+    fn apply<'a>(
+        &self,
+        mut val: Cow<'a, [u8]>,
+    ) -> std::result::Result<Cow<'a, [u8]>, InvalidValue> {
+        if let Some(null_re) = &self.null_re {
+            if null_re.is_match(&val) {
+                val = Cow::Borrowed(&[]);
+            }
+        }
+        if self.trim {
+            val = match val {
+                Cow::Borrowed(v) => Cow::Borrowed(trim_ascii_whitespace(v)),
+                Cow::Owned(v) => Cow::Owned(trim_ascii_whitespace(&v).to_owned()),
+            };
+        }
+        if let Some((re, with)) = &self.replace {
+            if let Cow::Owned(replaced) = re.replace_all(&val, &with[..]) {
+                val = Cow::Owned(replaced);
+            }
+        }
+        if let Some(case) = self.case {
+            val = Cow::Owned(match (case, std::str::from_utf8(&val)) {
+                (Case::Upper, Ok(s)) => s.to_uppercase().into_bytes(),
+                (Case::Lower, Ok(s)) => s.to_lowercase().into_bytes(),
+                (Case::Upper, Err(_)) => val.to_ascii_uppercase(),
+                (Case::Lower, Err(_)) => val.to_ascii_lowercase(),
+            });
+        }
+        if let Some(normalizer) = &self.normalizer {
+            if !val.is_empty() {
+                val = match normalizer.normalize(&val) {
+                    Ok(normalized) => Cow::Owned(normalized),
+                    Err(_) if self.on_invalid == OnInvalid::Null => Cow::Borrowed(&[]),
+                    Err(message) => {
+                        return Err(InvalidValue {
+                            fail: self.on_invalid == OnInvalid::Fail,
+                            message,
+                        })
+                    }
+                };
+            }
+        }
+        if let Some(max_len) = self.truncate {
+            if val.len() > max_len {
+                val = match val {
+                    Cow::Borrowed(v) => Cow::Borrowed(truncate_bytes(v, max_len)),
+                    Cow::Owned(v) => {
+                        Cow::Owned(truncate_bytes(&v, max_len).to_owned())
+                    }
+                };
+            }
+        }
+        Ok(val)
+    }
+}
+
+/// Transforms to apply to each column of a specific file.
+#[derive(Debug, Default)]
+pub struct ColumnTransforms {
+    /// Our compiled transforms.
+    transforms: Vec<Transform>,
+    /// For each column, the indices of the transforms to apply.
+    by_column: Vec<Vec<usize>>,
+}
+
+impl ColumnTransforms {
+    /// Are there any transforms to apply?
+    pub fn is_empty(&self) -> bool {
+        self.by_column.iter().all(|col| col.is_empty())
+    }
+
+    /// Can any of our transforms reject a value?
+    pub fn can_reject(&self) -> bool {
+        self.transforms.iter().any(|t| t.can_reject())
+    }
+
+    /// The type of the values in column `col`, if one of our transforms
+    /// normalizes them.
+    pub fn column_type(&self, col: usize) -> Option<ColumnType> {
+        self.by_column
+            .get(col)?
+            .iter()
+            .rev()
+            .find_map(|&idx| self.transforms[idx].normalizer.as_ref())
+            .map(|normalizer| normalizer.column_type())
+    }
+
+    /// Apply all the transforms for column `col` to `val`.
+    pub fn apply<'a>(
+        &self,
+        col: usize,
+        mut val: Cow<'a, [u8]>,
+    ) -> std::result::Result<Cow<'a, [u8]>, InvalidValue> {
+        if let Some(col_transforms) = self.by_column.get(col) {
+            for &idx in col_transforms {
+                val = self.transforms[idx].apply(val)?;
+            }
+        }
+        Ok(val)
+    }
+}
+
+#[test]
+fn applies_column_transforms() {
+    let rules: TransformRules = toml::from_str(
+        r#"
+[[rules]]
+columns = ["phone"]
+replace = { regex = "[^0-9]", with = "" }
+
+[[rules]]
+columns = ["/^st/"]
+trim = true
+case = "upper"
+
+[[rules]]
+null = "N/A"
+truncate = 4
+"#,
+    )
+    .unwrap();
+    let names = ["phone", "state", "other"]
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+    let transforms = rules.compile(&names).unwrap();
+    let apply = |col, val: &str| {
+        String::from_utf8(
+            transforms
+                .apply(col, Cow::Borrowed(val.as_bytes()))
+                .unwrap()
+                .into_owned(),
+        )
+        .unwrap()
+    };
+    assert_eq!(apply(0, "(202) 555"), "2025");
+    assert_eq!(apply(1, " ny "), "NY");
+    assert_eq!(apply(2, "N/A"), "");
+    assert_eq!(apply(2, " ny "), " ny ");
+    assert_eq!(apply(2, "héé"), "hé");
+}
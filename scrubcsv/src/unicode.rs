@@ -0,0 +1,134 @@
+//! Unicode-aware cleanups for values.
+//!
+//! We work on any ASCII-compatible encoding, so values which aren't valid
+//! UTF-8 only get the ASCII part of each cleanup.
+
+use std::str::FromStr;
+use unicode_normalization::{
+    is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization,
+};
+
+use crate::clean_values::trim_ascii_whitespace;
+use crate::errors::*;
+
+/// A Unicode normalization form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Canonical composition, which combines characters like "e" followed by
+    /// a combining accent into a single character.
+    Nfc,
+    /// Compatibility composition, which also replaces characters like "ﬁ"
+    /// and non-breaking spaces with their plain equivalents.
+    Nfkc,
+}
+
+impl FromStr for UnicodeForm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UnicodeForm> {
+        match s {
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfkc" => Ok(UnicodeForm::Nfkc),
+            _ => Err(format_err!("expected \"nfc\" or \"nfkc\", found {:?}", s)),
+        }
+    }
+}
+
+/// Should `--strip-control-chars` remove `c`? We keep tabs and newlines,
+/// which `--replace-newlines` handles.
+fn is_stripped_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        // Zero-width spaces, word joiners and byte order marks.
+        '\u{200B}' | '\u{2060}' | '\u{FEFF}' => true,
+        _ => c.is_control(),
+    }
+}
+
+/// Remove control characters, zero-width spaces and byte order marks from
+/// `val`. Returns `None` if there was nothing to remove.
+pub(crate) fn strip_control_chars(val: &[u8]) -> Option<Vec<u8>> {
+    match std::str::from_utf8(val) {
+        Ok(s) => {
+            if !s.chars().any(is_stripped_char) {
+                return None;
+            }
+            Some(
+                s.chars()
+                    .filter(|&c| !is_stripped_char(c))
+                    .collect::<String>()
+                    .into_bytes(),
+            )
+        }
+        Err(_) => {
+            let is_stripped =
+                |b: &u8| b.is_ascii() && is_stripped_char(char::from(*b));
+            if !val.iter().any(is_stripped) {
+                return None;
+            }
+            Some(val.iter().filter(|b| !is_stripped(b)).copied().collect())
+        }
+    }
+}
+
+/// Convert `val` to the normalization form `form`. Returns `None` if `val`
+/// is already normalized, or isn't valid UTF-8.
+pub(crate) fn normalize_unicode(val: &[u8], form: UnicodeForm) -> Option<Vec<u8>> {
+    let s = std::str::from_utf8(val).ok()?;
+    let quick = match form {
+        UnicodeForm::Nfc => is_nfc_quick(s.chars()),
+        UnicodeForm::Nfkc => is_nfkc_quick(s.chars()),
+    };
+    if quick == IsNormalized::Yes {
+        return None;
+    }
+    let normalized = match form {
+        UnicodeForm::Nfc => s.nfc().collect::<String>(),
+        UnicodeForm::Nfkc => s.nfkc().collect::<String>(),
+    };
+    if normalized == s {
+        None
+    } else {
+        Some(normalized.into_bytes())
+    }
+}
+
+/// Remove Unicode whitespace, zero-width spaces and byte order marks from the
+/// beginning and end of `val`.
+pub(crate) fn trim_unicode_whitespace(val: &[u8]) -> &[u8] {
+    match std::str::from_utf8(val) {
+        Ok(s) => s
+            .trim_matches(|c: char| {
+                c.is_whitespace() || matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}')
+            })
+            .as_bytes(),
+        Err(_) => trim_ascii_whitespace(val),
+    }
+}
+
+#[test]
+fn cleans_unicode() {
+    assert_eq!(
+        strip_control_chars("a\0b\u{0B}\u{200B}c\td\u{85}".as_bytes()).unwrap(),
+        b"abc\td"
+    );
+    assert_eq!(strip_control_chars(b"\xff\x00a").unwrap(), b"\xffa");
+    assert_eq!(strip_control_chars("café\n".as_bytes()), None);
+
+    let nfd = "cafe\u{301}".as_bytes();
+    assert_eq!(
+        normalize_unicode(nfd, UnicodeForm::Nfc).unwrap(),
+        "café".as_bytes()
+    );
+    assert_eq!(normalize_unicode("café".as_bytes(), UnicodeForm::Nfc), None);
+    assert_eq!(
+        normalize_unicode("ﬁ\u{A0}x".as_bytes(), UnicodeForm::Nfkc).unwrap(),
+        b"fi x"
+    );
+
+    assert_eq!(
+        trim_unicode_whitespace("\u{A0}\u{FEFF} a b\u{3000}".as_bytes()),
+        b"a b"
+    );
+    assert_eq!(trim_unicode_whitespace(b" \xff "), b"\xff");
+}
@@ -36,6 +36,140 @@ impl FromStr for CharSpecifier {
     }
 }
 
+/// Specifies our input delimiter, or asks us to detect it automatically.
+#[derive(Debug, Clone)]
+pub enum DelimiterSpecifier {
+    /// Detect the delimiter by sniffing the input.
+    Auto,
+    /// Use the specified delimiter.
+    Char(CharSpecifier),
+}
+
+impl FromStr for DelimiterSpecifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DelimiterSpecifier> {
+        match s {
+            "auto" => Ok(DelimiterSpecifier::Auto),
+            _ => Ok(DelimiterSpecifier::Char(s.parse()?)),
+        }
+    }
+}
+
+/// Truncate `val` to at most `max_len` bytes. If `val` contains UTF-8, we
+/// make sure not to split a multibyte character.
+pub fn truncate_bytes(val: &[u8], max_len: usize) -> &[u8] {
+    if val.len() <= max_len {
+        return val;
+    }
+    // Back up until we're not in the middle of a UTF-8 continuation byte.
+    let mut len = max_len;
+    while len > 0 && (val[len] & 0b1100_0000) == 0b1000_0000 {
+        len -= 1;
+    }
+    &val[..len]
+}
+
+/// How should we quote fields in our output?
+#[derive(Debug, Clone, Copy)]
+pub enum OutputQuoteStyle {
+    /// Only quote fields which contain delimiters, quotes or newlines.
+    Necessary,
+    /// Quote every field.
+    Always,
+    /// Quote every field that doesn't look like a number.
+    NonNumeric,
+    /// Never quote anything, even if that produces invalid CSV.
+    Never,
+}
+
+impl OutputQuoteStyle {
+    /// Convert to the equivalent `csv` type.
+    pub fn to_csv(self) -> csv::QuoteStyle {
+        match self {
+            OutputQuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            OutputQuoteStyle::Always => csv::QuoteStyle::Always,
+            OutputQuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            OutputQuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+
+    /// Convert to the equivalent `csv_core` type.
+    pub fn to_csv_core(self) -> csv_core::QuoteStyle {
+        match self {
+            OutputQuoteStyle::Necessary => csv_core::QuoteStyle::Necessary,
+            OutputQuoteStyle::Always => csv_core::QuoteStyle::Always,
+            OutputQuoteStyle::NonNumeric => csv_core::QuoteStyle::NonNumeric,
+            OutputQuoteStyle::Never => csv_core::QuoteStyle::Never,
+        }
+    }
+}
+
+impl FromStr for OutputQuoteStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputQuoteStyle> {
+        match s {
+            "necessary" => Ok(OutputQuoteStyle::Necessary),
+            "always" => Ok(OutputQuoteStyle::Always),
+            "non-numeric" => Ok(OutputQuoteStyle::NonNumeric),
+            "never" => Ok(OutputQuoteStyle::Never),
+            _ => Err(format_err!("invalid quote style: {:?}", s)),
+        }
+    }
+}
+
+/// What should we use to terminate output records?
+#[derive(Debug, Clone, Copy)]
+pub enum OutputTerminator {
+    /// A `CRLF` sequence, as recommended by RFC 4180.
+    Crlf,
+    /// A single byte, typically `LF`.
+    Byte(u8),
+}
+
+impl OutputTerminator {
+    /// Convert to the equivalent `csv` type.
+    pub fn to_csv(self) -> csv::Terminator {
+        match self {
+            OutputTerminator::Crlf => csv::Terminator::CRLF,
+            OutputTerminator::Byte(b) => csv::Terminator::Any(b),
+        }
+    }
+
+    /// Convert to the equivalent `csv_core` type.
+    pub fn to_csv_core(self) -> csv_core::Terminator {
+        match self {
+            OutputTerminator::Crlf => csv_core::Terminator::CRLF,
+            OutputTerminator::Byte(b) => csv_core::Terminator::Any(b),
+        }
+    }
+
+    /// The bytes used to terminate a record.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            OutputTerminator::Crlf => b"\r\n",
+            OutputTerminator::Byte(b) => std::slice::from_ref(b),
+        }
+    }
+}
+
+impl FromStr for OutputTerminator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputTerminator> {
+        match s {
+            "lf" | r"\n" => Ok(OutputTerminator::Byte(b'\n')),
+            "crlf" | r"\r\n" => Ok(OutputTerminator::Crlf),
+            "cr" | r"\r" => Ok(OutputTerminator::Byte(b'\r')),
+            _ => match CharSpecifier::from_str(s)?.char() {
+                Some(b) => Ok(OutputTerminator::Byte(b)),
+                None => Err(format_err!("record terminator is required")),
+            },
+        }
+    }
+}
+
 #[test]
 fn parses_char_specifiers() {
     assert_eq!(CharSpecifier::from_str(",").unwrap().char(), Some(b','));
@@ -44,3 +178,29 @@ fn parses_char_specifiers() {
     assert_eq!(CharSpecifier::from_str(r"tab").unwrap().char(), Some(b'\t'));
     assert_eq!(CharSpecifier::from_str(r"none").unwrap().char(), None);
 }
+
+#[test]
+fn truncates_without_splitting_characters() {
+    assert_eq!(truncate_bytes(b"abc", 5), b"abc");
+    assert_eq!(truncate_bytes(b"abc", 2), b"ab");
+    assert_eq!(truncate_bytes("aé".as_bytes(), 2), b"a");
+    assert_eq!(truncate_bytes("aé".as_bytes(), 3), "aé".as_bytes());
+    assert_eq!(truncate_bytes("€".as_bytes(), 2), b"");
+}
+
+#[test]
+fn parses_output_terminators() {
+    assert!(matches!(
+        OutputTerminator::from_str("lf").unwrap(),
+        OutputTerminator::Byte(b'\n')
+    ));
+    assert!(matches!(
+        OutputTerminator::from_str("crlf").unwrap(),
+        OutputTerminator::Crlf
+    ));
+    assert!(matches!(
+        OutputTerminator::from_str(";").unwrap(),
+        OutputTerminator::Byte(b';')
+    ));
+    assert!(OutputTerminator::from_str("none").is_err());
+}
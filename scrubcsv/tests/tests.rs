@@ -173,6 +173,32 @@ fn trim_whitespace() {
     assert_eq!(output.stdout_str(), "a,b,c,d\n1,2,,\n");
 }
 
+#[test]
+fn unicode_cleanups() {
+    let testdir = TestDir::new("scrubcsv", "unicode_cleanups");
+    let stats_file = testdir.path("stats.json");
+    let output = testdir
+        .cmd()
+        .arg("--trim-whitespace")
+        .arg("--unicode-whitespace")
+        .arg("--strip-control-chars")
+        .args(["--unicode-normalize", "nfc"])
+        .arg("--profile-columns")
+        .arg("--output-stats-to-file")
+        .arg(&stats_file)
+        .output_with_stdin("a,b,c\nx\0y,cafe\u{301},\u{A0}id\u{200B}\nok,ok,ok\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "a,b,c\nxy,café,id\nok,ok,ok\n");
+
+    let stats: Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap()).unwrap();
+    let columns = &stats["columns"];
+    assert_eq!(columns[0]["control_chars_stripped"], 1);
+    assert_eq!(columns[1]["unicode_normalized"], 1);
+    assert_eq!(columns[2]["control_chars_stripped"], 1);
+    assert_eq!(columns[2]["trimmed"], 1);
+}
+
 #[test]
 fn clean_column_names_unique() {
     let testdir = TestDir::new("scrubcsv", "clean_column_names_unique");
@@ -195,6 +221,139 @@ fn clean_column_names_stable() {
     assert_eq!(output.stdout_str(), "a,b,c_d\n");
 }
 
+#[test]
+fn clean_column_names_other_types() {
+    let testdir = TestDir::new("scrubcsv", "clean_column_names_other_types");
+    let examples = &[
+        ("snake_case", "user_id,http_server,order,user_id_2\n"),
+        ("postgres", "user_id,http_server,order_,user_id_2\n"),
+        ("preserve", "userID,HTTPServer,Order,userID_2\n"),
+    ];
+    for &(cleaner_type, expected) in examples {
+        let output = testdir
+            .cmd()
+            .arg(format!("--clean-column-names={}", cleaner_type))
+            .output_with_stdin("userID,HTTPServer,Order,userID\n")
+            .expect_success();
+        assert_eq!(output.stdout_str(), expected);
+    }
+}
+
+#[test]
+fn transliterate_column_names() {
+    let testdir = TestDir::new("scrubcsv", "transliterate_column_names");
+    for &cleaner_type in &["unique", "stable"] {
+        let output = testdir
+            .cmd()
+            .arg(format!("--clean-column-names={}", cleaner_type))
+            .arg("--transliterate-column-names")
+            .output_with_stdin("Straße,Prénom,Año  Fiscal\n")
+            .expect_success();
+        assert_eq!(output.stdout_str(), "strasse,prenom,ano_fiscal\n");
+    }
+}
+
+#[test]
+fn column_name_map() {
+    let testdir = TestDir::new("scrubcsv", "column_name_map");
+    testdir.create_file("map.csv", "original,clean\nCust ID,customer_id\n");
+
+    // A frozen map must contain every column.
+    let output = testdir
+        .cmd()
+        .args(["--clean-column-names", "--column-name-map", "map.csv"])
+        .output_with_stdin("Amount,Cust ID\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("missing from column name map"));
+    assert!(output.stderr_str().contains("Amount"));
+
+    // Add new columns to the map, and use them on the next run.
+    let output = testdir
+        .cmd()
+        .args(["--clean-column-names", "--column-name-map", "map.csv"])
+        .arg("--update-column-name-map")
+        .output_with_stdin("Amount,Cust ID\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "amount,customer_id\n");
+    testdir.expect_file_contents(
+        "map.csv",
+        "original,clean\nCust ID,customer_id\nAmount,amount\n",
+    );
+    let output = testdir
+        .cmd()
+        .args(["--column-name-map", "map.csv"])
+        .output_with_stdin("Cust ID,Amount\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "customer_id,amount\n");
+
+    // Cleaned names don't collide with mapped names.
+    testdir.create_file("order_map.csv", "original,clean\nOrder ID,order_id\n");
+    let output = testdir
+        .cmd()
+        .args(["--clean-column-names", "--column-name-map", "order_map.csv"])
+        .arg("--update-column-name-map")
+        .output_with_stdin("Order ID,order id\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "order_id,order_id_2\n");
+
+    // We don't save new names if scrubbing fails.
+    testdir
+        .cmd()
+        .args(["--clean-column-names", "--column-name-map", "map.csv"])
+        .arg("--update-column-name-map")
+        .args(["--dedupe", "--dedupe-max-keys", "1"])
+        .output_with_stdin("Cust ID,Region\n1,a\n2,b\n")
+        .expect_failure();
+    assert!(!std::fs::read_to_string(testdir.path("map.csv"))
+        .unwrap()
+        .contains("Region"));
+}
+
+#[test]
+fn expect_columns() {
+    let testdir = TestDir::new("scrubcsv", "expect_columns");
+    testdir.create_file("expected.txt", "id\nname\nemail\n");
+
+    // By default, the columns must match exactly.
+    let output = testdir
+        .cmd()
+        .args(["--expect-columns", "expected.txt"])
+        .output_with_stdin("id,name,phone\n1,Ann,555\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("added: [\"phone\"]"));
+    assert!(output.stderr_str().contains("missing: [\"email\"]"));
+
+    // Extra columns are allowed in superset mode.
+    let output = testdir
+        .cmd()
+        .args(["--expect-columns", "expected.txt"])
+        .args(["--expect-columns-mode", "superset"])
+        .output_with_stdin("email,phone,name,id\na@example.com,555,Ann,1\n")
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "email,phone,name,id\na@example.com,555,Ann,1\n"
+    );
+
+    // Reordering drops extra columns, and can fill in missing ones.
+    let output = testdir
+        .cmd()
+        .args(["--expect-columns", "expected.txt"])
+        .args(["--expect-columns-mode", "reorder"])
+        .arg("--allow-missing-columns")
+        .output_with_stdin("phone,name,id\n555,Ann,1\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "id,name,email\n1,Ann,\n");
+    let output = testdir
+        .cmd()
+        .args(["--expect-columns", "expected.txt"])
+        .args(["--expect-columns-mode", "reorder", "--trim-whitespace"])
+        .arg("--allow-missing-columns")
+        .output_with_stdin("phone,name,id\n555, Ann ,1\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "id,name,email\n1,Ann,\n");
+}
+
 #[test]
 fn clean_column_names_stable_rejects_certain_names() {
     let testdir = TestDir::new(
@@ -296,7 +455,984 @@ a,b,c
     assert_eq!(stats["rows"], 4);
     assert_eq!(stats["bad_rows"], 0);
     assert!(stats["elapsed_seconds"].is_number());
+    // Column profiles are slow, so we only include them if asked.
+    assert!(stats.get("columns").is_none());
     assert!(stats["bytes_processed"].is_number());
     assert!(stats["bytes_per_second"].is_number());
     assert!(stats["bytes_processed"].as_u64().unwrap() > 0);
 }
+
+#[test]
+fn output_dialect() {
+    let testdir = TestDir::new("scrubcsv", "output_dialect");
+    let output = testdir
+        .cmd()
+        .args(["--output-delimiter", "|"])
+        .args(["--output-quote-style", "always"])
+        .args(["--output-terminator", "crlf"])
+        .args(["--output-escape", "\\"])
+        .output_with_stdin("a,b\n1,\"x \"\"y\"\"\"\n")
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "\"a\"|\"b\"\r\n\"1\"|\"x \\\"y\\\"\"\r\n"
+    );
+}
+
+#[test]
+fn output_tab_delimiter() {
+    let testdir = TestDir::new("scrubcsv", "output_tab_delimiter");
+    let output = testdir
+        .cmd()
+        .args(["--output-delimiter", "tab"])
+        .output_with_stdin("a,b\n1,2\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "a\tb\n1\t2\n");
+}
+
+#[test]
+fn select_exclude_and_rename() {
+    let testdir = TestDir::new("scrubcsv", "select_exclude_and_rename");
+    let output = testdir
+        .cmd()
+        .arg("--clean-column-names")
+        .args(["--select", "/^c/"])
+        .args(["--select", "1"])
+        .args(["--exclude", "c_2"])
+        .args(["--rename", "a=id"])
+        .output_with_stdin("A,B,C 1,C 2\n1,2,3,4\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "c_1,id\n3,1\n");
+}
+
+#[test]
+fn drop_row_if_null_with_excluded_column() {
+    let testdir = TestDir::new("scrubcsv", "drop_row_if_null_with_excluded_column");
+    let mut input = "a,b\n".to_owned();
+    let mut expected = "b\n".to_owned();
+    for _ in 0..20 {
+        input.push_str("1,x\n");
+        expected.push_str("x\n");
+    }
+    input.push_str(",y\n");
+    let output = testdir
+        .cmd()
+        .args(["--exclude", "a"])
+        .args(["--rename", "a=key"])
+        .args(["--drop-row-if-null", "key"])
+        .output_with_stdin(&input)
+        .expect_success();
+    assert_eq!(output.stdout_str(), expected);
+}
+
+#[test]
+fn rename_rejects_duplicate_and_reserved_names() {
+    let testdir = TestDir::new("scrubcsv", "rename_rejects_duplicate_names");
+    let output = testdir
+        .cmd()
+        .args(["--rename", "a=b"])
+        .output_with_stdin("a,b\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("duplicate output column name"));
+
+    let output = testdir
+        .cmd()
+        .args(["--rename", "a=reserved_a"])
+        .arg("--reserve-column-names=^reserved_")
+        .output_with_stdin("a,b\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("reserved column name"));
+}
+
+#[test]
+fn transform_rules() {
+    let testdir = TestDir::new("scrubcsv", "transform_rules");
+    testdir.create_file(
+        "rules.json",
+        r#"{
+  "rules": [
+    { "columns": ["phone"], "replace": { "regex": "[^0-9]", "with": "" } },
+    { "columns": ["state"], "trim": true, "case": "upper" },
+    { "columns": ["/amount$/"], "null": "N/A|n\\.a\\." }
+  ]
+}"#,
+    );
+    let output = testdir
+        .cmd()
+        .args(["--transform-rules", "rules.json"])
+        .output_with_stdin("phone,state,amount,note\n(202) 555-1212, ny ,N/A,N/A\n")
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "phone,state,amount,note\n2025551212,NY,,N/A\n"
+    );
+}
+
+#[test]
+fn normalize_values() {
+    let testdir = TestDir::new("scrubcsv", "normalize_values");
+    testdir.create_file(
+        "rules.toml",
+        r#"
+[[rules]]
+columns = ["price"]
+number = {}
+
+[[rules]]
+columns = ["active"]
+boolean = {}
+on_invalid = "null"
+
+[[rules]]
+columns = ["date"]
+date = { formats = ["%m/%d/%Y", "%Y-%m-%d"] }
+"#,
+    );
+    // Pad the input with good rows, so our bad row stays under 10%.
+    let mut input = "\
+price,active,date
+\"$1,234.50\",YES,03/04/2021
+12,maybe,2021-3-4
+(7),n,
+abc,y,2021-01-01
+"
+    .to_owned();
+    input.push_str(&"0,no,2021-01-01\n".repeat(10));
+    let output = testdir
+        .cmd()
+        .args(["--transform-rules", "rules.toml"])
+        .arg("--output-stats-to-file")
+        .arg(testdir.path("stats.json"))
+        .output_with_stdin(&input)
+        .expect_success();
+    let mut expected = "\
+price,active,date
+1234.50,true,2021-03-04
+12,,2021-03-04
+-7,false,
+"
+    .to_owned();
+    expected.push_str(&"0,false,2021-01-01\n".repeat(10));
+    assert_eq!(output.stdout_str(), expected);
+    let stats: Value = serde_json::from_str(
+        &std::fs::read_to_string(testdir.path("stats.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(stats["rejections"]["invalid_value"], 1);
+
+    // Fail on invalid values if asked.
+    testdir.create_file(
+        "fail.toml",
+        "[[rules]]\ncolumns = [\"price\"]\nnumber = {}\non_invalid = \"fail\"\n",
+    );
+    let output = testdir
+        .cmd()
+        .args(["--transform-rules", "fail.toml"])
+        .output_with_stdin(&input)
+        .expect_failure();
+    assert!(output
+        .stderr_str()
+        .contains("cannot parse \"abc\" as a number (record 5, column 1)"));
+}
+
+#[test]
+fn field_and_row_limits() {
+    let testdir = TestDir::new("scrubcsv", "field_and_row_limits");
+    let mut input =
+        "id,name,notes\n1,Zoë,abcdefgh\n2,Bob,abcdefghijklmnop\n".to_owned();
+    input.push_str(&"3,Al,x\n".repeat(20));
+    let stats_file = testdir.path("stats.json");
+    let output = testdir
+        .cmd()
+        .args(["--max-field-bytes", "3"])
+        .args(["--max-field-bytes", "notes=10"])
+        .args(["--max-row-bytes", "15"])
+        .arg("--profile-columns")
+        .arg("--output-stats-to-file")
+        .arg(&stats_file)
+        .output_with_stdin(&input)
+        .expect_success();
+    let mut expected = "id,name,notes\n1,Zo,abcdefgh\n".to_owned();
+    expected.push_str(&"3,Al,x\n".repeat(20));
+    assert_eq!(output.stdout_str(), expected);
+    let stats: Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap()).unwrap();
+    assert_eq!(stats["rejections"]["row_too_long"], 1);
+    assert_eq!(stats["columns"][1]["truncated"], 1);
+
+    let output = testdir
+        .cmd()
+        .args(["--max-field-bytes", "notes=10"])
+        .args(["--long-field-action", "drop"])
+        .output_with_stdin(&input)
+        .expect_success();
+    let mut expected = "id,name,notes\n1,Zoë,abcdefgh\n".to_owned();
+    expected.push_str(&"3,Al,x\n".repeat(20));
+    assert_eq!(output.stdout_str(), expected);
+
+    // We only limit columns which we output.
+    let mut expected = "id,name\n1,Zoë\n2,Bob\n".to_owned();
+    expected.push_str(&"3,Al\n".repeat(20));
+    for cols in [
+        &["--exclude", "notes"][..],
+        &["--select", "id", "--select", "name"][..],
+    ] {
+        for threads in ["1", "2"] {
+            let output = testdir
+                .cmd()
+                .args(cols)
+                .args(["--threads", threads])
+                .args(["--max-field-bytes", "4"])
+                .args(["--long-field-action", "drop"])
+                .output_with_stdin(&input)
+                .expect_success();
+            assert_eq!(output.stdout_str(), expected);
+        }
+    }
+}
+
+#[test]
+fn threads_match_sequential_output() {
+    // Build an input large enough to be split into several chunks, with
+    // quoted newlines, CRLF line endings and the occasional bad row.
+    let mut input = "a,b,c\n".to_owned();
+    for i in 0..100_000 {
+        match i % 1000 {
+            0 => input.push_str("bad,row\n"),
+            1 => input
+                .push_str(&format!("{},\"multi\r\nline \"\"{}\"\"\",  x  \r\n", i, i)),
+            _ => input.push_str(&format!("{},NULL,\"{}\"\n", i, i * 7)),
+        }
+    }
+
+    let testdir = TestDir::new("scrubcsv", "threads_match_sequential_output");
+    let run = |threads: &str| {
+        testdir
+            .cmd()
+            .args(["--threads", threads])
+            .args(["--null", "NULL"])
+            .arg("--trim-whitespace")
+            .arg("--replace-newlines")
+            .output_with_stdin(&input)
+            .expect_success()
+    };
+    let sequential = run("1");
+    let parallel = run("4");
+    assert_eq!(sequential.stdout_str(), parallel.stdout_str());
+    assert!(sequential.stderr_str().contains("100001 rows (100 bad)"));
+    assert!(parallel.stderr_str().contains("100001 rows (100 bad)"));
+}
+
+#[test]
+fn fast_path_matches_general_path() {
+    // Mix in a BOM, quotes, blank lines and unusual line endings, plus enough
+    // good rows to stay under the bad row limit.
+    let mut input =
+        "\u{feff}a,b,c\r\n1,\"x\ny\",3\r\n\"q\"\"z\",,\n1,2\n\n4,5,6\r".to_owned();
+    for i in 0..10 {
+        input.push_str(&format!("{},\"{}\",x\n", i, i));
+    }
+    input.push_str("7,\"8\",9");
+    let testdir = TestDir::new("scrubcsv", "fast_path_matches_general_path");
+    let option_sets: &[&[&str]] = &[
+        &[],
+        &["--select", "c", "--select", "a"],
+        &["--output-quote-style", "always"],
+        &["--output-delimiter", "tab", "--output-terminator", "crlf"],
+        &["--output-escape", "\\"],
+    ];
+    for options in option_sets {
+        let run = |extra: &[&str]| {
+            testdir
+                .cmd()
+                .args(*options)
+                .args(extra)
+                .output_with_stdin(&input)
+                .expect_success()
+        };
+        let fast = run(&[]);
+        let general = run(&["--no-fast-path"]);
+        assert_eq!(fast.stdout_str(), general.stdout_str());
+        assert!(fast.stderr_str().contains("16 rows (1 bad)"));
+    }
+}
+
+#[test]
+fn dedupe_on_key_columns() {
+    let testdir = TestDir::new("scrubcsv", "dedupe_on_key_columns");
+    testdir.create_file(
+        "in.csv",
+        "\
+id,region,value
+1,a,first
+2,a,x
+ 1 ,a,second
+1,b,y
+1,a,third
+",
+    );
+    let stats_file = testdir.path("stats.json");
+    let output = testdir
+        .cmd()
+        .args(["--dedupe-on", "id,region"])
+        .arg("--trim-whitespace")
+        .arg("--output-stats-to-file")
+        .arg(&stats_file)
+        .arg("in.csv")
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "id,region,value\n1,a,first\n2,a,x\n1,b,y\n"
+    );
+    assert!(output.stderr_str().contains("6 rows (0 bad, 2 duplicate)"));
+    let stats: Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap()).unwrap();
+    assert_eq!(stats["duplicate_rows"], 2);
+
+    let output = testdir
+        .cmd()
+        .args(["--dedupe-on", "id,region"])
+        .args(["--dedupe-keep", "last"])
+        .arg("--trim-whitespace")
+        .arg("in.csv")
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "id,region,value\n2,a,x\n1,b,y\n1,a,third\n"
+    );
+
+    // We can't read standard input twice.
+    let output = testdir
+        .cmd()
+        .args(["--dedupe-on", "id"])
+        .args(["--dedupe-keep", "last"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("requires an input file"));
+
+    // We'd never output rows whose last copy wasn't sampled.
+    let output = testdir
+        .cmd()
+        .args(["--dedupe-on", "id"])
+        .args(["--dedupe-keep", "last"])
+        .args(["--limit", "2"])
+        .arg("in.csv")
+        .expect_failure();
+    assert!(output
+        .stderr_str()
+        .contains("cannot use --dedupe-keep=last with --limit"));
+
+    // We refuse to track too many keys.
+    let output = testdir
+        .cmd()
+        .args(["--dedupe-on", "id,region"])
+        .args(["--dedupe-max-keys", "2"])
+        .arg("--trim-whitespace")
+        .arg("in.csv")
+        .expect_failure();
+    assert!(output.stderr_str().contains("more than 2 distinct keys"));
+}
+
+#[test]
+fn dedupe_full_rows() {
+    let testdir = TestDir::new("scrubcsv", "dedupe_full_rows");
+    let output = testdir
+        .cmd()
+        .arg("--dedupe")
+        .args(["--exclude", "c"])
+        .output_with_stdin("a,b,c\n1,2,3\n1,2,4\n1,3,3\n1,2\n")
+        .expect("error running scrubcsv");
+    assert_eq!(output.stdout_str(), "a,b\n1,2\n1,3\n");
+    assert!(output.stderr_str().contains("5 rows (1 bad, 1 duplicate)"));
+}
+
+#[test]
+fn no_headers_with_supplied_header() {
+    let testdir = TestDir::new("scrubcsv", "no_headers_with_supplied_header");
+    let input = "1,,x\n2,b,y\n3,c\n4,d,z\n5,e,w\n6,f,v\n7,g,u\n8,h,t\n9,i,s\n10,j,r\n";
+    let expected =
+        "ID,Name,Code\n2,b,y\n4,d,z\n5,e,w\n6,f,v\n7,g,u\n8,h,t\n9,i,s\n10,j,r\n";
+    for threads in ["1", "2"] {
+        let output = testdir
+            .cmd()
+            .arg("--no-headers")
+            .args(["--header", "ID,Name,Code"])
+            .args(["--threads", threads])
+            .output_with_stdin(input)
+            .expect_success();
+        assert!(output.stdout_str().starts_with("ID,Name,Code\n1,,x\n"));
+        assert!(output.stderr_str().contains("10 rows (1 bad)"));
+    }
+
+    testdir.create_file("header.csv", "ID|Name|Code\n");
+    let output = testdir
+        .cmd()
+        .arg("--no-headers")
+        .args(["-d", "|"])
+        .args(["--header-file", "header.csv"])
+        .arg("--clean-column-names")
+        .arg("--drop-row-if-null=name")
+        .output_with_stdin(input.replace(',', "|"))
+        .expect("error running scrubcsv");
+    assert_eq!(output.stdout_str(), expected.to_lowercase());
+
+    // We need column names from somewhere.
+    testdir.cmd().arg("--no-headers").expect_failure();
+}
+
+#[test]
+fn header_replaces_input_header() {
+    let testdir = TestDir::new("scrubcsv", "header_replaces_input_header");
+    let output = testdir
+        .cmd()
+        .args(["--header", "x,y"])
+        .output_with_stdin("a,b\n1,2\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "x,y\n1,2\n");
+    assert!(output.stderr_str().contains("2 rows (0 bad)"));
+}
+
+#[test]
+fn skip_preamble_and_footer() {
+    let testdir = TestDir::new("scrubcsv", "skip_preamble_and_footer");
+    testdir.create_file(
+        "in.csv",
+        "\
+Quarterly \"extract
+Generated: 2024-01-01
+
+id,name
+1,a
+2,b
+TOTAL ROWS: 2
+",
+    );
+    let output = testdir
+        .cmd()
+        .args(["--skip-lines", "1"])
+        .args(["--header-regex", "^id,"])
+        .args(["--footer-regex", r"^TOTAL ROWS: (?P<rows>\d+)$"])
+        .arg("in.csv")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "id,name\n1,a\n2,b\n");
+    assert!(output.stderr_str().contains("3 rows (0 bad)"));
+
+    let output = testdir
+        .cmd()
+        .args(["--skip-lines", "3"])
+        .args(["--skip-trailing", "1"])
+        .args(["--dedupe-on", "id"])
+        .args(["--dedupe-keep", "last"])
+        .arg("in.csv")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "id,name\n1,a\n2,b\n");
+
+    // Fail if the footer has the wrong row count.
+    testdir.create_file("bad_count.csv", "id,name\n1,a\nTOTAL ROWS: 2\n");
+    let output = testdir
+        .cmd()
+        .args(["--footer-regex", r"^TOTAL ROWS: (?P<rows>\d+)$"])
+        .arg("bad_count.csv")
+        .expect_failure();
+    assert!(output
+        .stderr_str()
+        .contains("footer says there are 2 rows, but we found 1"));
+}
+
+#[test]
+fn comment_lines() {
+    let testdir = TestDir::new("scrubcsv", "comment_lines");
+    let stats_file = testdir.path("stats.json");
+    let input =
+        "# exported nightly\na\tb\n1\t2\n# checkpoint\n3\t\"#4\n# not a comment\"\n";
+    for threads in ["1", "2"] {
+        let output = testdir
+            .cmd()
+            .args(["-d", "tab"])
+            .args(["--comment", "#"])
+            .args(["--threads", threads])
+            .arg("--output-stats-to-file")
+            .arg(&stats_file)
+            .output_with_stdin(input)
+            .expect_success();
+        assert_eq!(output.stdout_str(), "a,b\n1,2\n3,\"#4\n# not a comment\"\n");
+        assert!(output.stderr_str().contains("3 rows (0 bad, 2 comments)"));
+        let stats: Value =
+            serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap())
+                .unwrap();
+        assert_eq!(stats["bad_rows"], 0);
+        assert_eq!(stats["comment_lines"], 2);
+    }
+}
+
+#[test]
+fn output_column_stats_to_file() {
+    let testdir = TestDir::new("scrubcsv", "output_column_stats_to_file");
+    let mut input = "id,name,note\n".to_owned();
+    for i in 0..200 {
+        input.push_str(&format!("{}, name {} ,\"{}\"\n", i, i % 10, i % 2));
+    }
+    input.push_str("1,2\n1,2,3,4\n1,2\n,NULL,\"a\nb\"\n");
+    let stats_file = testdir.path("stats.json");
+    for threads in ["1", "2"] {
+        testdir
+            .cmd()
+            .args(["--threads", threads])
+            .args(["--null", "NULL"])
+            .args(["--drop-row-if-null", "id"])
+            .arg("--trim-whitespace")
+            .arg("--replace-newlines")
+            .arg("--profile-columns")
+            .arg("--output-stats-to-file")
+            .arg(&stats_file)
+            .output_with_stdin(&input)
+            .expect_success();
+        let stats: Value =
+            serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap())
+                .unwrap();
+        assert_eq!(stats["bad_rows"], 4);
+        assert_eq!(
+            stats["rejections"],
+            serde_json::json!({
+                "wrong_column_count": 3,
+                "missing_required_value": 1,
+                "invalid_value": 0,
+                "field_too_long": 0,
+                "row_too_long": 0,
+                "duplicate": 0,
+            })
+        );
+        assert_eq!(
+            stats["bad_row_column_counts"],
+            serde_json::json!({ "2": 2, "4": 1 })
+        );
+        let name = &stats["columns"][1];
+        assert_eq!(name["name"], "name");
+        assert_eq!(name["trimmed"], 200);
+        assert_eq!(name["min_length"], 6);
+        assert_eq!(name["max_length"], 6);
+        assert_eq!(name["approx_distinct"], 10);
+        let note = &stats["columns"][2];
+        assert_eq!(note["approx_distinct"], 2);
+        assert_eq!(note["empty"], 0);
+    }
+}
+
+#[test]
+fn sniff_with_footer() {
+    let testdir = TestDir::new("scrubcsv", "sniff_with_footer");
+    testdir.create_file("in.tsv", "id\tname\n1\ta\n2\tb\nTOTAL\t2\n");
+    for extra in [&[][..], &["--dedupe-on", "id", "--dedupe-keep", "last"][..]] {
+        let output = testdir
+            .cmd()
+            .arg("--sniff")
+            .args(["--footer-regex", r"^TOTAL\t(?P<rows>\d+)$"])
+            .args(extra)
+            .arg("in.tsv")
+            .expect_success();
+        assert_eq!(output.stdout_str(), "id,name\n1,a\n2,b\n");
+    }
+}
+
+#[test]
+fn sniff_dialect() {
+    let testdir = TestDir::new("scrubcsv", "sniff_dialect");
+    testdir.create_file(
+        "in.csv",
+        "id;name;note\n1;'Smith; Ann';x\n2;Bob;'y'\n3;Cy;z\n",
+    );
+    let stats_file = testdir.path("stats.json");
+    for args in [&["--sniff"][..], &["-d", "auto"][..]] {
+        let output = testdir
+            .cmd()
+            .args(args)
+            .arg("--output-stats-to-file")
+            .arg(&stats_file)
+            .arg("in.csv")
+            .expect_success();
+        assert_eq!(
+            output.stdout_str(),
+            "id,name,note\n1,Smith; Ann,x\n2,Bob,y\n3,Cy,z\n"
+        );
+        let stats: Value =
+            serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap())
+                .unwrap();
+        assert_eq!(stats["dialect"]["delimiter"], ";");
+        assert_eq!(stats["dialect"]["quote"], "'");
+        assert_eq!(stats["dialect"]["escape"], Value::Null);
+        assert_eq!(stats["dialect"]["has_headers"], true);
+    }
+
+    // We detect whether the input has a header row, and we need column names
+    // if it doesn't.
+    let output = testdir
+        .cmd()
+        .args(["--sniff", "--header", "a,b"])
+        .output_with_stdin("1\t2\n3\t4\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "a,b\n1,2\n3,4\n");
+    let output = testdir
+        .cmd()
+        .arg("--sniff")
+        .output_with_stdin("1\t2\n3\t4\n")
+        .expect_failure();
+    assert!(output
+        .stderr_str()
+        .contains("input does not seem to have a header row"));
+}
+
+#[test]
+fn escape_character() {
+    let testdir = TestDir::new("scrubcsv", "escape_character");
+    let input = r#"id,name,note
+1,"Say \"hi\", Ann",a\tb
+2,\N,line 1\nline 2
+"#;
+
+    // Without `--escape`, the escaped quotes split the field.
+    let output = testdir.cmd().output_with_stdin(input).expect_failure();
+    assert!(output.stderr_str().contains("3 rows (1 bad)"));
+
+    let output = testdir
+        .cmd()
+        .args(["--escape", r"\"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(
+        output.stdout_str(),
+        "id,name,note\n1,\"Say \"\"hi\"\", Ann\",a\tb\n2,,\"line 1\nline 2\"\n"
+    );
+
+    // Escapes are decoded inside quotes, including `\N`, and escaped
+    // delimiters outside quotes are part of the value.
+    let output = testdir
+        .cmd()
+        .args(["--escape", r"\"])
+        .output_with_stdin("a,b,c\n\"x\\ty\",\"1\\n2\",u\\,v\n\"\\N\",w,\\N\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "a,b,c\nx\ty,\"1\n2\",\"u,v\"\n,w,\n");
+    assert!(output.stderr_str().contains("3 rows (0 bad)"));
+}
+
+#[test]
+fn compressed_input_and_output() {
+    let testdir = TestDir::new("scrubcsv", "compressed_input_and_output");
+    let stats_file = testdir.path("stats.json");
+    for ext in ["gz", "zst", "sz", "bz2"] {
+        // Compress our output, and then read it back.
+        let compressed = format!("out.csv.{}", ext);
+        testdir
+            .cmd()
+            .args(["--output", &compressed])
+            .output_with_stdin("a,b\n1,2\n3,4\n")
+            .expect_success();
+        let output = testdir
+            .cmd()
+            .arg("--output-stats-to-file")
+            .arg(&stats_file)
+            .arg(&compressed)
+            .expect_success();
+        assert_eq!(output.stdout_str(), "a,b\n1,2\n3,4\n");
+        let stats: Value =
+            serde_json::from_str(&std::fs::read_to_string(&stats_file).unwrap())
+                .unwrap();
+        assert_eq!(stats["bytes_processed"], 12);
+        let compressed_len =
+            std::fs::metadata(testdir.path(&compressed)).unwrap().len();
+        assert_eq!(stats["compressed_bytes_processed"], compressed_len);
+
+        // We also recognize compressed data on stdin.
+        let data = std::fs::read(testdir.path(&compressed)).unwrap();
+        let output = testdir.cmd().output_with_stdin(data).expect_success();
+        assert_eq!(output.stdout_str(), "a,b\n1,2\n3,4\n");
+    }
+}
+
+#[test]
+fn json_error_format() {
+    let testdir = TestDir::new("scrubcsv", "json_error_format");
+
+    // Errors about a specific value include its record and column.
+    testdir.create_file(
+        "rules.toml",
+        "[[rules]]\ncolumns = [\"b\"]\nnumber = {}\non_invalid = \"fail\"\n",
+    );
+    let output = testdir
+        .cmd()
+        .args(["--transform-rules", "rules.toml"])
+        .args(["--error-format", "json"])
+        .output_with_stdin("a,b\n1,2\n3,x4\n")
+        .expect_failure();
+    let error: Value = serde_json::from_str(output.stderr_str().trim()).unwrap();
+    assert_eq!(
+        error["location"],
+        serde_json::json!({ "record": 3, "column": 2, "snippet": "x4" })
+    );
+
+    // Read errors include the record being parsed, and the approximate line,
+    // byte offset and input just before the error.
+    let mut input = "a,b\n".to_owned();
+    for i in 0..20_000 {
+        input.push_str(&format!("{},x\n", i));
+    }
+    testdir
+        .cmd()
+        .args(["--output", "in.csv.gz"])
+        .output_with_stdin(&input)
+        .expect_success();
+    let compressed = std::fs::read(testdir.path("in.csv.gz")).unwrap();
+    std::fs::write(
+        testdir.path("in.csv.gz"),
+        &compressed[..compressed.len() / 2],
+    )
+    .unwrap();
+    let output = testdir
+        .cmd()
+        .args(["--error-format", "json"])
+        .arg("in.csv.gz")
+        .expect_failure();
+    let error: Value = serde_json::from_str(output.stderr_str().trim()).unwrap();
+    assert_eq!(error["error"], "cannot read record");
+    let location = &error["location"];
+    assert!(location["record"].as_u64().unwrap() > 1);
+    assert_eq!(location["approximate"], true);
+    assert!(location["line"].as_u64().unwrap() > 1);
+    assert!(location["byte"].as_u64().unwrap() > 0);
+    assert!(location["snippet"].as_str().unwrap().contains(",x\n"));
+}
+
+#[test]
+fn progress() {
+    let testdir = TestDir::new("scrubcsv", "progress");
+    let mut input = "a,b\n".to_owned();
+    for i in 0..5_000 {
+        input.push_str(&format!("{},x\n", i));
+    }
+    testdir.create_file("in.csv", &input);
+
+    // When we know the size of our input, we can estimate how long is left.
+    for args in [&[][..], &["--threads", "2"], &["--trim-whitespace"]] {
+        let output = testdir
+            .cmd()
+            .args(["--progress-interval", "0"])
+            .args(args)
+            .arg("in.csv")
+            .expect_success();
+        let stderr = output.stderr_str();
+        let line = stderr.lines().next().unwrap();
+        assert!(line.starts_with("progress: "), "{:?}", line);
+        assert!(line.contains(" rows (0 bad), "), "{:?}", line);
+        assert!(line.contains("% done, ETA "), "{:?}", line);
+    }
+
+    // We can't estimate how long is left when reading stdin.
+    let output = testdir
+        .cmd()
+        .args(["--progress-interval", "0"])
+        .output_with_stdin(&input)
+        .expect_success();
+    let stderr = output.stderr_str();
+    assert!(stderr.starts_with("progress: "), "{:?}", stderr);
+    assert!(!stderr.contains("ETA"), "{:?}", stderr);
+
+    // `--quiet` suppresses progress reports.
+    let output = testdir
+        .cmd()
+        .args(["--progress-interval", "0", "--quiet"])
+        .arg("in.csv")
+        .expect_success();
+    assert_eq!(output.stderr_str(), "");
+}
+
+#[test]
+fn output_format_jsonl_and_parquet() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let testdir = TestDir::new("scrubcsv", "output_format_jsonl_and_parquet");
+    testdir.create_file(
+        "rules.toml",
+        r#"
+[[rules]]
+columns = ["price"]
+number = {}
+
+[[rules]]
+columns = ["active"]
+boolean = {}
+
+[[rules]]
+columns = ["day"]
+date = { formats = ["%m/%d/%Y"] }
+"#,
+    );
+    testdir.create_file("schema.toml", "[columns]\nid = \"integer\"\n");
+    let mut input = "\
+id,name,price,active,day
+1,Ann,\"$1,234.50\",yes,03/04/2021
+2,,NA,,
+x,Bad,1,y,01/01/2020
+"
+    .to_owned();
+    for i in 3..20 {
+        input.push_str(&format!("{},Pat,1,no,01/01/2020\n", i));
+    }
+    testdir.create_file("in.csv", &input);
+    let args = [
+        "--transform-rules",
+        "rules.toml",
+        "--schema",
+        "schema.toml",
+        "--null",
+        "NA",
+        "--output-stats-to-file",
+        "stats.json",
+    ];
+
+    // JSON Lines use our inferred and declared types, and nulls.
+    let output = testdir
+        .cmd()
+        .args(args)
+        .args(["--output-format", "jsonl"])
+        .arg("in.csv")
+        .expect_success();
+    let lines = output
+        .stdout_str()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 19);
+    assert_eq!(
+        lines[0],
+        serde_json::json!({
+            "id": 1,
+            "name": "Ann",
+            "price": 1234.5,
+            "active": true,
+            "day": "2021-03-04",
+        })
+    );
+    assert_eq!(
+        lines[1],
+        serde_json::json!({
+            "id": 2,
+            "name": null,
+            "price": null,
+            "active": null,
+            "day": null,
+        })
+    );
+
+    // Values which don't match our schema are invalid.
+    let stats: Value = serde_json::from_str(
+        &std::fs::read_to_string(testdir.path("stats.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(stats["rejections"]["invalid_value"], 1);
+
+    // Parquet uses the same types.
+    testdir
+        .cmd()
+        .args(args)
+        .args(["--output-format", "parquet", "--output", "out.parquet"])
+        .arg("in.csv")
+        .expect_success();
+    let file = std::fs::File::open(testdir.path("out.parquet")).unwrap();
+    let reader = SerializedFileReader::new(file).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 19);
+    let rows = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| {
+            row.unwrap()
+                .get_column_iter()
+                .map(|(name, field)| (name.clone(), field.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let field = |name: &str, field| (name.to_owned(), field);
+    assert_eq!(
+        rows[0],
+        vec![
+            field("id", Field::Long(1)),
+            field("name", Field::Str("Ann".to_owned())),
+            field("price", Field::Double(1234.5)),
+            field("active", Field::Bool(true)),
+            field("day", Field::Date(18690)),
+        ]
+    );
+    assert_eq!(rows[1][1], field("name", Field::Null));
+    assert_eq!(rows[1][2], field("price", Field::Null));
+}
+
+#[test]
+fn sampling() {
+    let testdir = TestDir::new("scrubcsv", "sampling");
+    let mut input = "a,b\n".to_owned();
+    for i in 0..1000 {
+        if i % 50 == 49 {
+            input.push_str(&format!("{:04}\n", i));
+        } else {
+            input.push_str(&format!("{:04},x\n", i));
+        }
+    }
+    testdir.create_file("in.csv", &input);
+    let run = |args: &[&str]| {
+        let output = testdir
+            .cmd()
+            .args(["--output-stats-to-file", "stats.json"])
+            .args(args)
+            .arg("in.csv")
+            .expect_success();
+        let stats: Value = serde_json::from_str(
+            &std::fs::read_to_string(testdir.path("stats.json")).unwrap(),
+        )
+        .unwrap();
+        (output.stdout_str().to_owned(), stats["sample"].clone())
+    };
+
+    // `--limit` stops early, and estimates the size of the input.
+    let (output, sample) = run(&["--limit", "10"]);
+    assert_eq!(output.lines().count(), 11);
+    assert!(output.ends_with("0009,x\n"));
+    assert_eq!(sample["limit"], 10);
+    assert_eq!(sample["read_all_input"], false);
+    assert_eq!(sample["rows_checked"], 10);
+    let total = sample["estimated_total_rows"].as_u64().unwrap();
+    assert!((800..1200).contains(&total), "{}", total);
+
+    // `--sample-rate` is deterministic, and extrapolates the bad row ratio.
+    let (output, sample) = run(&["--sample-rate", "0.5", "--seed", "7"]);
+    assert_eq!(run(&["--sample-rate", "0.5", "--seed", "7"]).0, output);
+    assert_ne!(run(&["--sample-rate", "0.5", "--seed", "8"]).0, output);
+    let checked = sample["rows_checked"].as_u64().unwrap();
+    assert!((400..600).contains(&checked), "{}", checked);
+    assert_eq!(sample["read_all_input"], true);
+    assert_eq!(sample["estimated_total_rows"], 1000);
+    let ratio = sample["bad_row_ratio"].as_f64().unwrap();
+    assert!(ratio > 0.0 && ratio < 0.1, "{}", ratio);
+
+    // `--reservoir` picks rows from the whole input, in their original order.
+    let (output, sample) = run(&["--reservoir", "5", "--seed", "3"]);
+    assert_eq!(run(&["--reservoir", "5", "--seed", "3"]).0, output);
+    let ids = output
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(ids.len(), 5);
+    assert!(ids.windows(2).all(|w| w[0] < w[1]), "{:?}", ids);
+    assert_eq!(sample["rows_checked"], 1000);
+    assert_eq!(sample["estimated_bad_rows"], 20);
+
+    // Some combinations don't make sense.
+    testdir
+        .cmd()
+        .args(["--limit", "1", "--reservoir", "1"])
+        .arg("in.csv")
+        .expect_failure();
+    testdir
+        .cmd()
+        .args(["--sample-rate", "0"])
+        .arg("in.csv")
+        .expect_failure();
+}